@@ -0,0 +1,17 @@
+CREATE TABLE IF NOT EXISTS orders (
+    [id] SERIAL PRIMARY KEY,
+    [order_uid] VARCHAR(255) NOT NULL,
+    [track_number] VARCHAR(255) NOT NULL,
+    [entry] VARCHAR(255) NOT NULL,
+    [delivery] JSON NOT NULL,
+    [payment] JSON NOT NULL,
+    [items] JSON NOT NULL,
+    [locale] VARCHAR(255),
+    [internal_signature] VARCHAR(255),
+    [customer_id] VARCHAR(255),
+    [delivery_service] VARCHAR(255),
+    [shardkey] VARCHAR(255),
+    [sm_id] INTEGER,
+    [date_created] TIMESTAMP,
+    [oof_shard] VARCHAR(255)
+);
@@ -0,0 +1,114 @@
+-- Moves delivery, payment and items out of the JSON columns into their own
+-- tables and replaces the SERIAL id, which SQLite never populated, with a
+-- real autoincrement column.
+ALTER TABLE orders RENAME TO orders_legacy;
+
+CREATE TABLE orders (
+    [id] INTEGER PRIMARY KEY AUTOINCREMENT,
+    [order_uid] VARCHAR(255) NOT NULL UNIQUE,
+    [track_number] VARCHAR(255) NOT NULL,
+    [entry] VARCHAR(255) NOT NULL,
+    [locale] VARCHAR(255),
+    [internal_signature] VARCHAR(255),
+    [customer_id] VARCHAR(255),
+    [delivery_service] VARCHAR(255),
+    [shardkey] VARCHAR(255),
+    [sm_id] INTEGER,
+    [date_created] TIMESTAMP,
+    [oof_shard] VARCHAR(255)
+);
+
+CREATE TABLE deliveries (
+    [order_uid] VARCHAR(255) NOT NULL PRIMARY KEY REFERENCES orders (order_uid) ON DELETE CASCADE,
+    [name] VARCHAR(255) NOT NULL,
+    [phone] VARCHAR(255) NOT NULL,
+    [zip] VARCHAR(255) NOT NULL,
+    [city] VARCHAR(255) NOT NULL,
+    [address] VARCHAR(255) NOT NULL,
+    [region] VARCHAR(255) NOT NULL,
+    [email] VARCHAR(255) NOT NULL
+);
+
+CREATE TABLE payments (
+    [order_uid] VARCHAR(255) NOT NULL PRIMARY KEY REFERENCES orders (order_uid) ON DELETE CASCADE,
+    [transaction] VARCHAR(255) NOT NULL,
+    [request_id] VARCHAR(255) NOT NULL,
+    [currency] VARCHAR(255) NOT NULL,
+    [provider] VARCHAR(255) NOT NULL,
+    [amount] INTEGER NOT NULL,
+    [payment_dt] INTEGER NOT NULL,
+    [bank] VARCHAR(255) NOT NULL,
+    [delivery_cost] INTEGER NOT NULL,
+    [goods_total] INTEGER NOT NULL,
+    [custom_fee] INTEGER NOT NULL
+);
+
+CREATE TABLE items (
+    [id] INTEGER PRIMARY KEY AUTOINCREMENT,
+    [order_uid] VARCHAR(255) NOT NULL REFERENCES orders (order_uid) ON DELETE CASCADE,
+    [position] INTEGER NOT NULL,
+    [chrt_id] INTEGER NOT NULL,
+    [track_number] VARCHAR(255) NOT NULL,
+    [price] INTEGER NOT NULL,
+    [rid] VARCHAR(255) NOT NULL,
+    [name] VARCHAR(255) NOT NULL,
+    [sale] INTEGER NOT NULL,
+    [size] VARCHAR(255) NOT NULL,
+    [total_price] INTEGER NOT NULL,
+    [nm_id] INTEGER NOT NULL,
+    [brand] VARCHAR(255) NOT NULL,
+    [status] INTEGER NOT NULL
+);
+
+INSERT INTO orders (order_uid, track_number, entry, locale, internal_signature, customer_id, delivery_service, shardkey, sm_id, date_created, oof_shard)
+    SELECT order_uid, track_number, entry, locale, internal_signature, customer_id, delivery_service, shardkey, sm_id, date_created, oof_shard
+    FROM orders_legacy
+    ORDER BY rowid;
+
+INSERT INTO deliveries (order_uid, name, phone, zip, city, address, region, email)
+    SELECT order_uid,
+           json_extract(delivery, '$.name'),
+           json_extract(delivery, '$.phone'),
+           json_extract(delivery, '$.zip'),
+           json_extract(delivery, '$.city'),
+           json_extract(delivery, '$.address'),
+           json_extract(delivery, '$.region'),
+           json_extract(delivery, '$.email')
+    FROM orders_legacy;
+
+INSERT INTO payments (order_uid, [transaction], request_id, currency, provider, amount, payment_dt, bank, delivery_cost, goods_total, custom_fee)
+    SELECT order_uid,
+           json_extract(payment, '$.transaction'),
+           json_extract(payment, '$.request_id'),
+           json_extract(payment, '$.currency'),
+           json_extract(payment, '$.provider'),
+           json_extract(payment, '$.amount'),
+           json_extract(payment, '$.payment_dt'),
+           json_extract(payment, '$.bank'),
+           json_extract(payment, '$.delivery_cost'),
+           json_extract(payment, '$.goods_total'),
+           json_extract(payment, '$.custom_fee')
+    FROM orders_legacy;
+
+INSERT INTO items (order_uid, position, chrt_id, track_number, price, rid, name, sale, size, total_price, nm_id, brand, status)
+    SELECT orders_legacy.order_uid,
+           item.key,
+           json_extract(item.value, '$.chrt_id'),
+           json_extract(item.value, '$.track_number'),
+           json_extract(item.value, '$.price'),
+           json_extract(item.value, '$.rid'),
+           json_extract(item.value, '$.name'),
+           json_extract(item.value, '$.sale'),
+           json_extract(item.value, '$.size'),
+           json_extract(item.value, '$.total_price'),
+           json_extract(item.value, '$.nm_id'),
+           json_extract(item.value, '$.brand'),
+           json_extract(item.value, '$.status')
+    FROM orders_legacy, json_each(orders_legacy.items) AS item;
+
+DROP TABLE orders_legacy;
+
+CREATE INDEX deliveries_city ON deliveries (city);
+CREATE INDEX payments_bank ON payments (bank);
+CREATE INDEX items_order_uid ON items (order_uid);
+CREATE INDEX items_nm_id ON items (nm_id);
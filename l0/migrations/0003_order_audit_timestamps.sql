@@ -0,0 +1,4 @@
+-- Orders are no longer removed: DELETE /orders/:order_uid sets deleted_at and
+-- the order disappears from every query, PUT and PATCH set updated_at.
+ALTER TABLE orders ADD COLUMN [updated_at] TIMESTAMP;
+ALTER TABLE orders ADD COLUMN [deleted_at] TIMESTAMP;
@@ -0,0 +1,11 @@
+-- Keys of API clients. Only the SHA-256 hash of a key is stored, the prefix
+-- tells keys apart in listings. Revoked keys are kept for audit.
+CREATE TABLE api_keys (
+    [id] INTEGER PRIMARY KEY AUTOINCREMENT,
+    [name] VARCHAR(255) NOT NULL,
+    [scope] VARCHAR(16) NOT NULL CHECK ([scope] IN ('read', 'write', 'admin')),
+    [prefix] VARCHAR(16) NOT NULL,
+    [key_hash] CHAR(64) NOT NULL UNIQUE,
+    [created_at] TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    [revoked_at] TIMESTAMP
+);
@@ -0,0 +1,6 @@
+-- date_created in UTC as text that sorts like the instants, so that ranges,
+-- sorting and the days of /stats/daily do not depend on the offset an order
+-- was sent with. SQLite keeps milliseconds of the orders stored before.
+ALTER TABLE orders ADD COLUMN [created_utc] VARCHAR(64);
+UPDATE orders SET created_utc = strftime('%Y-%m-%dT%H:%M:%f', date_created) || '000000Z';
+CREATE INDEX orders_created_utc ON orders (created_utc, order_uid);
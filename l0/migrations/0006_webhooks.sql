@@ -0,0 +1,40 @@
+-- Changes of orders are written to the outbox in the transaction that makes
+-- them, the webhook dispatcher turns every event into a delivery per webhook.
+-- Times are UTC text with milliseconds, so they compare like the instants.
+CREATE TABLE outbox (
+    [id] INTEGER PRIMARY KEY AUTOINCREMENT,
+    [event] VARCHAR(16) NOT NULL CHECK ([event] IN ('created', 'updated', 'deleted')),
+    [order_uid] VARCHAR(255) NOT NULL,
+    -- the order after the change as JSON
+    [payload] TEXT NOT NULL,
+    [created_at] VARCHAR(32) NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+    [dispatched_at] VARCHAR(32)
+);
+
+CREATE INDEX outbox_undispatched ON outbox (id) WHERE dispatched_at IS NULL;
+
+-- The secret signs the deliveries, so it is kept in the clear. Deleted
+-- webhooks are kept with their deliveries for audit.
+CREATE TABLE webhooks (
+    [id] INTEGER PRIMARY KEY AUTOINCREMENT,
+    [url] TEXT NOT NULL,
+    [secret] VARCHAR(64) NOT NULL,
+    [created_at] VARCHAR(32) NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+    [deleted_at] VARCHAR(32)
+);
+
+-- next_attempt_at is set while a delivery is pending only.
+CREATE TABLE webhook_deliveries (
+    [id] INTEGER PRIMARY KEY AUTOINCREMENT,
+    [webhook_id] INTEGER NOT NULL REFERENCES webhooks ([id]),
+    [event_id] INTEGER NOT NULL REFERENCES outbox ([id]),
+    [status] VARCHAR(16) NOT NULL DEFAULT 'pending' CHECK ([status] IN ('pending', 'delivered', 'dead')),
+    [attempts] INTEGER NOT NULL DEFAULT 0,
+    [next_attempt_at] VARCHAR(32),
+    [last_error] TEXT,
+    [created_at] VARCHAR(32) NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+    [updated_at] VARCHAR(32) NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+    UNIQUE ([webhook_id], [event_id])
+);
+
+CREATE INDEX webhook_deliveries_due ON webhook_deliveries (next_attempt_at) WHERE status = 'pending';
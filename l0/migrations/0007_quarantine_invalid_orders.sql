@@ -0,0 +1,76 @@
+-- Orders stored before locale, payment.currency and date_created were typed
+-- may hold values the types reject, and a single one would fail every load
+-- of the orders. Values that only differ in case or surrounding spaces are
+-- fixed. The other orders are moved here as order JSON, with the fields that
+-- are wrong, to be corrected and imported again.
+CREATE TABLE quarantined_orders (
+    [order_uid] VARCHAR(255) NOT NULL PRIMARY KEY,
+    -- the fields whose values were rejected, e.g. `locale date_created`
+    [reason] TEXT NOT NULL,
+    [order] TEXT NOT NULL,
+    [quarantined_at] TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+UPDATE orders SET locale = lower(trim(locale))
+    WHERE lower(trim(locale)) IN ('ru', 'en', 'be', 'kk', 'ky', 'hy', 'uz');
+UPDATE payments SET currency = upper(trim(currency))
+    WHERE upper(trim(currency)) GLOB '[A-Z][A-Z][A-Z]';
+
+-- created_utc is NULL where SQLite could not read date_created as a time
+CREATE TEMP TABLE invalid_orders AS
+    SELECT order_uid, trim(
+        iif(locale_invalid, 'locale ', '') || iif(currency_invalid, 'payment.currency ', '') || iif(date_created_invalid, 'date_created', '')
+    ) AS reason
+    FROM (
+        SELECT orders.order_uid,
+               orders.locale IS NULL OR orders.locale NOT IN ('ru', 'en', 'be', 'kk', 'ky', 'hy', 'uz') AS locale_invalid,
+               payments.currency IS NULL OR NOT payments.currency GLOB '[A-Z][A-Z][A-Z]' AS currency_invalid,
+               orders.created_utc IS NULL
+                   OR NOT orders.date_created GLOB '[0-9][0-9][0-9][0-9]-[0-9][0-9]-[0-9][0-9][T ][0-9][0-9]:[0-9][0-9]:[0-9][0-9]*'
+                   OR NOT (orders.date_created GLOB '*[Zz]' OR orders.date_created GLOB '*[+-][0-9][0-9]:[0-9][0-9]') AS date_created_invalid
+        FROM orders
+        LEFT JOIN payments ON payments.order_uid = orders.order_uid
+        WHERE orders.deleted_at IS NULL
+    )
+    WHERE locale_invalid OR currency_invalid OR date_created_invalid;
+
+INSERT INTO quarantined_orders ([order_uid], [reason], [order])
+    SELECT orders.order_uid, invalid_orders.reason, json_object(
+        'order_uid', orders.order_uid,
+        'track_number', orders.track_number,
+        'entry', orders.entry,
+        'delivery', (
+            SELECT json_object('name', name, 'phone', phone, 'zip', zip, 'city', city, 'address', address, 'region', region, 'email', email)
+            FROM deliveries WHERE deliveries.order_uid = orders.order_uid
+        ),
+        'payment', (
+            SELECT json_object('transaction', [transaction], 'request_id', request_id, 'currency', currency, 'provider', provider,
+                               'amount', amount, 'payment_dt', payment_dt, 'bank', bank, 'delivery_cost', delivery_cost,
+                               'goods_total', goods_total, 'custom_fee', custom_fee)
+            FROM payments WHERE payments.order_uid = orders.order_uid
+        ),
+        'items', (
+            SELECT json_group_array(json(item)) FROM (
+                SELECT json_object('chrt_id', chrt_id, 'track_number', track_number, 'price', price, 'rid', rid,
+                                   'name', name, 'sale', sale, 'size', size, 'total_price', total_price,
+                                   'nm_id', nm_id, 'brand', brand, 'status', status) AS item
+                FROM items WHERE items.order_uid = orders.order_uid ORDER BY position
+            )
+        ),
+        'locale', orders.locale,
+        'internal_signature', orders.internal_signature,
+        'customer_id', orders.customer_id,
+        'delivery_service', orders.delivery_service,
+        'shardkey', orders.shardkey,
+        'sm_id', orders.sm_id,
+        'date_created', orders.date_created,
+        'oof_shard', orders.oof_shard
+    )
+    FROM orders
+    JOIN invalid_orders ON invalid_orders.order_uid = orders.order_uid;
+
+DELETE FROM items WHERE order_uid IN (SELECT order_uid FROM invalid_orders);
+DELETE FROM payments WHERE order_uid IN (SELECT order_uid FROM invalid_orders);
+DELETE FROM deliveries WHERE order_uid IN (SELECT order_uid FROM invalid_orders);
+DELETE FROM orders WHERE order_uid IN (SELECT order_uid FROM invalid_orders);
+DROP TABLE invalid_orders;
@@ -0,0 +1,29 @@
+-- Orders keep delivery, payment and items as JSONB documents, the columns
+-- next to them are the ones orders are filtered and sorted by. Text that is
+-- sorted compares byte by byte, like SQLite and the in-memory storage.
+CREATE TABLE orders (
+    id BIGSERIAL PRIMARY KEY,
+    order_uid TEXT COLLATE "C" NOT NULL UNIQUE,
+    track_number TEXT NOT NULL,
+    entry TEXT NOT NULL,
+    delivery JSONB NOT NULL,
+    payment JSONB NOT NULL,
+    items JSONB NOT NULL,
+    locale TEXT NOT NULL,
+    internal_signature TEXT NOT NULL,
+    customer_id TEXT NOT NULL,
+    delivery_service TEXT COLLATE "C" NOT NULL,
+    shardkey TEXT NOT NULL,
+    sm_id INTEGER NOT NULL,
+    -- RFC 3339 as received, ranges compare it as text like the other backends
+    date_created TEXT COLLATE "C" NOT NULL,
+    -- date_created in UTC, the days of /stats/daily
+    created_utc TIMESTAMPTZ NOT NULL,
+    oof_shard TEXT NOT NULL,
+    updated_at TIMESTAMPTZ,
+    deleted_at TIMESTAMPTZ
+);
+
+CREATE INDEX orders_date_created ON orders (date_created, order_uid) WHERE deleted_at IS NULL;
+CREATE INDEX orders_sm_id ON orders (sm_id, order_uid) WHERE deleted_at IS NULL;
+CREATE INDEX orders_customer_id ON orders (customer_id) WHERE deleted_at IS NULL;
@@ -0,0 +1,11 @@
+-- Keys of API clients. Only the SHA-256 hash of a key is stored, the prefix
+-- tells keys apart in listings. Revoked keys are kept for audit.
+CREATE TABLE api_keys (
+    id BIGSERIAL PRIMARY KEY,
+    name TEXT NOT NULL,
+    scope TEXT NOT NULL CHECK (scope IN ('read', 'write', 'admin')),
+    prefix TEXT NOT NULL,
+    key_hash CHAR(64) NOT NULL UNIQUE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    revoked_at TIMESTAMPTZ
+);
@@ -0,0 +1,4 @@
+-- Ranges and sorting compare date_created as instants through created_utc,
+-- not as the text an order was sent with.
+DROP INDEX orders_date_created;
+CREATE INDEX orders_created_utc ON orders (created_utc, order_uid) WHERE deleted_at IS NULL;
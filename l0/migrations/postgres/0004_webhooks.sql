@@ -0,0 +1,39 @@
+-- Changes of orders are written to the outbox in the transaction that makes
+-- them, the webhook dispatcher turns every event into a delivery per webhook.
+CREATE TABLE outbox (
+    id BIGSERIAL PRIMARY KEY,
+    event TEXT NOT NULL CHECK (event IN ('created', 'updated', 'deleted')),
+    order_uid TEXT NOT NULL,
+    -- the order after the change
+    payload JSONB NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    dispatched_at TIMESTAMPTZ
+);
+
+CREATE INDEX outbox_undispatched ON outbox (id) WHERE dispatched_at IS NULL;
+
+-- The secret signs the deliveries, so it is kept in the clear. Deleted
+-- webhooks are kept with their deliveries for audit.
+CREATE TABLE webhooks (
+    id BIGSERIAL PRIMARY KEY,
+    url TEXT NOT NULL,
+    secret TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    deleted_at TIMESTAMPTZ
+);
+
+-- next_attempt_at is set while a delivery is pending only.
+CREATE TABLE webhook_deliveries (
+    id BIGSERIAL PRIMARY KEY,
+    webhook_id BIGINT NOT NULL REFERENCES webhooks (id),
+    event_id BIGINT NOT NULL REFERENCES outbox (id),
+    status TEXT NOT NULL DEFAULT 'pending' CHECK (status IN ('pending', 'delivered', 'dead')),
+    attempts INTEGER NOT NULL DEFAULT 0,
+    next_attempt_at TIMESTAMPTZ,
+    last_error TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    UNIQUE (webhook_id, event_id)
+);
+
+CREATE INDEX webhook_deliveries_due ON webhook_deliveries (next_attempt_at) WHERE status = 'pending';
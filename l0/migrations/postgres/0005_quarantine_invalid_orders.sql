@@ -0,0 +1,53 @@
+-- Orders stored before locale, payment.currency and date_created were typed
+-- may hold values the types reject, and a single one would fail every load
+-- of the orders. Values that only differ in case or surrounding spaces are
+-- fixed. The other orders are moved here as order JSON, with the fields that
+-- are wrong, to be corrected and imported again.
+CREATE TABLE quarantined_orders (
+    order_uid TEXT COLLATE "C" PRIMARY KEY,
+    -- the fields whose values were rejected, e.g. `locale date_created`
+    reason TEXT NOT NULL,
+    "order" JSONB NOT NULL,
+    quarantined_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+UPDATE orders SET locale = lower(btrim(locale))
+    WHERE lower(btrim(locale)) IN ('ru', 'en', 'be', 'kk', 'ky', 'hy', 'uz') AND locale <> lower(btrim(locale));
+UPDATE orders SET payment = jsonb_set(payment, '{currency}', to_jsonb(upper(btrim(payment->>'currency'))))
+    WHERE upper(btrim(payment->>'currency')) ~ '^[A-Z]{3}$' AND payment->>'currency' <> upper(btrim(payment->>'currency'));
+
+WITH invalid AS (
+    SELECT order_uid,
+           locale NOT IN ('ru', 'en', 'be', 'kk', 'ky', 'hy', 'uz') AS locale_invalid,
+           coalesce(payment->>'currency' !~ '^[A-Z]{3}$', true) AS currency_invalid,
+           date_created !~ '^\d{4}-\d{2}-\d{2}[Tt ]\d{2}:\d{2}:\d{2}(\.\d+)?([Zz]|[+-]\d{2}:\d{2})$' AS date_created_invalid
+    FROM orders
+    WHERE deleted_at IS NULL
+), moved AS (
+    DELETE FROM orders
+    USING invalid
+    WHERE orders.order_uid = invalid.order_uid AND (locale_invalid OR currency_invalid OR date_created_invalid)
+    RETURNING orders.*, concat_ws(' ',
+        CASE WHEN locale_invalid THEN 'locale' END,
+        CASE WHEN currency_invalid THEN 'payment.currency' END,
+        CASE WHEN date_created_invalid THEN 'date_created' END
+    ) AS reason
+)
+INSERT INTO quarantined_orders (order_uid, reason, "order")
+    SELECT order_uid, reason, jsonb_build_object(
+        'order_uid', order_uid,
+        'track_number', track_number,
+        'entry', entry,
+        'delivery', delivery,
+        'payment', payment,
+        'items', items,
+        'locale', locale,
+        'internal_signature', internal_signature,
+        'customer_id', customer_id,
+        'delivery_service', delivery_service,
+        'shardkey', shardkey,
+        'sm_id', sm_id,
+        'date_created', date_created,
+        'oof_shard', oof_shard
+    )
+    FROM moved;
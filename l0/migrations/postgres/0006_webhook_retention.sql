@@ -0,0 +1,6 @@
+-- Delivered and dead deliveries are deleted once they are older than
+-- webhooks.retention_hours, and with them the events that have no
+-- deliveries left.
+CREATE INDEX webhook_deliveries_finished ON webhook_deliveries (updated_at) WHERE status <> 'pending';
+CREATE INDEX webhook_deliveries_event ON webhook_deliveries (event_id);
+CREATE INDEX outbox_dispatched ON outbox (dispatched_at) WHERE dispatched_at IS NOT NULL;
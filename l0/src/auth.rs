@@ -0,0 +1,404 @@
+use std::{fmt, str::FromStr, sync::{Arc, Mutex}};
+use async_trait::async_trait;
+use axum::{extract::{rejection::JsonRejection, Path, Request, State}, http::{header, HeaderMap, Method, StatusCode}, middleware::Next, response::{IntoResponse, Response}, Extension, Json};
+use base64::{engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD}, Engine};
+use rand::{rngs::OsRng, RngCore};
+use rusqlite::{params, OptionalExtension, Row};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use utoipa::ToSchema;
+
+use crate::Order;
+use crate::db::{self, Pool};
+use crate::error::{AppError, Problem, Resource};
+use crate::repository::RepositoryError;
+use crate::validation;
+
+// Keys start with it, so a leaked key is easy to recognize.
+const KEY_PREFIX: &str = "l0_";
+// characters of a key kept in the clear to tell keys apart
+const SHOWN_PREFIX_LEN: usize = 11;
+// replaces personal data in responses to read-only keys
+pub const REDACTED: &str = "***";
+
+#[derive(Deserialize, Clone, Copy, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    // without authentication every request is allowed everything
+    pub enabled: bool,
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        AuthConfig { enabled: true }
+    }
+}
+
+// What a key allows, each scope includes the ones before it.
+#[derive(Serialize, Deserialize, ToSchema, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    // reading orders and statistics, personal data is redacted
+    Read,
+    // creating and changing orders
+    Write,
+    // managing API keys
+    Admin,
+}
+
+impl Scope {
+    fn as_str(self) -> &'static str {
+        match self {
+            Scope::Read => "read",
+            Scope::Write => "write",
+            Scope::Admin => "admin",
+        }
+    }
+}
+
+impl FromStr for Scope {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "read" => Ok(Scope::Read),
+            "write" => Ok(Scope::Write),
+            "admin" => Ok(Scope::Admin),
+            _ => Err(format!("Unknown scope: {}", value)),
+        }
+    }
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+#[derive(Serialize, Deserialize, ToSchema, Clone, PartialEq, Debug)]
+pub struct ApiKey {
+    pub id: i64,
+    pub name: String,
+    pub scope: Scope,
+    // first characters of the key
+    pub prefix: String,
+    pub created_at: String,
+    pub revoked_at: Option<String>,
+}
+
+// Body of POST /admin/keys.
+#[derive(Deserialize, ToSchema, Debug)]
+pub struct NewApiKey {
+    pub name: String,
+    pub scope: Scope,
+}
+
+// Response of POST /admin/keys, the only place the key itself is shown.
+#[derive(Serialize, Deserialize, ToSchema, Debug)]
+pub struct IssuedApiKey {
+    pub id: i64,
+    pub name: String,
+    pub scope: Scope,
+    pub prefix: String,
+    pub created_at: String,
+    pub key: String,
+}
+
+// Storage of API keys, keys are looked up by their hash.
+#[async_trait]
+pub trait KeyRepository: Send + Sync {
+    async fn insert(&self, name: &str, scope: Scope, prefix: &str, key_hash: &str) -> Result<ApiKey, RepositoryError>;
+    async fn list(&self) -> Result<Vec<ApiKey>, RepositoryError>;
+    // The key with the hash unless it has been revoked.
+    async fn find(&self, key_hash: &str) -> Result<Option<ApiKey>, RepositoryError>;
+    // Returns false if there is no key with the id, revoking twice is not an error.
+    async fn revoke(&self, id: i64) -> Result<bool, RepositoryError>;
+}
+
+pub fn hash_key(key: &str) -> String {
+    Sha256::digest(key.as_bytes()).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// Generates a key of 256 random bits and stores its hash.
+pub async fn issue_key(keys: &dyn KeyRepository, name: &str, scope: Scope) -> Result<IssuedApiKey, RepositoryError> {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    let key = format!("{}{}", KEY_PREFIX, URL_SAFE_NO_PAD.encode(bytes));
+
+    let api_key = keys.insert(name, scope, &key[..SHOWN_PREFIX_LEN], &hash_key(&key)).await?;
+    Ok(IssuedApiKey {
+        id: api_key.id,
+        name: api_key.name,
+        scope: api_key.scope,
+        prefix: api_key.prefix,
+        created_at: api_key.created_at,
+        key,
+    })
+}
+
+// sqlite
+pub struct SqliteKeyRepository {
+    pool: Pool,
+}
+
+impl SqliteKeyRepository {
+    pub fn new(pool: Pool) -> Self {
+        SqliteKeyRepository { pool }
+    }
+}
+
+const KEY_COLUMNS: &str = "id, name, scope, prefix, created_at, revoked_at";
+
+fn api_key(row: &Row) -> rusqlite::Result<ApiKey> {
+    let scope: String = row.get(2)?;
+    Ok(ApiKey {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        scope: scope.parse().map_err(|_| rusqlite::Error::InvalidColumnType(2, String::from("scope"), rusqlite::types::Type::Text))?,
+        prefix: row.get(3)?,
+        created_at: row.get(4)?,
+        revoked_at: row.get(5)?,
+    })
+}
+
+#[async_trait]
+impl KeyRepository for SqliteKeyRepository {
+    async fn insert(&self, name: &str, scope: Scope, prefix: &str, key_hash: &str) -> Result<ApiKey, RepositoryError> {
+        let (name, prefix, key_hash) = (name.to_string(), prefix.to_string(), key_hash.to_string());
+        db::with_connection(&self.pool, "insert_key", move |connection| {
+            connection.execute(
+                "INSERT INTO api_keys (name, scope, prefix, key_hash) VALUES (?1, ?2, ?3, ?4)",
+                params![name, scope.as_str(), prefix, key_hash],
+            )?;
+            let id = connection.last_insert_rowid();
+            let api_key = connection.query_row(&format!("SELECT {} FROM api_keys WHERE id = ?1", KEY_COLUMNS), params![id], api_key)?;
+            Ok(api_key)
+        }).await
+    }
+
+    async fn list(&self) -> Result<Vec<ApiKey>, RepositoryError> {
+        db::with_connection(&self.pool, "list_keys", |connection| {
+            let mut stmt = connection.prepare(&format!("SELECT {} FROM api_keys ORDER BY id", KEY_COLUMNS))?;
+            let keys = stmt.query_map([], api_key)?.collect::<Result<Vec<ApiKey>, rusqlite::Error>>()?;
+            Ok(keys)
+        }).await
+    }
+
+    async fn find(&self, key_hash: &str) -> Result<Option<ApiKey>, RepositoryError> {
+        let key_hash = key_hash.to_string();
+        db::with_connection(&self.pool, "find_key", move |connection| {
+            let api_key = connection.query_row(
+                &format!("SELECT {} FROM api_keys WHERE key_hash = ?1 AND revoked_at IS NULL", KEY_COLUMNS),
+                params![key_hash],
+                api_key,
+            ).optional()?;
+            Ok(api_key)
+        }).await
+    }
+
+    async fn revoke(&self, id: i64) -> Result<bool, RepositoryError> {
+        db::with_connection(&self.pool, "revoke_key", move |connection| {
+            connection.execute("UPDATE api_keys SET revoked_at = CURRENT_TIMESTAMP WHERE id = ?1 AND revoked_at IS NULL", params![id])?;
+            let exists = connection.query_row("SELECT 1 FROM api_keys WHERE id = ?1", params![id], |_| Ok(())).optional()?;
+            Ok(exists.is_some())
+        }).await
+    }
+}
+
+// in-memory
+// Keys of the in-memory storage, they are gone after a restart.
+#[derive(Default)]
+pub struct InMemoryKeyRepository {
+    keys: Mutex<Vec<(ApiKey, String)>>,
+}
+
+impl InMemoryKeyRepository {
+    pub fn new() -> Self {
+        InMemoryKeyRepository::default()
+    }
+}
+
+#[async_trait]
+impl KeyRepository for InMemoryKeyRepository {
+    async fn insert(&self, name: &str, scope: Scope, prefix: &str, key_hash: &str) -> Result<ApiKey, RepositoryError> {
+        let mut keys = self.keys.lock().map_err(|err| RepositoryError::Storage(err.to_string()))?;
+        let api_key = ApiKey {
+            id: keys.len() as i64 + 1,
+            name: name.to_string(),
+            scope,
+            prefix: prefix.to_string(),
+            created_at: chrono::Utc::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+            revoked_at: None,
+        };
+        keys.push((api_key.clone(), key_hash.to_string()));
+        Ok(api_key)
+    }
+
+    async fn list(&self) -> Result<Vec<ApiKey>, RepositoryError> {
+        let keys = self.keys.lock().map_err(|err| RepositoryError::Storage(err.to_string()))?;
+        Ok(keys.iter().map(|(api_key, _)| api_key.clone()).collect())
+    }
+
+    async fn find(&self, key_hash: &str) -> Result<Option<ApiKey>, RepositoryError> {
+        let keys = self.keys.lock().map_err(|err| RepositoryError::Storage(err.to_string()))?;
+        Ok(keys.iter()
+            .find(|(api_key, hash)| hash == key_hash && api_key.revoked_at.is_none())
+            .map(|(api_key, _)| api_key.clone()))
+    }
+
+    async fn revoke(&self, id: i64) -> Result<bool, RepositoryError> {
+        let mut keys = self.keys.lock().map_err(|err| RepositoryError::Storage(err.to_string()))?;
+        let Some((api_key, _)) = keys.iter_mut().find(|(api_key, _)| api_key.id == id) else {
+            return Ok(false);
+        };
+        api_key.revoked_at.get_or_insert_with(|| chrono::Utc::now().format("%Y-%m-%d %H:%M:%S").to_string());
+        Ok(true)
+    }
+}
+
+// What the request is allowed, `authenticate` adds it to the request.
+#[derive(Clone, Copy, Debug)]
+pub struct Access {
+    pub scope: Scope,
+    // `None` when authentication is disabled
+    pub key_id: Option<i64>,
+}
+
+impl Access {
+    // Hides the customer, their id and contact details, from read-only keys.
+    pub fn redact(self, order: &mut Order) {
+        if self.scope > Scope::Read {
+            return;
+        }
+        // links the orders of a customer together
+        order.customer_id = String::from(REDACTED);
+        let delivery = &mut order.delivery;
+        // city and region with the zip would still locate the customer
+        for field in [&mut delivery.name, &mut delivery.phone, &mut delivery.zip, &mut delivery.city, &mut delivery.address, &mut delivery.region, &mut delivery.email] {
+            *field = String::from(REDACTED);
+        }
+    }
+
+    // Read-only keys may not filter by the customer_id redact hides, the
+    // matching orders would tell whose they are.
+    pub fn check_customer_filter(self, customer_id: Option<&String>) -> Result<(), AppError> {
+        if self.scope <= Scope::Read && customer_id.is_some() {
+            return Err(AppError::Forbidden(Scope::Write));
+        }
+        Ok(())
+    }
+}
+
+// State of the `authenticate` layer.
+#[derive(Clone)]
+pub struct Auth {
+    config: AuthConfig,
+    keys: Arc<dyn KeyRepository>,
+}
+
+impl Auth {
+    pub fn new(config: AuthConfig, keys: Arc<dyn KeyRepository>) -> Self {
+        Auth { config, keys }
+    }
+}
+
+// GET and HEAD read, other methods write, /admin/* needs the admin scope.
+fn required_scope(method: &Method, path: &str) -> Scope {
+    if path.starts_with("/admin/") {
+        Scope::Admin
+    } else if method == Method::GET || method == Method::HEAD {
+        Scope::Read
+    } else {
+        Scope::Write
+    }
+}
+
+// `Authorization: Bearer <key>`, or HTTP Basic with the key as the password
+// so the browser can log in to /ui, the user name is ignored.
+fn request_key(headers: &HeaderMap) -> Option<String> {
+    let value = headers.get(header::AUTHORIZATION)?.to_str().ok()?;
+    let (scheme, credentials) = value.split_once(' ')?;
+    if scheme.eq_ignore_ascii_case("bearer") {
+        return Some(credentials.trim().to_string());
+    }
+    if !scheme.eq_ignore_ascii_case("basic") {
+        return None;
+    }
+    let credentials = String::from_utf8(STANDARD.decode(credentials.trim()).ok()?).ok()?;
+    let (_, key) = credentials.split_once(':')?;
+    Some(key.to_string())
+}
+
+async fn authorize(auth: &Auth, headers: &HeaderMap, required: Scope) -> Result<Access, AppError> {
+    if !auth.config.enabled {
+        return Ok(Access { scope: Scope::Admin, key_id: None });
+    }
+    let key = request_key(headers).ok_or(AppError::Unauthorized)?;
+    let api_key = auth.keys.find(&hash_key(&key)).await?.ok_or(AppError::Unauthorized)?;
+    crate::logging::record_api_key(api_key.id);
+
+    if api_key.scope < required {
+        return Err(AppError::Forbidden(required));
+    }
+    Ok(Access { scope: api_key.scope, key_id: Some(api_key.id) })
+}
+
+// Layer of every route that exposes orders or keys.
+pub async fn authenticate(State(auth): State<Auth>, mut request: Request, next: Next) -> Response {
+    let required = required_scope(request.method(), request.uri().path());
+    match authorize(&auth, request.headers(), required).await {
+        Ok(access) => {
+            request.extensions_mut().insert(access);
+            next.run(request).await
+        }
+        Err(err) => err.into_response(),
+    }
+}
+
+// handlers
+#[utoipa::path(post, path = "/admin/keys", tag = "admin", request_body = NewApiKey,
+    responses(
+        (status = 201, body = IssuedApiKey),
+        (status = 400, body = Problem),
+        (status = 422, body = Problem),
+        (status = 500, body = Problem),
+    ))]
+pub async fn create_key(Extension(keys): Extension<Arc<dyn KeyRepository>>, payload: Result<Json<NewApiKey>, JsonRejection>) -> Result<(StatusCode, Json<IssuedApiKey>), AppError> {
+    let Json(new_key) = payload.map_err(|rejection| AppError::InvalidJson(Resource::ApiKey, rejection))?;
+    let mut errors = Vec::new();
+    validation::required("", "name", &new_key.name, &mut errors);
+    if !errors.is_empty() {
+        return Err(AppError::Validation(Resource::ApiKey, errors));
+    }
+
+    let issued = issue_key(keys.as_ref(), new_key.name.trim(), new_key.scope).await?;
+    tracing::info!(id = issued.id, scope = %issued.scope, "API key issued");
+    Ok((StatusCode::CREATED, Json(issued)))
+}
+
+#[utoipa::path(get, path = "/admin/keys", tag = "admin",
+    responses(
+        (status = 200, body = Vec<ApiKey>),
+        (status = 500, body = Problem),
+    ))]
+pub async fn list_keys(Extension(keys): Extension<Arc<dyn KeyRepository>>) -> Result<Json<Vec<ApiKey>>, AppError> {
+    Ok(Json(keys.list().await?))
+}
+
+#[utoipa::path(delete, path = "/admin/keys/{id}", tag = "admin", params(("id" = i64, Path)),
+    responses(
+        (status = 204, description = "The key has been revoked"),
+        (status = 404, body = Problem),
+        (status = 500, body = Problem),
+    ))]
+pub async fn revoke_key(Path(id): Path<String>, Extension(keys): Extension<Arc<dyn KeyRepository>>) -> Result<StatusCode, AppError> {
+    let revoked = match id.parse::<i64>() {
+        Ok(key_id) => keys.revoke(key_id).await?,
+        Err(_) => false,
+    };
+    if !revoked {
+        return Err(AppError::KeyNotFound(id));
+    }
+    tracing::info!(id, "API key revoked");
+    Ok(StatusCode::NO_CONTENT)
+}
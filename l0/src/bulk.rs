@@ -0,0 +1,360 @@
+use std::{io, sync::Arc};
+use axum::{body::{Body, Bytes}, extract::{rejection::QueryRejection, Query}, http::header, response::{IntoResponse, Response}, Extension, Json};
+use futures_util::{stream, StreamExt, TryStreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::Order;
+use crate::auth::Access;
+use crate::config::ServerConfig;
+use crate::limits;
+use crate::error::{AppError, Problem, Resource};
+use crate::query::{Cursor, OrderFilter, SortKey};
+use crate::repository::{Inserted, OrderRepository, RepositoryError};
+use crate::validation::{self, InvalidOrder};
+
+// orders stored in one transaction by POST /orders/bulk
+pub const IMPORT_BATCH_SIZE: usize = 500;
+// orders loaded at once by GET /orders/export
+pub const EXPORT_PAGE_SIZE: usize = 500;
+// line errors a report lists, `failed` counts all of them
+pub const MAX_IMPORT_ERRORS: usize = 100;
+
+// Response of POST /orders/bulk.
+#[derive(Serialize, Deserialize, ToSchema, Default, PartialEq, Debug)]
+pub struct ImportReport {
+    pub created: usize,
+    // identical orders that were already stored
+    pub unchanged: usize,
+    pub failed: usize,
+    // the errors of the first MAX_IMPORT_ERRORS failed lines
+    pub errors: Vec<LineError>,
+}
+
+// An order that was not stored, `code` is one of the error codes of the API
+// or `line_too_long`.
+#[derive(Serialize, Deserialize, ToSchema, PartialEq, Debug)]
+pub struct LineError {
+    // 1-based line number in the request body
+    pub line: usize,
+    pub code: String,
+    pub message: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<Value>,
+}
+
+impl LineError {
+    fn new(line: usize, problem: Problem) -> Self {
+        LineError { line, code: problem.code, message: problem.message, detail: problem.detail }
+    }
+}
+
+enum Line {
+    Complete(usize, Vec<u8>),
+    TooLong(usize),
+}
+
+// Splits a body arriving in chunks into lines of at most `limit` bytes,
+// the rest of a longer line is skipped.
+struct LineReader {
+    limit: usize,
+    buffer: Vec<u8>,
+    number: usize,
+    too_long: bool,
+}
+
+impl LineReader {
+    fn new(limit: usize) -> Self {
+        LineReader { limit, buffer: Vec::new(), number: 0, too_long: false }
+    }
+
+    fn push(&mut self, mut chunk: &[u8]) -> Vec<Line> {
+        let mut lines = Vec::new();
+        loop {
+            let end = chunk.iter().position(|byte| *byte == b'\n');
+            let part = &chunk[..end.unwrap_or(chunk.len())];
+            if !self.too_long {
+                if self.buffer.len() + part.len() > self.limit {
+                    self.too_long = true;
+                    self.buffer.clear();
+                } else {
+                    self.buffer.extend_from_slice(part);
+                }
+            }
+            let Some(end) = end else {
+                return lines;
+            };
+            lines.push(self.finish_line());
+            chunk = &chunk[end + 1..];
+        }
+    }
+
+    fn finish(mut self) -> Option<Line> {
+        (self.too_long || !self.buffer.is_empty()).then(|| self.finish_line())
+    }
+
+    fn finish_line(&mut self) -> Line {
+        self.number += 1;
+        if std::mem::take(&mut self.too_long) {
+            Line::TooLong(self.number)
+        } else {
+            Line::Complete(self.number, std::mem::take(&mut self.buffer))
+        }
+    }
+}
+
+struct Import {
+    repository: Arc<dyn OrderRepository>,
+    report: ImportReport,
+    // orders waiting to be stored with their line numbers
+    batch: Vec<(usize, Order)>,
+    max_items: usize,
+}
+
+impl Import {
+    async fn line(&mut self, line: Line, limit: usize) -> Result<(), AppError> {
+        match line {
+            Line::TooLong(number) => self.fail(number, Problem {
+                code: String::from("line_too_long"),
+                message: format!("The line is longer than {} bytes", limit),
+                detail: None,
+            }),
+            Line::Complete(_, bytes) if bytes.trim_ascii().is_empty() => {}
+            Line::Complete(number, bytes) => match validation::parse_order(&bytes) {
+                Err(InvalidOrder::Json(err)) => self.fail(number, Problem {
+                    code: String::from("invalid_json"),
+                    message: String::from("The line is not a valid order"),
+                    detail: Some(Value::String(err)),
+                }),
+                Err(InvalidOrder::Rules(errors)) => self.fail(number, AppError::Validation(Resource::Order, errors).problem()),
+                Ok(order) => match limits::check_item_count(&order, self.max_items) {
+                    Err(errors) => self.fail(number, AppError::Validation(Resource::Order, errors).problem()),
+                    Ok(()) => self.batch.push((number, order)),
+                },
+            },
+        }
+
+        if self.batch.len() >= IMPORT_BATCH_SIZE {
+            self.flush().await?;
+        }
+        Ok(())
+    }
+
+    async fn flush(&mut self) -> Result<(), AppError> {
+        let (numbers, orders): (Vec<usize>, Vec<Order>) = std::mem::take(&mut self.batch).into_iter().unzip();
+        if orders.is_empty() {
+            return Ok(());
+        }
+        let results = self.repository.insert_batch(&orders).await?;
+        for (number, result) in numbers.into_iter().zip(results) {
+            match result {
+                Ok(Inserted::Created) => self.report.created += 1,
+                Ok(Inserted::Unchanged) => self.report.unchanged += 1,
+                Err(err) => self.fail(number, AppError::from(err).problem()),
+            }
+        }
+        Ok(())
+    }
+
+    fn fail(&mut self, line: usize, problem: Problem) {
+        self.report.failed += 1;
+        self.report.errors.push(LineError::new(line, problem));
+        if self.report.errors.len() > MAX_IMPORT_ERRORS {
+            // storage errors of a batch arrive after the errors of later lines
+            if let Some((last, _)) = self.report.errors.iter().enumerate().max_by_key(|(_, error)| error.line) {
+                self.report.errors.swap_remove(last);
+            }
+        }
+    }
+}
+
+// Stores orders sent as NDJSON, one order per line, in batches of
+// IMPORT_BATCH_SIZE. The body is read as it arrives, only a line has to fit
+// into the body limit.
+#[utoipa::path(post, path = "/orders/bulk", tag = "orders",
+    request_body(content = String, content_type = "application/x-ndjson", description = "One order per line"),
+    responses(
+        (status = 200, body = ImportReport),
+        (status = 400, body = Problem),
+        (status = 500, body = Problem),
+    ))]
+pub async fn import_orders(Extension(repository): Extension<Arc<dyn OrderRepository>>, Extension(server): Extension<ServerConfig>, body: Body) -> Result<Json<ImportReport>, AppError> {
+    let mut import = Import { repository, report: ImportReport::default(), batch: Vec::new(), max_items: server.max_items };
+    let mut reader = LineReader::new(server.body_limit);
+
+    let mut chunks = body.into_data_stream();
+    while let Some(chunk) = chunks.next().await {
+        let chunk = chunk.map_err(|err| AppError::InvalidBody(err.to_string()))?;
+        for line in reader.push(&chunk) {
+            import.line(line, server.body_limit).await?;
+        }
+    }
+    if let Some(line) = reader.finish() {
+        import.line(line, server.body_limit).await?;
+    }
+    import.flush().await?;
+    // storage errors come in with their batch
+    import.report.errors.sort_by_key(|error| error.line);
+
+    tracing::info!(created = import.report.created, unchanged = import.report.unchanged, failed = import.report.failed, "orders imported");
+    Ok(Json(import.report))
+}
+
+#[derive(Deserialize, ToSchema, Default, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportFormat {
+    #[default]
+    Ndjson,
+    // one row per item
+    Csv,
+}
+
+// Query string of GET /orders/export.
+#[derive(Deserialize, IntoParams, Default, Debug)]
+#[into_params(parameter_in = Query)]
+pub struct ExportQuery {
+    #[serde(default)]
+    pub format: ExportFormat,
+}
+
+// Streams every stored order sorted by date_created, a page of
+// EXPORT_PAGE_SIZE orders at a time.
+#[utoipa::path(get, path = "/orders/export", tag = "orders", params(ExportQuery),
+    responses(
+        (status = 200, content(
+            (String = "application/x-ndjson"),
+            (String = "text/csv"),
+        )),
+        (status = 400, body = Problem),
+    ))]
+pub async fn export_orders(Extension(repository): Extension<Arc<dyn OrderRepository>>, Extension(access): Extension<Access>, query: Result<Query<ExportQuery>, QueryRejection>) -> Result<Response, AppError> {
+    let Query(query) = query?;
+    let (content_type, filename) = match query.format {
+        ExportFormat::Ndjson => ("application/x-ndjson", "orders.ndjson"),
+        ExportFormat::Csv => ("text/csv; charset=utf-8", "orders.csv"),
+    };
+
+    let header = match query.format {
+        ExportFormat::Ndjson => None,
+        ExportFormat::Csv => Some(Ok(Bytes::from(csv_record(CSV_HEADER.iter().copied())))),
+    };
+    let pages = stream::try_unfold(Some(None), move |after: Option<Option<Cursor>>| {
+        let repository = Arc::clone(&repository);
+        async move {
+            let Some(after) = after else {
+                return Ok(None);
+            };
+            let filter = OrderFilter { limit: EXPORT_PAGE_SIZE, after, ..OrderFilter::default() };
+            let mut page = repository.page(&filter).await?;
+            page.orders.iter_mut().for_each(|order| access.redact(order));
+            let next = match (&page.next_cursor, page.orders.last()) {
+                (Some(_), Some(last)) => Some(Some(Cursor { key: SortKey::of(last, filter.sort), order_uid: last.order_uid.clone() })),
+                _ => None,
+            };
+            Ok::<_, RepositoryError>(Some((Bytes::from(export_page(&page.orders, query.format)), next)))
+        }
+    });
+    // the status has already been sent, all that is left is to cut the response short
+    let body = stream::iter(header).chain(pages).map_err(|err| {
+        tracing::error!(%err, "Failed to export orders");
+        io::Error::other(err.to_string())
+    });
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, content_type.to_string()),
+            (header::CONTENT_DISPOSITION, format!("attachment; filename=\"{}\"", filename)),
+        ],
+        Body::from_stream(body),
+    ).into_response())
+}
+
+fn export_page(orders: &[Order], format: ExportFormat) -> Vec<u8> {
+    let mut out = Vec::new();
+    for order in orders {
+        match format {
+            ExportFormat::Ndjson => {
+                if serde_json::to_writer(&mut out, order).is_ok() {
+                    out.push(b'\n');
+                }
+            }
+            ExportFormat::Csv => {
+                if order.items.is_empty() {
+                    out.extend(csv_record(csv_row(order, None).iter().map(String::as_str)));
+                }
+                for item in &order.items {
+                    out.extend(csv_record(csv_row(order, Some(item)).iter().map(String::as_str)));
+                }
+            }
+        }
+    }
+    out
+}
+
+const CSV_HEADER: [&str; 39] = [
+    "order_uid", "track_number", "entry", "locale", "internal_signature", "customer_id", "delivery_service",
+    "shardkey", "sm_id", "date_created", "oof_shard",
+    "delivery_name", "delivery_phone", "delivery_zip", "delivery_city", "delivery_address", "delivery_region", "delivery_email",
+    "payment_transaction", "payment_request_id", "payment_currency", "payment_provider", "payment_amount", "payment_payment_dt",
+    "payment_bank", "payment_delivery_cost", "payment_goods_total", "payment_custom_fee",
+    "item_chrt_id", "item_track_number", "item_price", "item_rid", "item_name", "item_sale", "item_size", "item_total_price",
+    "item_nm_id", "item_brand", "item_status",
+];
+
+// Flattens an order and one of its items into the columns of CSV_HEADER.
+fn csv_row(order: &Order, item: Option<&crate::Item>) -> Vec<String> {
+    let delivery = &order.delivery;
+    let payment = &order.payment;
+    let mut row = vec![
+        order.order_uid.clone(), order.track_number.clone(), order.entry.clone(), order.locale.to_string(),
+        order.internal_signature.clone(), order.customer_id.clone(), order.delivery_service.clone(),
+        order.shardkey.clone(), order.sm_id.to_string(), order.date_created.to_string(), order.oof_shard.clone(),
+        delivery.name.clone(), delivery.phone.clone(), delivery.zip.clone(), delivery.city.clone(),
+        delivery.address.clone(), delivery.region.clone(), delivery.email.clone(),
+        payment.transaction.clone(), payment.request_id.clone(), payment.currency.to_string(), payment.provider.clone(),
+        payment.amount.to_string(), payment.payment_dt.seconds().to_string(), payment.bank.clone(),
+        payment.delivery_cost.to_string(), payment.goods_total.to_string(), payment.custom_fee.to_string(),
+    ];
+    match item {
+        Some(item) => row.extend([
+            item.chrt_id.to_string(), item.track_number.clone(), item.price.to_string(), item.rid.clone(),
+            item.name.clone(), item.sale.to_string(), item.size.clone(), item.total_price.to_string(),
+            item.nm_id.to_string(), item.brand.clone(), item.status.to_string(),
+        ]),
+        None => row.extend(std::iter::repeat_n(String::new(), 11)),
+    }
+    row
+}
+
+// One RFC 4180 record, fields with separators, quotes or line breaks are quoted.
+fn csv_record<'a>(fields: impl Iterator<Item = &'a str>) -> Vec<u8> {
+    let mut record = Vec::new();
+    for (index, field) in fields.enumerate() {
+        if index > 0 {
+            record.push(b',');
+        }
+        let escaped;
+        let field = if is_formula(field) {
+            escaped = format!("'{}", field);
+            escaped.as_str()
+        } else {
+            field
+        };
+        if field.contains([',', '"', '\n', '\r']) {
+            record.push(b'"');
+            record.extend(field.replace('"', "\"\"").into_bytes());
+            record.push(b'"');
+        } else {
+            record.extend(field.as_bytes());
+        }
+    }
+    record.extend(b"\r\n");
+    record
+}
+
+// Spreadsheets run text starting with these characters as a formula, a
+// leading ' keeps it text. Numbers such as -5 or +9720000000 stay as they are.
+fn is_formula(field: &str) -> bool {
+    field.starts_with(['=', '+', '-', '@', '\t', '\r']) && field.parse::<f64>().is_err()
+}
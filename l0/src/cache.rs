@@ -0,0 +1,289 @@
+use std::{collections::{BTreeMap, HashMap}, str::FromStr, sync::{atomic::{AtomicBool, Ordering}, Arc, Mutex, OnceLock}};
+use async_trait::async_trait;
+use serde::Deserialize;
+
+use crate::Order;
+use crate::metrics::Metrics;
+use crate::query::{OrderFilter, OrderPage};
+use crate::repository::{Inserted, OrderRepository, RepositoryError};
+use crate::stats::{BrandTotal, DailyOrders, DateRange, Revenue};
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum EvictionPolicy {
+    // evicts the order that was read or written least recently
+    Lru,
+    // evicts the order that was written first, reads do not count
+    Fifo,
+}
+
+impl FromStr for EvictionPolicy {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "lru" => Ok(EvictionPolicy::Lru),
+            "fifo" => Ok(EvictionPolicy::Fifo),
+            _ => Err(format!("Unknown eviction policy: {}", value)),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct CacheConfig {
+    pub capacity: usize,
+    pub policy: EvictionPolicy,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        CacheConfig { capacity: 10_000, policy: EvictionPolicy::Lru }
+    }
+}
+
+pub struct OrderCache {
+    config: CacheConfig,
+    state: Mutex<CacheState>,
+}
+
+#[derive(Default)]
+struct CacheState {
+    tick: u64,
+    orders: HashMap<String, (u64, Order)>,
+    // order_uid by the tick of its last use, the oldest one is evicted first
+    queue: BTreeMap<u64, String>,
+    // counts invalidations, a fill remembers the one it began at
+    generation: u64,
+    // fills in flight, `invalidated` is cleared once there are none
+    fills: usize,
+    // generation at which an order_uid was last invalidated during a fill
+    invalidated: HashMap<String, u64>,
+}
+
+impl CacheState {
+    fn touch(&mut self, order_uid: &str) {
+        if let Some((tick, _)) = self.orders.get_mut(order_uid) {
+            self.queue.remove(tick);
+            self.tick += 1;
+            *tick = self.tick;
+            self.queue.insert(self.tick, order_uid.to_string());
+        }
+    }
+
+    fn insert(&mut self, capacity: usize, order: Order) {
+        if capacity == 0 {
+            return;
+        }
+        if let Some((tick, _)) = self.orders.remove(&order.order_uid) {
+            self.queue.remove(&tick);
+        }
+
+        while self.orders.len() >= capacity {
+            let Some((_, order_uid)) = self.queue.pop_first() else {
+                break;
+            };
+            self.orders.remove(&order_uid);
+        }
+
+        self.tick += 1;
+        let tick = self.tick;
+        self.queue.insert(tick, order.order_uid.clone());
+        self.orders.insert(order.order_uid.clone(), (tick, order));
+    }
+}
+
+// Fills the cache with orders read or written after it began, unless they
+// have been invalidated since: the order may be older than the write that
+// invalidated it. Begun by `OrderCache::fill`.
+pub struct Fill<'a> {
+    cache: &'a OrderCache,
+    generation: u64,
+}
+
+impl Fill<'_> {
+    pub fn put(&self, order: Order) {
+        let Ok(mut state) = self.cache.state.lock() else {
+            return;
+        };
+        if state.invalidated.get(&order.order_uid).is_some_and(|generation| *generation > self.generation) {
+            return;
+        }
+        state.insert(self.cache.config.capacity, order);
+    }
+}
+
+// also runs when the request is cancelled mid-read
+impl Drop for Fill<'_> {
+    fn drop(&mut self) {
+        let Ok(mut state) = self.cache.state.lock() else {
+            return;
+        };
+        state.fills -= 1;
+        if state.fills == 0 {
+            state.invalidated.clear();
+        }
+    }
+}
+
+impl OrderCache {
+    pub fn new(config: CacheConfig) -> Self {
+        OrderCache { config, state: Mutex::new(CacheState::default()) }
+    }
+
+    pub fn get(&self, order_uid: &str) -> Option<Order> {
+        let mut state = self.state.lock().ok()?;
+        if self.config.policy == EvictionPolicy::Lru {
+            state.touch(order_uid);
+        }
+        state.orders.get(order_uid).map(|(_, order)| order.clone())
+    }
+
+    // Begins a read or write of the storage whose orders are to be cached.
+    pub fn fill(&self) -> Fill<'_> {
+        let mut generation = 0;
+        if let Ok(mut state) = self.state.lock() {
+            state.fills += 1;
+            generation = state.generation;
+        }
+        Fill { cache: self, generation }
+    }
+
+    // Drops the order and keeps fills begun before from caching it again.
+    pub fn remove(&self, order_uid: &str) {
+        let Ok(mut state) = self.state.lock() else {
+            return;
+        };
+        if let Some((tick, _)) = state.orders.remove(order_uid) {
+            state.queue.remove(&tick);
+        }
+        state.generation += 1;
+        if state.fills > 0 {
+            let generation = state.generation;
+            state.invalidated.insert(order_uid.to_string(), generation);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.state.lock().map(|state| state.orders.len()).unwrap_or(0)
+    }
+}
+
+// Serves `get` from the cache and keeps it up to date on writes; everything
+// else goes to the wrapped repository.
+pub struct CachedOrderRepository {
+    inner: Arc<dyn OrderRepository>,
+    cache: OrderCache,
+    warmed_up: AtomicBool,
+    // why the warm-up failed, reported by `ready`
+    warm_up_error: OnceLock<String>,
+    metrics: Metrics,
+}
+
+impl CachedOrderRepository {
+    pub fn new(inner: Arc<dyn OrderRepository>, config: CacheConfig, metrics: Metrics) -> Self {
+        CachedOrderRepository { inner, cache: OrderCache::new(config), warmed_up: AtomicBool::new(false), warm_up_error: OnceLock::new(), metrics }
+    }
+
+    // Loads stored orders into the cache, returns the number of cached orders.
+    // The repository is not ready until this has finished, nor after it failed.
+    pub async fn warm_up(&self) -> Result<usize, RepositoryError> {
+        let fill = self.cache.fill();
+        let orders = self.inner.list().await.inspect_err(|err| {
+            self.warm_up_error.set(err.to_string()).ok();
+        })?;
+        for order in orders {
+            fill.put(order);
+        }
+        drop(fill);
+        self.warmed_up.store(true, Ordering::SeqCst);
+        Ok(self.cache.len())
+    }
+}
+
+#[async_trait]
+impl OrderRepository for CachedOrderRepository {
+    async fn list(&self) -> Result<Vec<Order>, RepositoryError> {
+        self.inner.list().await
+    }
+
+    async fn page(&self, filter: &OrderFilter) -> Result<OrderPage, RepositoryError> {
+        self.inner.page(filter).await
+    }
+
+    async fn get(&self, order_uid: &str) -> Result<Option<Order>, RepositoryError> {
+        if let Some(order) = self.cache.get(order_uid) {
+            self.metrics.cache_hit();
+            return Ok(Some(order));
+        }
+        self.metrics.cache_miss();
+
+        // a concurrent update or delete keeps the order read here out of the cache
+        let fill = self.cache.fill();
+        let order = self.inner.get(order_uid).await?;
+        if let Some(order) = &order {
+            fill.put(order.clone());
+        }
+        Ok(order)
+    }
+
+    async fn insert(&self, order: &Order) -> Result<Inserted, RepositoryError> {
+        let fill = self.cache.fill();
+        let inserted = self.inner.insert(order).await?;
+        fill.put(order.clone());
+        Ok(inserted)
+    }
+
+    async fn insert_batch(&self, orders: &[Order]) -> Result<Vec<Result<Inserted, RepositoryError>>, RepositoryError> {
+        let fill = self.cache.fill();
+        let results = self.inner.insert_batch(orders).await?;
+        for (order, result) in orders.iter().zip(&results) {
+            if result.is_ok() {
+                fill.put(order.clone());
+            }
+        }
+        Ok(results)
+    }
+
+    // Changes invalidate the order once they are stored, the next `get`
+    // reads it again. Putting the order back instead could race with
+    // another change of it. A failed change may still have changed the
+    // storage, so the order is invalidated in any case.
+    async fn update(&self, order: &Order) -> Result<(), RepositoryError> {
+        let updated = self.inner.update(order).await;
+        self.cache.remove(&order.order_uid);
+        updated
+    }
+
+    async fn delete(&self, order_uid: &str) -> Result<(), RepositoryError> {
+        let deleted = self.inner.delete(order_uid).await;
+        self.cache.remove(order_uid);
+        deleted
+    }
+
+    async fn count(&self) -> Result<u64, RepositoryError> {
+        self.inner.count().await
+    }
+
+    async fn revenue(&self, range: &DateRange) -> Result<Vec<Revenue>, RepositoryError> {
+        self.inner.revenue(range).await
+    }
+
+    async fn top_brands(&self, range: &DateRange, limit: usize) -> Result<Vec<BrandTotal>, RepositoryError> {
+        self.inner.top_brands(range, limit).await
+    }
+
+    async fn daily_orders(&self, range: &DateRange) -> Result<Vec<DailyOrders>, RepositoryError> {
+        self.inner.daily_orders(range).await
+    }
+
+    async fn ready(&self) -> Result<(), RepositoryError> {
+        if let Some(err) = self.warm_up_error.get() {
+            return Err(RepositoryError::Storage(format!("the cache could not be warmed up: {}", err)));
+        }
+        if !self.warmed_up.load(Ordering::SeqCst) {
+            return Err(RepositoryError::Storage(String::from("the cache is warming up")));
+        }
+        self.inner.ready().await
+    }
+}
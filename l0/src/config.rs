@@ -0,0 +1,400 @@
+use std::{fmt, net::ToSocketAddrs, path::Path, str::FromStr};
+use serde::Deserialize;
+
+use crate::auth::AuthConfig;
+use crate::cache::CacheConfig;
+use crate::db::DEFAULT_POOL_SIZE;
+use crate::events::EventsConfig;
+use crate::limits::{RateLimitConfig, MIN_PER_SECOND};
+use crate::nats::NatsConfig;
+use crate::webhooks::WebhooksConfig;
+
+// Read when L0_CONFIG is not set; a missing default file is not an error.
+pub const DEFAULT_CONFIG_PATH: &str = "l0.toml";
+
+// Settings of the server. Every field has a default, the TOML file and then
+// the L0_* environment variables override them.
+#[derive(Deserialize, Clone, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub database: DatabaseConfig,
+    pub cache: CacheConfig,
+    pub events: EventsConfig,
+    pub auth: AuthConfig,
+    pub rate_limit: RateLimitConfig,
+    pub webhooks: WebhooksConfig,
+    pub log: LogConfig,
+    // ingestion from NATS is disabled without this section
+    pub nats: Option<NatsConfig>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub bind_address: String,
+    // maximum size of a request body in bytes
+    pub body_limit: usize,
+    // seconds in-flight requests get to finish after SIGINT or SIGTERM
+    pub shutdown_timeout: u64,
+    // maximum number of items of an order
+    pub max_items: usize,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig { bind_address: String::from("0.0.0.0:3000"), body_limit: 2 * 1024 * 1024, shutdown_timeout: 30, max_items: 1000 }
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseConfig {
+    pub backend: Backend,
+    // file of the sqlite backend
+    pub path: String,
+    // connection URL of the postgres backend
+    pub url: Option<String>,
+    pub pool_size: u32,
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        DatabaseConfig { backend: Backend::default(), path: String::from("database.db"), url: None, pool_size: DEFAULT_POOL_SIZE }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    #[default]
+    Sqlite,
+    // needs the postgres feature
+    Postgres,
+    // orders and keys are lost on restart, for tests and demos
+    Memory,
+}
+
+impl FromStr for Backend {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "sqlite" => Ok(Backend::Sqlite),
+            "postgres" => Ok(Backend::Postgres),
+            "memory" => Ok(Backend::Memory),
+            _ => Err(format!("Unknown storage backend: {}", value)),
+        }
+    }
+}
+
+impl fmt::Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let backend = match self {
+            Self::Sqlite => "sqlite",
+            Self::Postgres => "postgres",
+            Self::Memory => "memory",
+        };
+        write!(f, "{}", backend)
+    }
+}
+
+#[derive(Deserialize, Clone, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    pub level: LogLevel,
+    pub format: LogFormat,
+}
+
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    // human-readable lines
+    #[default]
+    Text,
+    // one JSON object per line
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("Unknown log format: {}", value)),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Error,
+    Warn,
+    #[default]
+    Info,
+    Debug,
+    Trace,
+}
+
+impl FromStr for LogLevel {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "error" => Ok(LogLevel::Error),
+            "warn" => Ok(LogLevel::Warn),
+            "info" => Ok(LogLevel::Info),
+            "debug" => Ok(LogLevel::Debug),
+            "trace" => Ok(LogLevel::Trace),
+            _ => Err(format!("Unknown log level: {}", value)),
+        }
+    }
+}
+
+impl fmt::Display for LogLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let level = match self {
+            Self::Error => "error",
+            Self::Warn => "warn",
+            Self::Info => "info",
+            Self::Debug => "debug",
+            Self::Trace => "trace",
+        };
+        write!(f, "{}", level)
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Read { path: String, err: String },
+    Parse { path: String, err: String },
+    // every invalid setting, not only the first one
+    Invalid(Vec<String>),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Read { path, err } => write!(f, "Failed to read the config file {}: {}", path, err),
+            Self::Parse { path, err } => write!(f, "Failed to parse the config file {}: {}", path, err),
+            Self::Invalid(errors) => {
+                write!(f, "Invalid configuration:")?;
+                for err in errors {
+                    write!(f, "\n  - {}", err)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Config {
+    // Loads the file named by L0_CONFIG (or l0.toml if it exists) and applies
+    // the process environment on top of it.
+    pub fn load() -> Result<Self, ConfigError> {
+        let path = std::env::var("L0_CONFIG").ok();
+        let (path, required) = match &path {
+            Some(path) => (path.as_str(), true),
+            None => (DEFAULT_CONFIG_PATH, false),
+        };
+
+        let file = if required || Path::new(path).exists() {
+            let contents = std::fs::read_to_string(path).map_err(|err| {
+                ConfigError::Read { path: path.to_string(), err: err.to_string() }
+            })?;
+            Some((path, contents))
+        } else {
+            None
+        };
+
+        Config::from_sources(file.as_ref().map(|(path, contents)| (*path, contents.as_str())), |name| {
+            std::env::var(name).ok()
+        })
+    }
+
+    // `file` is the path and contents of the TOML file, `env` looks up
+    // environment variables.
+    pub fn from_sources(file: Option<(&str, &str)>, env: impl Fn(&str) -> Option<String>) -> Result<Self, ConfigError> {
+        let mut config = match file {
+            Some((path, contents)) => toml::from_str(contents).map_err(|err| {
+                ConfigError::Parse { path: path.to_string(), err: err.to_string() }
+            })?,
+            None => Config::default(),
+        };
+
+        let mut errors = Vec::new();
+        config.apply_env(&env, &mut errors);
+        config.validate(&mut errors);
+
+        if errors.is_empty() {
+            Ok(config)
+        } else {
+            Err(ConfigError::Invalid(errors))
+        }
+    }
+
+    fn apply_env(&mut self, env: &impl Fn(&str) -> Option<String>, errors: &mut Vec<String>) {
+        fn parse<T: FromStr>(env: &impl Fn(&str) -> Option<String>, name: &str, expected: &str, errors: &mut Vec<String>) -> Option<T> {
+            let value = env(name)?;
+            match value.parse() {
+                Ok(value) => Some(value),
+                Err(_) => {
+                    errors.push(format!("{} must be {}, got {:?}", name, expected, value));
+                    None
+                }
+            }
+        }
+
+        if let Some(bind_address) = env("L0_BIND_ADDRESS") {
+            self.server.bind_address = bind_address;
+        }
+        if let Some(body_limit) = parse(env, "L0_BODY_LIMIT", "a number of bytes", errors) {
+            self.server.body_limit = body_limit;
+        }
+        if let Some(shutdown_timeout) = parse(env, "L0_SHUTDOWN_TIMEOUT", "a number of seconds", errors) {
+            self.server.shutdown_timeout = shutdown_timeout;
+        }
+        if let Some(max_items) = parse(env, "L0_MAX_ITEMS", "a number of items", errors) {
+            self.server.max_items = max_items;
+        }
+        if let Some(backend) = parse(env, "L0_DATABASE_BACKEND", "sqlite, postgres or memory", errors) {
+            self.database.backend = backend;
+        }
+        if let Some(path) = env("L0_DATABASE_PATH") {
+            self.database.path = path;
+        }
+        if let Some(url) = env("L0_DATABASE_URL") {
+            self.database.url = Some(url);
+        }
+        if let Some(pool_size) = parse(env, "L0_DB_POOL_SIZE", "a positive integer", errors) {
+            self.database.pool_size = pool_size;
+        }
+        if let Some(capacity) = parse(env, "L0_CACHE_CAPACITY", "a number of orders", errors) {
+            self.cache.capacity = capacity;
+        }
+        if let Some(policy) = parse(env, "L0_CACHE_POLICY", "lru or fifo", errors) {
+            self.cache.policy = policy;
+        }
+        if let Some(buffer) = parse(env, "L0_EVENTS_BUFFER", "a number of events", errors) {
+            self.events.buffer = buffer;
+        }
+        if let Some(enabled) = parse(env, "L0_AUTH_ENABLED", "true or false", errors) {
+            self.auth.enabled = enabled;
+        }
+        if let Some(burst) = parse(env, "L0_RATE_LIMIT_BURST", "a number of requests", errors) {
+            self.rate_limit.burst = burst;
+        }
+        if let Some(per_second) = parse(env, "L0_RATE_LIMIT_PER_SECOND", "a number of requests per second", errors) {
+            self.rate_limit.per_second = per_second;
+        }
+        if let Some(poll_interval_ms) = parse(env, "L0_WEBHOOKS_POLL_INTERVAL_MS", "a number of milliseconds", errors) {
+            self.webhooks.poll_interval_ms = poll_interval_ms;
+        }
+        if let Some(max_attempts) = parse(env, "L0_WEBHOOKS_MAX_ATTEMPTS", "a number of attempts", errors) {
+            self.webhooks.max_attempts = max_attempts;
+        }
+        if let Some(backoff_base_ms) = parse(env, "L0_WEBHOOKS_BACKOFF_BASE_MS", "a number of milliseconds", errors) {
+            self.webhooks.backoff_base_ms = backoff_base_ms;
+        }
+        if let Some(backoff_max_ms) = parse(env, "L0_WEBHOOKS_BACKOFF_MAX_MS", "a number of milliseconds", errors) {
+            self.webhooks.backoff_max_ms = backoff_max_ms;
+        }
+        if let Some(timeout_ms) = parse(env, "L0_WEBHOOKS_TIMEOUT_MS", "a number of milliseconds", errors) {
+            self.webhooks.timeout_ms = timeout_ms;
+        }
+        if let Some(retention_hours) = parse(env, "L0_WEBHOOKS_RETENTION_HOURS", "a number of hours", errors) {
+            self.webhooks.retention_hours = retention_hours;
+        }
+        if let Some(ca_file) = env("L0_WEBHOOKS_CA_FILE") {
+            self.webhooks.ca_file = Some(ca_file);
+        }
+        if let Some(level) = parse(env, "L0_LOG_LEVEL", "one of error, warn, info, debug, trace", errors) {
+            self.log.level = level;
+        }
+        if let Some(format) = parse(env, "L0_LOG_FORMAT", "text or json", errors) {
+            self.log.format = format;
+        }
+
+        if let Some(url) = env("L0_NATS_URL") {
+            self.nats.get_or_insert_with(NatsConfig::default).url = url;
+        }
+        if let Some(nats) = &mut self.nats {
+            if let Some(subject) = env("L0_NATS_SUBJECT") {
+                nats.subject = subject;
+            }
+            if let Some(queue_group) = env("L0_NATS_QUEUE_GROUP") {
+                nats.queue_group = Some(queue_group);
+            }
+            if let Some(dead_letter_subject) = env("L0_NATS_DEAD_LETTER_SUBJECT") {
+                nats.dead_letter_subject = dead_letter_subject;
+            }
+        }
+    }
+
+    fn validate(&self, errors: &mut Vec<String>) {
+        if self.server.bind_address.to_socket_addrs().map_or(true, |mut addrs| addrs.next().is_none()) {
+            errors.push(format!("server.bind_address must be a host:port address, got {:?}", self.server.bind_address));
+        }
+        if self.server.body_limit == 0 {
+            errors.push(String::from("server.body_limit must be greater than 0"));
+        }
+        if self.server.shutdown_timeout == 0 {
+            errors.push(String::from("server.shutdown_timeout must be greater than 0"));
+        }
+        if self.server.max_items == 0 {
+            errors.push(String::from("server.max_items must be greater than 0"));
+        }
+        match self.database.backend {
+            Backend::Sqlite if self.database.path.trim().is_empty() => {
+                errors.push(String::from("database.path must not be empty"));
+            }
+            Backend::Postgres if !cfg!(feature = "postgres") => {
+                errors.push(String::from("database.backend postgres needs l0 built with the postgres feature"));
+            }
+            Backend::Postgres if self.database.url.as_ref().is_none_or(|url| url.trim().is_empty()) => {
+                errors.push(String::from("database.url must be set for the postgres backend"));
+            }
+            _ => {}
+        }
+        if self.database.pool_size == 0 {
+            errors.push(String::from("database.pool_size must be greater than 0"));
+        }
+        if self.events.buffer == 0 {
+            errors.push(String::from("events.buffer must be greater than 0"));
+        }
+        if self.rate_limit.burst > 0 && !(self.rate_limit.per_second.is_finite() && self.rate_limit.per_second >= MIN_PER_SECOND) {
+            errors.push(format!("rate_limit.per_second must be at least {}, got {}", MIN_PER_SECOND, self.rate_limit.per_second));
+        }
+        let webhooks = &self.webhooks;
+        for (name, value) in [
+            ("webhooks.poll_interval_ms", webhooks.poll_interval_ms),
+            ("webhooks.max_attempts", u64::from(webhooks.max_attempts)),
+            ("webhooks.backoff_base_ms", webhooks.backoff_base_ms),
+            ("webhooks.timeout_ms", webhooks.timeout_ms),
+        ] {
+            if value == 0 {
+                errors.push(format!("{} must be greater than 0", name));
+            }
+        }
+        if webhooks.backoff_max_ms < webhooks.backoff_base_ms {
+            errors.push(format!("webhooks.backoff_max_ms must be at least webhooks.backoff_base_ms ({}), got {}", webhooks.backoff_base_ms, webhooks.backoff_max_ms));
+        }
+        if webhooks.ca_file.as_ref().is_some_and(|ca_file| ca_file.trim().is_empty()) {
+            errors.push(String::from("webhooks.ca_file must not be empty"));
+        }
+        if let Some(nats) = &self.nats {
+            if nats.url.trim().is_empty() {
+                errors.push(String::from("nats.url must not be empty"));
+            }
+            if nats.subject.trim().is_empty() {
+                errors.push(String::from("nats.subject must not be empty"));
+            }
+            if nats.dead_letter_subject.trim().is_empty() {
+                errors.push(String::from("nats.dead_letter_subject must not be empty"));
+            }
+        }
+    }
+}
@@ -0,0 +1,57 @@
+use std::time::{Duration, Instant};
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::Connection;
+
+use crate::repository::RepositoryError;
+
+pub type Pool = r2d2::Pool<SqliteConnectionManager>;
+
+pub const DEFAULT_POOL_SIZE: u32 = 8;
+
+// How long a connection waits for a lock held by another connection before
+// failing with SQLITE_BUSY.
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Opens a pool of `size` connections to the database file at `path`. The
+// database is switched to WAL mode, so readers do not wait for writers.
+pub fn open_pool(path: &str, size: u32) -> Result<Pool, r2d2::Error> {
+    let manager = SqliteConnectionManager::file(path).with_init(|connection| {
+        connection.pragma_update(None, "journal_mode", "WAL")?;
+        connection.pragma_update(None, "synchronous", "NORMAL")?;
+        connection.pragma_update(None, "foreign_keys", "ON")?;
+        connection.busy_timeout(BUSY_TIMEOUT)
+    });
+    r2d2::Pool::builder().max_size(size).build(manager)
+}
+
+// Moves the write-ahead log into the database file and truncates it, so a
+// stopped server leaves a self-contained database behind.
+pub fn checkpoint(pool: &Pool) -> Result<(), RepositoryError> {
+    let connection = pool.get()?;
+    let busy: i64 = connection.query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |row| row.get(0))?;
+    if busy != 0 {
+        return Err(RepositoryError::Storage(String::from("the database is busy, the checkpoint is incomplete")));
+    }
+    Ok(())
+}
+
+// Runs `f` with a pooled connection on the blocking thread pool, so slow
+// queries neither stall tokio workers nor wait for each other. The call is
+// traced as a `db` span inside the span of the request.
+pub async fn with_connection<T, F>(pool: &Pool, operation: &'static str, f: F) -> Result<T, RepositoryError>
+where
+    T: Send + 'static,
+    F: FnOnce(&mut Connection) -> Result<T, RepositoryError> + Send + 'static,
+{
+    let pool = pool.clone();
+    let span = tracing::debug_span!("db", operation);
+    tokio::task::spawn_blocking(move || {
+        let _entered = span.enter();
+        let started = Instant::now();
+        let result = pool.get().map_err(RepositoryError::from).and_then(|mut connection| f(&mut connection));
+        tracing::debug!(elapsed_ms = started.elapsed().as_secs_f64() * 1000.0, ok = result.is_ok(), "query finished");
+        result
+    })
+    .await
+    .map_err(|err| RepositoryError::Storage(err.to_string()))?
+}
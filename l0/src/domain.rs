@@ -0,0 +1,383 @@
+use std::{fmt, str::FromStr};
+use chrono::{DateTime, FixedOffset, Utc};
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSql, ToSqlOutput, ValueRef};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use utoipa::{openapi::{schema::{ObjectBuilder, Schema, SchemaFormat, KnownFormat, Type}, RefOr}, PartialSchema, ToSchema};
+
+// Typed fields of an order. Each one serializes to the same JSON as the
+// string or number it replaced, so clients see no difference.
+
+// An RFC 3339 timestamp such as date_created. It keeps the text it was sent
+// with, so it is returned and stored unchanged, and two timestamps are equal
+// when both the instant and the offset are. Ranges and sorting of orders go
+// by `utc()`.
+#[derive(Clone, Debug)]
+pub struct Timestamp {
+    time: DateTime<FixedOffset>,
+    text: String,
+}
+
+impl Timestamp {
+    pub fn utc(&self) -> DateTime<Utc> {
+        self.time.with_timezone(&Utc)
+    }
+
+    // The instant in UTC with nanoseconds, text that sorts like the instants.
+    // SQLite keeps it in orders.created_utc.
+    pub fn sort_key(&self) -> String {
+        self.utc().format("%Y-%m-%dT%H:%M:%S%.9fZ").to_string()
+    }
+
+    fn offset_seconds(&self) -> i32 {
+        self.time.offset().local_minus_utc()
+    }
+}
+
+impl PartialEq for Timestamp {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other).is_eq()
+    }
+}
+
+impl Eq for Timestamp {}
+
+impl PartialOrd for Timestamp {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+// DateTime compares the instants only
+impl Ord for Timestamp {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.time.cmp(&other.time).then_with(|| self.offset_seconds().cmp(&other.offset_seconds()))
+    }
+}
+
+impl AsRef<DateTime<FixedOffset>> for Timestamp {
+    fn as_ref(&self) -> &DateTime<FixedOffset> {
+        &self.time
+    }
+}
+
+impl FromStr for Timestamp {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        DateTime::parse_from_rfc3339(value)
+            .map(|time| Timestamp { time, text: value.to_owned() })
+            .map_err(|_| format!("{:?} is not an RFC 3339 timestamp, e.g. 2021-11-26T06:22:19Z", value))
+    }
+}
+
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+// A point in time sent as whole unix seconds, such as payment_dt.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct UnixTime(DateTime<Utc>);
+
+impl UnixTime {
+    pub fn from_seconds(seconds: i64) -> Option<Self> {
+        DateTime::from_timestamp(seconds, 0).map(UnixTime)
+    }
+
+    pub fn seconds(&self) -> i64 {
+        self.0.timestamp()
+    }
+
+    pub fn utc(&self) -> DateTime<Utc> {
+        self.0
+    }
+}
+
+// An amount of money in the minor units of its currency, e.g. 1817 is
+// 18.17 USD but 1817 JPY. It is made by `Currency::money` only and
+// serializes to the bare number of minor units, the currency is sent once
+// in payment.currency. Amounts in different currencies are never added up.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Money {
+    minor: u32,
+    currency: Currency,
+}
+
+impl Money {
+    pub fn checked_add(self, other: Money) -> Option<Money> {
+        if self.currency != other.currency {
+            return None;
+        }
+        self.minor.checked_add(other.minor).map(|minor| Money { minor, ..self })
+    }
+
+    // In major units with the code, e.g. 18.17 USD.
+    pub fn format(&self) -> String {
+        let exponent = self.currency.exponent();
+        if exponent == 0 {
+            return format!("{} {}", self.minor, self.currency);
+        }
+        let scale = 10u32.pow(exponent);
+        format!("{}.{:0width$} {}", self.minor / scale, self.minor % scale, self.currency, width = exponent as usize)
+    }
+}
+
+impl From<Money> for u64 {
+    fn from(money: Money) -> Self {
+        u64::from(money.minor)
+    }
+}
+
+// The bare number of minor units, as in the JSON.
+impl fmt::Display for Money {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.minor)
+    }
+}
+
+impl Serialize for Money {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u32(self.minor)
+    }
+}
+
+// A three letter ISO 4217 code, e.g. USD. Every Money of a payment and of
+// the items of its order is in this currency.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Currency([u8; 3]);
+
+impl Currency {
+    pub fn as_str(&self) -> &str {
+        // only ASCII letters get in
+        std::str::from_utf8(&self.0).unwrap_or_default()
+    }
+
+    // Digits after the decimal point of an amount, the minor unit of ISO 4217.
+    // Codes without one, such as XAU, count in hundredths like most.
+    pub fn exponent(&self) -> u32 {
+        match self.as_str() {
+            "BIF" | "CLP" | "DJF" | "GNF" | "ISK" | "JPY" | "KMF" | "KRW" | "PYG" | "RWF" | "UGX" | "UYI" | "VND" | "VUV"
+            | "XAF" | "XOF" | "XPF" => 0,
+            "BHD" | "IQD" | "JOD" | "KWD" | "LYD" | "OMR" | "TND" => 3,
+            "CLF" | "UYW" => 4,
+            _ => 2,
+        }
+    }
+
+    // `minor` units of this currency, the only way to make Money.
+    pub fn money(self, minor: u32) -> Money {
+        Money { minor, currency: self }
+    }
+}
+
+impl FromStr for Currency {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.as_bytes() {
+            &[a, b, c] if [a, b, c].iter().all(u8::is_ascii_uppercase) => Ok(Currency([a, b, c])),
+            _ => Err(format!("{:?} is not a three letter ISO 4217 code, e.g. USD", value)),
+        }
+    }
+}
+
+impl fmt::Display for Currency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+// Languages of the storefronts orders come from, ISO 639-1.
+#[derive(Serialize, Deserialize, ToSchema, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Locale {
+    Ru,
+    En,
+    Be,
+    Kk,
+    Ky,
+    Hy,
+    Uz,
+}
+
+impl Locale {
+    pub const ALL: [Locale; 7] = [Locale::Ru, Locale::En, Locale::Be, Locale::Kk, Locale::Ky, Locale::Hy, Locale::Uz];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Locale::Ru => "ru",
+            Locale::En => "en",
+            Locale::Be => "be",
+            Locale::Kk => "kk",
+            Locale::Ky => "ky",
+            Locale::Hy => "hy",
+            Locale::Uz => "uz",
+        }
+    }
+}
+
+impl FromStr for Locale {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        Locale::ALL.into_iter()
+            .find(|locale| locale.as_str() == value)
+            .ok_or_else(|| format!("{:?} is not a supported locale, one of ru, en, be, kk, ky, hy, uz", value))
+    }
+}
+
+impl fmt::Display for Locale {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+// serde
+impl Serialize for Timestamp {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Timestamp {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        value.parse().map_err(de::Error::custom)
+    }
+}
+
+impl Serialize for UnixTime {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_i64(self.seconds())
+    }
+}
+
+impl<'de> Deserialize<'de> for UnixTime {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let seconds = i64::deserialize(deserializer)?;
+        UnixTime::from_seconds(seconds)
+            .ok_or_else(|| de::Error::custom(format!("{} is out of the range of unix timestamps", seconds)))
+    }
+}
+
+impl Serialize for Currency {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for Currency {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        value.parse().map_err(de::Error::custom)
+    }
+}
+
+// OpenAPI
+impl PartialSchema for Timestamp {
+    fn schema() -> RefOr<Schema> {
+        ObjectBuilder::new()
+            .schema_type(Type::String)
+            .format(Some(SchemaFormat::KnownFormat(KnownFormat::DateTime)))
+            .examples(["2021-11-26T06:22:19Z"])
+            .into()
+    }
+}
+
+impl ToSchema for Timestamp {}
+
+impl PartialSchema for UnixTime {
+    fn schema() -> RefOr<Schema> {
+        ObjectBuilder::new()
+            .schema_type(Type::Integer)
+            .format(Some(SchemaFormat::KnownFormat(KnownFormat::Int64)))
+            .description(Some("Unix time in seconds"))
+            .into()
+    }
+}
+
+impl ToSchema for UnixTime {}
+
+impl PartialSchema for Money {
+    fn schema() -> RefOr<Schema> {
+        ObjectBuilder::new()
+            .schema_type(Type::Integer)
+            .format(Some(SchemaFormat::KnownFormat(KnownFormat::Int64)))
+            .minimum(Some(0))
+            .maximum(Some(u32::MAX))
+            .description(Some("Minor units of payment.currency, e.g. 1817 is 18.17 USD"))
+            .into()
+    }
+}
+
+impl ToSchema for Money {}
+
+impl PartialSchema for Currency {
+    fn schema() -> RefOr<Schema> {
+        ObjectBuilder::new()
+            .schema_type(Type::String)
+            .pattern(Some("^[A-Z]{3}$"))
+            .examples(["USD"])
+            .into()
+    }
+}
+
+impl ToSchema for Currency {}
+
+// SQLite columns: timestamps and codes are text, the rest integers.
+impl ToSql for Timestamp {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.to_string()))
+    }
+}
+
+impl FromSql for Timestamp {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        value.as_str()?.parse().map_err(|err: String| FromSqlError::Other(err.into()))
+    }
+}
+
+impl ToSql for UnixTime {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.seconds()))
+    }
+}
+
+impl FromSql for UnixTime {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        let seconds = value.as_i64()?;
+        UnixTime::from_seconds(seconds).ok_or(FromSqlError::OutOfRange(seconds))
+    }
+}
+
+// Money is read back through `Currency::money` with the currency of its payment.
+impl ToSql for Money {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.minor))
+    }
+}
+
+impl ToSql for Currency {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.as_str()))
+    }
+}
+
+impl FromSql for Currency {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        value.as_str()?.parse().map_err(|err: String| FromSqlError::Other(err.into()))
+    }
+}
+
+impl ToSql for Locale {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.as_str()))
+    }
+}
+
+impl FromSql for Locale {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        value.as_str()?.parse().map_err(|err: String| FromSqlError::Other(err.into()))
+    }
+}
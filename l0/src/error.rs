@@ -0,0 +1,196 @@
+use std::time::Duration;
+use axum::{extract::{rejection::{JsonRejection, QueryRejection}, ws::rejection::WebSocketUpgradeRejection}, http::{header, HeaderValue, StatusCode}, response::{IntoResponse, Response}, Json};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use utoipa::ToSchema;
+
+use crate::auth::Scope;
+use crate::repository::RepositoryError;
+use crate::validation::{FieldError, InvalidOrder};
+
+// What a request body holds, named by the messages of InvalidJson and Validation.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Resource {
+    Order,
+    OrderPatch,
+    ApiKey,
+    Webhook,
+}
+
+impl Resource {
+    pub fn name(self) -> &'static str {
+        match self {
+            Resource::Order => "order",
+            Resource::OrderPatch => "order patch",
+            Resource::ApiKey => "API key",
+            Resource::Webhook => "webhook",
+        }
+    }
+}
+
+pub enum AppError {
+    // the request body is not the JSON of the resource
+    InvalidJson(Resource, JsonRejection),
+    // the request body is JSON, but not of an order, e.g. a field is missing
+    NotAnOrder(String),
+    // the query string is malformed or out of range
+    InvalidQuery(String),
+    // the request body could not be read
+    InvalidBody(String),
+    // a WebSocket route was requested without a valid upgrade
+    NotWebSocket(WebSocketUpgradeRejection),
+    // no API key, or an unknown or revoked one
+    Unauthorized,
+    // the API key lacks the scope
+    Forbidden(Scope),
+    // the client has sent too many requests, it may retry after the duration
+    RateLimited(Duration),
+    OrderNotFound(String),
+    OrderAlreadyExists(String),
+    KeyNotFound(String),
+    WebhookNotFound(String),
+    DeliveryNotFound(String),
+    Validation(Resource, Vec<FieldError>),
+    Storage(String),
+    // GET /readyz while the service cannot serve requests
+    NotReady(String),
+}
+
+// Body of every error response. `code` is stable and meant for clients to
+// match on, `message` is for humans.
+#[derive(Serialize, Deserialize, ToSchema, PartialEq, Debug)]
+pub struct Problem {
+    pub code: String,
+    pub message: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<Value>,
+}
+
+impl AppError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Self::InvalidJson(_, rejection) => rejection.status(),
+            Self::NotAnOrder(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            Self::InvalidQuery(_) => StatusCode::BAD_REQUEST,
+            Self::InvalidBody(_) => StatusCode::BAD_REQUEST,
+            Self::NotWebSocket(rejection) => rejection.status(),
+            Self::Unauthorized => StatusCode::UNAUTHORIZED,
+            Self::Forbidden(_) => StatusCode::FORBIDDEN,
+            Self::RateLimited(_) => StatusCode::TOO_MANY_REQUESTS,
+            Self::OrderNotFound(_) => StatusCode::NOT_FOUND,
+            Self::OrderAlreadyExists(_) => StatusCode::CONFLICT,
+            Self::KeyNotFound(_) => StatusCode::NOT_FOUND,
+            Self::WebhookNotFound(_) => StatusCode::NOT_FOUND,
+            Self::DeliveryNotFound(_) => StatusCode::NOT_FOUND,
+            Self::Validation(..) => StatusCode::UNPROCESSABLE_ENTITY,
+            Self::Storage(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::NotReady(_) => StatusCode::SERVICE_UNAVAILABLE,
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::InvalidJson(..) | Self::NotAnOrder(_) => "invalid_json",
+            Self::InvalidQuery(_) => "invalid_query",
+            Self::InvalidBody(_) => "invalid_body",
+            Self::NotWebSocket(_) => "websocket_required",
+            Self::Unauthorized => "unauthorized",
+            Self::Forbidden(_) => "forbidden",
+            Self::RateLimited(_) => "rate_limited",
+            Self::OrderNotFound(_) => "order_not_found",
+            Self::OrderAlreadyExists(_) => "order_already_exists",
+            Self::KeyNotFound(_) => "api_key_not_found",
+            Self::WebhookNotFound(_) => "webhook_not_found",
+            Self::DeliveryNotFound(_) => "webhook_delivery_not_found",
+            Self::Validation(..) => "validation_failed",
+            Self::Storage(_) => "storage_error",
+            Self::NotReady(_) => "not_ready",
+        }
+    }
+
+    pub fn problem(self) -> Problem {
+        let code = self.code().to_string();
+        let (message, detail) = match self {
+            Self::InvalidJson(resource, rejection) => (format!("The request body is not a valid {}", resource.name()), Some(Value::String(rejection.body_text()))),
+            Self::NotAnOrder(err) => (String::from("The request body is not a valid order"), Some(Value::String(err))),
+            Self::InvalidQuery(err) => (String::from("The query string is not valid"), Some(Value::String(err))),
+            Self::InvalidBody(err) => (String::from("The request body could not be read"), Some(Value::String(err))),
+            Self::NotWebSocket(rejection) => (String::from("The route only accepts WebSocket connections"), Some(Value::String(rejection.body_text()))),
+            Self::Unauthorized => (String::from("A valid API key is required, send it as Authorization: Bearer <key> or as the password of HTTP Basic"), None),
+            Self::Forbidden(scope) => (format!("The API key does not have the {} scope", scope), None),
+            Self::RateLimited(retry_after) => (format!("Too many requests, retry in {} seconds", retry_after_secs(retry_after)), None),
+            Self::OrderNotFound(order_uid) => (format!("Order with order_uid={} does not exist", order_uid), None),
+            Self::OrderAlreadyExists(order_uid) => (format!("Order with order_uid={} already exists", order_uid), None),
+            Self::KeyNotFound(id) => (format!("API key with id={} does not exist", id), None),
+            Self::WebhookNotFound(id) => (format!("Webhook with id={} does not exist", id), None),
+            Self::DeliveryNotFound(id) => (format!("Webhook delivery with id={} does not exist or its webhook has been deleted", id), None),
+            Self::Validation(resource, errors) => (format!("The {} violates validation rules", resource.name()), serde_json::to_value(errors).ok()),
+            Self::Storage(err) => {
+                tracing::error!(%err, "Storage failure");
+                (String::from("The order storage failed to process the request"), None)
+            }
+            Self::NotReady(reason) => (String::from("The service is not ready to serve requests"), Some(Value::String(reason))),
+        };
+        Problem { code, message, detail }
+    }
+}
+
+// Retry-After is in whole seconds, rounded up so a retry is not rejected again.
+fn retry_after_secs(retry_after: Duration) -> u64 {
+    retry_after.as_secs_f64().ceil().max(1.0) as u64
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        let status = self.status();
+        let retry_after = match self {
+            Self::RateLimited(retry_after) => Some(retry_after_secs(retry_after)),
+            _ => None,
+        };
+        let problem = self.problem();
+        if !status.is_server_error() {
+            tracing::info!(code = %problem.code, reason = %problem.message, "request rejected");
+        }
+        let mut response = (status, Json(problem)).into_response();
+        if status == StatusCode::UNAUTHORIZED {
+            response.headers_mut().insert(header::WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+            // makes the browser ask for the key on /ui
+            response.headers_mut().append(header::WWW_AUTHENTICATE, HeaderValue::from_static(r#"Basic realm="l0", charset="UTF-8""#));
+        }
+        if let Some(retry_after) = retry_after {
+            response.headers_mut().insert(header::RETRY_AFTER, HeaderValue::from(retry_after));
+        }
+        response
+    }
+}
+
+impl From<RepositoryError> for AppError {
+    fn from(err: RepositoryError) -> Self {
+        match err {
+            RepositoryError::AlreadyExists(order_uid) => AppError::OrderAlreadyExists(order_uid),
+            RepositoryError::NotFound(order_uid) => AppError::OrderNotFound(order_uid),
+            RepositoryError::Storage(err) => AppError::Storage(err),
+        }
+    }
+}
+
+impl From<InvalidOrder> for AppError {
+    fn from(err: InvalidOrder) -> Self {
+        match err {
+            InvalidOrder::Json(err) => AppError::NotAnOrder(err),
+            InvalidOrder::Rules(errors) => AppError::Validation(Resource::Order, errors),
+        }
+    }
+}
+
+impl From<QueryRejection> for AppError {
+    fn from(rejection: QueryRejection) -> Self {
+        AppError::InvalidQuery(rejection.body_text())
+    }
+}
+
+impl From<WebSocketUpgradeRejection> for AppError {
+    fn from(rejection: WebSocketUpgradeRejection) -> Self {
+        AppError::NotWebSocket(rejection)
+    }
+}
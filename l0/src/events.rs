@@ -0,0 +1,280 @@
+use std::{collections::HashMap, convert::Infallible, sync::{Arc, Mutex}};
+use async_trait::async_trait;
+use axum::{extract::{rejection::QueryRejection, ws::{rejection::WebSocketUpgradeRejection, Message, WebSocket, WebSocketUpgrade}, Query}, response::{sse::{Event, KeepAlive, Sse}, IntoResponse, Response}, Extension};
+use futures_util::{stream, Stream};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::Order;
+use crate::auth::Access;
+use crate::error::{AppError, Problem};
+use crate::query::{OrderFilter, OrderPage};
+use crate::repository::{Inserted, OrderRepository, RepositoryError};
+use crate::stats::{BrandTotal, DailyOrders, DateRange, Revenue};
+
+#[derive(Deserialize, Clone, Copy, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct EventsConfig {
+    // events a subscriber may fall behind by before it is disconnected
+    pub buffer: usize,
+}
+
+impl Default for EventsConfig {
+    fn default() -> Self {
+        EventsConfig { buffer: 64 }
+    }
+}
+
+#[derive(Serialize, Deserialize, ToSchema, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum OrderEventKind {
+    Created,
+    Updated,
+}
+
+impl OrderEventKind {
+    fn name(self) -> &'static str {
+        match self {
+            OrderEventKind::Created => "created",
+            OrderEventKind::Updated => "updated",
+        }
+    }
+}
+
+// A WebSocket message, SSE sends the kind as the event name and the order as data.
+#[derive(Serialize, Deserialize, ToSchema, Clone, PartialEq, Debug)]
+pub struct OrderEvent {
+    pub event: OrderEventKind,
+    pub order: Order,
+}
+
+// Query string of the streams, a subscriber gets only the orders that match.
+#[derive(Deserialize, IntoParams, Default, Clone, Debug)]
+#[into_params(parameter_in = Query)]
+pub struct EventFilter {
+    pub customer_id: Option<String>,
+    pub delivery_service: Option<String>,
+}
+
+impl EventFilter {
+    fn matches(&self, order: &Order) -> bool {
+        self.customer_id.as_ref().is_none_or(|customer_id| *customer_id == order.customer_id)
+            && self.delivery_service.as_ref().is_none_or(|delivery_service| *delivery_service == order.delivery_service)
+    }
+}
+
+struct Subscriber {
+    filter: EventFilter,
+    sender: mpsc::Sender<Arc<OrderEvent>>,
+}
+
+#[derive(Default)]
+struct Subscribers {
+    next_id: u64,
+    subscribers: HashMap<u64, Subscriber>,
+    closed: bool,
+}
+
+// Hands order events to subscribers. Each subscriber has a buffer of
+// `config.buffer` events, publishing never waits: a subscriber whose buffer
+// is full is disconnected and has to subscribe again.
+#[derive(Clone)]
+pub struct OrderEvents {
+    config: EventsConfig,
+    state: Arc<Mutex<Subscribers>>,
+}
+
+impl OrderEvents {
+    pub fn new(config: EventsConfig) -> Self {
+        OrderEvents { config, state: Arc::new(Mutex::new(Subscribers::default())) }
+    }
+
+    // The receiver ends when the subscriber is disconnected or the events are closed.
+    pub fn subscribe(&self, filter: EventFilter) -> mpsc::Receiver<Arc<OrderEvent>> {
+        let (sender, receiver) = mpsc::channel(self.config.buffer);
+        if let Ok(mut state) = self.state.lock() {
+            if !state.closed {
+                // streams that ended since the last event, publish may be rare
+                state.subscribers.retain(|_, subscriber| !subscriber.sender.is_closed());
+                state.next_id += 1;
+                let id = state.next_id;
+                state.subscribers.insert(id, Subscriber { filter, sender });
+            }
+        }
+        receiver
+    }
+
+    pub fn publish(&self, event: OrderEventKind, order: &Order) {
+        let Ok(mut state) = self.state.lock() else {
+            return;
+        };
+        if !state.subscribers.values().any(|subscriber| subscriber.filter.matches(order)) {
+            return;
+        }
+
+        let event = Arc::new(OrderEvent { event, order: order.clone() });
+        state.subscribers.retain(|id, subscriber| {
+            if !subscriber.filter.matches(&event.order) {
+                return true;
+            }
+            match subscriber.sender.try_send(Arc::clone(&event)) {
+                Ok(()) => true,
+                Err(mpsc::error::TrySendError::Full(_)) => {
+                    tracing::warn!(subscriber = id, buffer = self.config.buffer, "Disconnecting a slow event subscriber");
+                    false
+                }
+                Err(mpsc::error::TrySendError::Closed(_)) => false,
+            }
+        });
+    }
+
+    // Ends every stream, called on shutdown so open streams do not hold it up.
+    pub fn close(&self) {
+        if let Ok(mut state) = self.state.lock() {
+            state.closed = true;
+            state.subscribers.clear();
+        }
+    }
+
+    pub fn subscribers(&self) -> usize {
+        self.state.lock().map(|state| state.subscribers.len()).unwrap_or(0)
+    }
+}
+
+// Publishes an event for every order the wrapped repository creates or updates.
+pub struct PublishingOrderRepository {
+    inner: Arc<dyn OrderRepository>,
+    events: OrderEvents,
+}
+
+impl PublishingOrderRepository {
+    pub fn new(inner: Arc<dyn OrderRepository>, events: OrderEvents) -> Self {
+        PublishingOrderRepository { inner, events }
+    }
+}
+
+#[async_trait]
+impl OrderRepository for PublishingOrderRepository {
+    async fn list(&self) -> Result<Vec<Order>, RepositoryError> {
+        self.inner.list().await
+    }
+
+    async fn get(&self, order_uid: &str) -> Result<Option<Order>, RepositoryError> {
+        self.inner.get(order_uid).await
+    }
+
+    async fn page(&self, filter: &OrderFilter) -> Result<OrderPage, RepositoryError> {
+        self.inner.page(filter).await
+    }
+
+    async fn insert(&self, order: &Order) -> Result<Inserted, RepositoryError> {
+        let inserted = self.inner.insert(order).await?;
+        if inserted == Inserted::Created {
+            self.events.publish(OrderEventKind::Created, order);
+        }
+        Ok(inserted)
+    }
+
+    async fn insert_batch(&self, orders: &[Order]) -> Result<Vec<Result<Inserted, RepositoryError>>, RepositoryError> {
+        let results = self.inner.insert_batch(orders).await?;
+        for (order, result) in orders.iter().zip(&results) {
+            if matches!(result, Ok(Inserted::Created)) {
+                self.events.publish(OrderEventKind::Created, order);
+            }
+        }
+        Ok(results)
+    }
+
+    async fn update(&self, order: &Order) -> Result<(), RepositoryError> {
+        self.inner.update(order).await?;
+        self.events.publish(OrderEventKind::Updated, order);
+        Ok(())
+    }
+
+    async fn delete(&self, order_uid: &str) -> Result<(), RepositoryError> {
+        self.inner.delete(order_uid).await
+    }
+
+    async fn count(&self) -> Result<u64, RepositoryError> {
+        self.inner.count().await
+    }
+
+    async fn revenue(&self, range: &DateRange) -> Result<Vec<Revenue>, RepositoryError> {
+        self.inner.revenue(range).await
+    }
+
+    async fn top_brands(&self, range: &DateRange, limit: usize) -> Result<Vec<BrandTotal>, RepositoryError> {
+        self.inner.top_brands(range, limit).await
+    }
+
+    async fn daily_orders(&self, range: &DateRange) -> Result<Vec<DailyOrders>, RepositoryError> {
+        self.inner.daily_orders(range).await
+    }
+
+    async fn ready(&self) -> Result<(), RepositoryError> {
+        self.inner.ready().await
+    }
+}
+
+// handlers
+#[utoipa::path(get, path = "/orders/events", tag = "orders", params(EventFilter),
+    responses(
+        (status = 200, description = "Server-Sent Events named `created` or `updated` with the order as data", content_type = "text/event-stream"),
+        (status = 400, body = Problem),
+    ))]
+pub async fn order_events(Extension(events): Extension<OrderEvents>, Extension(access): Extension<Access>, query: Result<Query<EventFilter>, QueryRejection>) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, AppError> {
+    let Query(filter) = query?;
+    access.check_customer_filter(filter.customer_id.as_ref())?;
+    let receiver = events.subscribe(filter);
+    let stream = stream::unfold(receiver, move |mut receiver| async move {
+        let event = receiver.recv().await?;
+        let mut order = event.order.clone();
+        access.redact(&mut order);
+        let data = Event::default().event(event.event.name()).json_data(&order).unwrap_or_default();
+        Some((Ok(data), receiver))
+    });
+    Ok(Sse::new(stream).keep_alive(KeepAlive::default()))
+}
+
+#[utoipa::path(get, path = "/orders/ws", tag = "orders", params(EventFilter),
+    responses(
+        (status = 101, description = "WebSocket with an OrderEvent JSON text message per event"),
+        (status = 400, body = Problem),
+        (status = 405, body = Problem),
+        (status = 426, body = Problem),
+    ))]
+pub async fn order_socket(upgrade: Result<WebSocketUpgrade, WebSocketUpgradeRejection>, Extension(events): Extension<OrderEvents>, Extension(access): Extension<Access>, query: Result<Query<EventFilter>, QueryRejection>) -> Result<Response, AppError> {
+    let Query(filter) = query?;
+    access.check_customer_filter(filter.customer_id.as_ref())?;
+    let upgrade = upgrade?;
+    // subscribe before the upgrade so no event is missed
+    let receiver = events.subscribe(filter);
+    Ok(upgrade.on_upgrade(move |socket| forward_events(socket, receiver, access)).into_response())
+}
+
+async fn forward_events(mut socket: WebSocket, mut receiver: mpsc::Receiver<Arc<OrderEvent>>, access: Access) {
+    loop {
+        tokio::select! {
+            event = receiver.recv() => {
+                let Some(event) = event else {
+                    socket.send(Message::Close(None)).await.ok();
+                    return;
+                };
+                let mut event = OrderEvent::clone(&event);
+                access.redact(&mut event.order);
+                let Ok(text) = serde_json::to_string(&event) else {
+                    continue;
+                };
+                if socket.send(Message::Text(text)).await.is_err() {
+                    return;
+                }
+            }
+            // messages from the client are ignored
+            message = socket.recv() => match message {
+                Some(Ok(Message::Close(_)) | Err(_)) | None => return,
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+}
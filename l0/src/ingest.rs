@@ -0,0 +1,209 @@
+use std::{fmt, sync::Arc, time::Duration};
+use async_trait::async_trait;
+use tracing::Instrument;
+
+use crate::{limits, Order};
+use crate::nats::{NatsConfig, NatsSource};
+use crate::repository::{OrderRepository, RepositoryError};
+use crate::validation::{self, InvalidOrder};
+
+pub struct Message {
+    pub payload: Vec<u8>,
+    // broker specific handle used to acknowledge the message, e.g. a NATS reply subject
+    pub ack_token: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct SourceError(pub String);
+
+impl fmt::Display for SourceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl From<std::io::Error> for SourceError {
+    fn from(err: std::io::Error) -> Self {
+        SourceError(err.to_string())
+    }
+}
+
+// A subscription that delivers order JSON messages.
+#[async_trait]
+pub trait OrderSource: Send {
+    // Waits for the next message, `None` once the subscription is closed.
+    async fn next(&mut self) -> Result<Option<Message>, SourceError>;
+    // The order has been stored, the message must not be redelivered.
+    async fn ack(&mut self, message: &Message) -> Result<(), SourceError>;
+    // The order could not be stored right now, the message should be redelivered.
+    async fn nack(&mut self, message: &Message) -> Result<(), SourceError>;
+    // The message can never be stored and is moved aside for inspection.
+    async fn dead_letter(&mut self, message: &Message, reason: &str) -> Result<(), SourceError>;
+}
+
+// Feeds messages from `source` into the same path as POST /orders until the
+// subscription is closed. Orders with more than `max_items` items are
+// dead-lettered like POST /orders rejects them.
+pub async fn run(source: &mut dyn OrderSource, repository: &Arc<dyn OrderRepository>, max_items: usize) -> Result<(), SourceError> {
+    while let Some(message) = source.next().await? {
+        let parsed = validation::parse_order(&message.payload).and_then(|order| {
+            limits::check_item_count(&order, max_items).map_err(InvalidOrder::Rules)?;
+            Ok(order)
+        });
+        let order: Order = match parsed {
+            Ok(order) => order,
+            Err(InvalidOrder::Json(err)) => {
+                tracing::warn!(%err, "Dead-lettering a message that is not an order");
+                source.dead_letter(&message, &err).await?;
+                continue;
+            }
+            Err(InvalidOrder::Rules(errors)) => {
+                let reason = errors.iter()
+                    .map(|error| format!("{}: {}", error.field, error.message))
+                    .collect::<Vec<String>>()
+                    .join("; ");
+                tracing::warn!(%reason, "Dead-lettering an invalid order");
+                source.dead_letter(&message, &reason).await?;
+                continue;
+            }
+        };
+
+        let span = tracing::info_span!("message", order_uid = %order.order_uid);
+        match repository.insert(&order).instrument(span.clone()).await {
+            // includes a redelivered message that has already been stored
+            Ok(_) => {
+                span.in_scope(|| tracing::info!("Order ingested"));
+                source.ack(&message).await?
+            }
+            Err(RepositoryError::AlreadyExists(order_uid)) => {
+                let reason = format!("a different order with order_uid={} already exists", order_uid);
+                span.in_scope(|| tracing::warn!(%reason, "Dead-lettering a conflicting order"));
+                source.dead_letter(&message, &reason).await?;
+            }
+            Err(_) => {
+                span.in_scope(|| tracing::warn!("Order could not be stored, requesting redelivery"));
+                source.nack(&message).await?
+            }
+        }
+    }
+    Ok(())
+}
+
+// Consumes orders from NATS, reconnecting whenever the connection is lost.
+pub async fn run_nats(config: NatsConfig, repository: Arc<dyn OrderRepository>, max_items: usize) {
+    loop {
+        match NatsSource::connect(&config).await {
+            Ok(mut source) => {
+                tracing::info!(url = %config.url, subject = %config.subject, "Consuming orders");
+                if let Err(err) = run(&mut source, &repository, max_items).await {
+                    tracing::error!(%err, "Order ingestion failed");
+                }
+            }
+            Err(err) => tracing::error!(%err, url = %config.url, "Failed to connect to NATS"),
+        }
+        tokio::time::sleep(Duration::from_secs(5)).await;
+    }
+}
+
+// in-process broker
+#[cfg(test)]
+pub mod broker {
+    use std::{collections::VecDeque, sync::{Arc, Mutex}};
+    use async_trait::async_trait;
+    use tokio::sync::Notify;
+
+    use super::{Message, OrderSource, SourceError};
+
+    // Stand-in for a message broker: a queue that redelivers nacked messages and
+    // records what happened to every delivered one.
+    #[derive(Clone, Default)]
+    pub struct InMemoryBroker {
+        state: Arc<Mutex<BrokerState>>,
+        notify: Arc<Notify>,
+    }
+
+    #[derive(Default)]
+    struct BrokerState {
+        queue: VecDeque<Message>,
+        closed: bool,
+        next_id: u64,
+        acked: Vec<Vec<u8>>,
+        nacked: Vec<Vec<u8>>,
+        dead_letters: Vec<(Vec<u8>, String)>,
+    }
+
+    pub struct InMemorySource {
+        broker: InMemoryBroker,
+    }
+
+    impl InMemoryBroker {
+        pub fn new() -> Self {
+            InMemoryBroker::default()
+        }
+
+        pub fn publish(&self, payload: impl Into<Vec<u8>>) {
+            let mut state = self.state.lock().unwrap();
+            state.next_id += 1;
+            let ack_token = Some(state.next_id.to_string());
+            state.queue.push_back(Message { payload: payload.into(), ack_token });
+            self.notify.notify_one();
+        }
+
+        // Lets the subscriber drain the queue and then finish.
+        pub fn close(&self) {
+            self.state.lock().unwrap().closed = true;
+            self.notify.notify_one();
+        }
+
+        pub fn subscribe(&self) -> InMemorySource {
+            InMemorySource { broker: self.clone() }
+        }
+
+        pub fn acked(&self) -> Vec<Vec<u8>> {
+            self.state.lock().unwrap().acked.clone()
+        }
+
+        pub fn nacked(&self) -> Vec<Vec<u8>> {
+            self.state.lock().unwrap().nacked.clone()
+        }
+
+        pub fn dead_letters(&self) -> Vec<(Vec<u8>, String)> {
+            self.state.lock().unwrap().dead_letters.clone()
+        }
+    }
+
+    #[async_trait]
+    impl OrderSource for InMemorySource {
+        async fn next(&mut self) -> Result<Option<Message>, SourceError> {
+            loop {
+                {
+                    let mut state = self.broker.state.lock().unwrap();
+                    if let Some(message) = state.queue.pop_front() {
+                        return Ok(Some(message));
+                    }
+                    if state.closed {
+                        return Ok(None);
+                    }
+                }
+                self.broker.notify.notified().await;
+            }
+        }
+
+        async fn ack(&mut self, message: &Message) -> Result<(), SourceError> {
+            self.broker.state.lock().unwrap().acked.push(message.payload.clone());
+            Ok(())
+        }
+
+        async fn nack(&mut self, message: &Message) -> Result<(), SourceError> {
+            let mut state = self.broker.state.lock().unwrap();
+            state.nacked.push(message.payload.clone());
+            state.queue.push_back(Message { payload: message.payload.clone(), ack_token: message.ack_token.clone() });
+            Ok(())
+        }
+
+        async fn dead_letter(&mut self, message: &Message, reason: &str) -> Result<(), SourceError> {
+            self.broker.state.lock().unwrap().dead_letters.push((message.payload.clone(), reason.to_string()));
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,177 @@
+use std::{collections::HashMap, net::{IpAddr, SocketAddr}, sync::{Arc, Mutex, PoisonError}, time::{Duration, Instant}};
+use axum::{async_trait, extract::{ConnectInfo, FromRequest, Request, State}, http::StatusCode, middleware::Next, response::{IntoResponse, Response}, Json};
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::Order;
+use crate::auth::Access;
+use crate::config::ServerConfig;
+use crate::error::{AppError, Resource};
+use crate::validation::{self, FieldError};
+
+// Past this many clients, clients with a full bucket are forgotten, then the
+// ones seen longest ago until a tenth of the room is free again.
+pub const MAX_CLIENTS: usize = 10_000;
+// The slowest refill rate_limit.per_second may set, a request per 1000 seconds.
+pub const MIN_PER_SECOND: f64 = 0.001;
+
+#[derive(Deserialize, Clone, Copy, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimitConfig {
+    // requests a client may send at once, 0 disables rate limiting
+    pub burst: u32,
+    // requests per second the burst refills at
+    pub per_second: f64,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        RateLimitConfig { burst: 100, per_second: 20.0 }
+    }
+}
+
+// Requests are counted per API key, or per address when authentication is disabled.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Client {
+    Key(i64),
+    // `None` without connection info, e.g. in tests
+    Address(Option<IpAddr>),
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+// A token bucket per client: a request takes a token, the bucket holds
+// `config.burst` tokens and refills at `config.per_second`.
+#[derive(Clone)]
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    buckets: Arc<Mutex<HashMap<Client, Bucket>>>,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> Self {
+        RateLimiter { config, buckets: Arc::new(Mutex::new(HashMap::new())) }
+    }
+
+    // Takes a token of `client`, or tells how long it has to wait for one.
+    pub fn acquire(&self, client: Client, now: Instant) -> Result<(), Duration> {
+        // every bucket is valid on its own, a panic of another request
+        // must not switch the limit off
+        let mut buckets = self.buckets.lock().unwrap_or_else(PoisonError::into_inner);
+        let burst = f64::from(self.config.burst);
+        let refill = |bucket: &Bucket| self.refill(bucket, now);
+        if buckets.len() >= MAX_CLIENTS && !buckets.contains_key(&client) {
+            buckets.retain(|_, bucket| refill(bucket) < burst);
+            let excess = buckets.len().saturating_sub(MAX_CLIENTS - MAX_CLIENTS / 10);
+            if excess > 0 {
+                let mut seen: Vec<(Instant, Client)> = buckets.iter().map(|(client, bucket)| (bucket.updated, *client)).collect();
+                seen.select_nth_unstable_by_key(excess - 1, |(updated, _)| *updated);
+                for (_, client) in &seen[..excess] {
+                    buckets.remove(client);
+                }
+            }
+        }
+
+        let bucket = buckets.entry(client).or_insert(Bucket { tokens: burst, updated: now });
+        bucket.tokens = refill(bucket);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(self.wait(bucket.tokens))
+        }
+    }
+
+    // Like acquire, but leaves the token in the bucket.
+    pub fn check(&self, client: Client, now: Instant) -> Result<(), Duration> {
+        let buckets = self.buckets.lock().unwrap_or_else(PoisonError::into_inner);
+        let tokens = buckets.get(&client).map_or(f64::from(self.config.burst), |bucket| self.refill(bucket, now));
+        if tokens >= 1.0 {
+            Ok(())
+        } else {
+            Err(self.wait(tokens))
+        }
+    }
+
+    fn refill(&self, bucket: &Bucket, now: Instant) -> f64 {
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+        (bucket.tokens + elapsed * self.config.per_second).min(f64::from(self.config.burst))
+    }
+
+    // Time until the bucket holds a token again.
+    fn wait(&self, tokens: f64) -> Duration {
+        // saturates instead of panicking should a rate below MIN_PER_SECOND get here
+        Duration::try_from_secs_f64((1.0 - tokens) / self.config.per_second).unwrap_or(Duration::MAX)
+    }
+}
+
+// Layer of the routes behind `auth::authenticate`, so the API key is known.
+pub async fn limit_requests(State(limiter): State<RateLimiter>, request: Request, next: Next) -> Response {
+    if limiter.config.burst == 0 {
+        return next.run(request).await;
+    }
+    let client = match request.extensions().get::<Access>().and_then(|access| access.key_id) {
+        Some(id) => Client::Key(id),
+        None => address(&request),
+    };
+    if let Err(retry_after) = limiter.acquire(client, Instant::now()) {
+        return AppError::RateLimited(retry_after).into_response();
+    }
+    next.run(request).await
+}
+
+// Layer in front of `auth::authenticate`. A request that fails authentication
+// takes a token of its client address, so once an address has used up its
+// burst on bad keys its requests are refused before any key is looked up.
+pub async fn limit_unauthenticated(State(limiter): State<RateLimiter>, request: Request, next: Next) -> Response {
+    if limiter.config.burst == 0 {
+        return next.run(request).await;
+    }
+    let client = address(&request);
+    if let Err(retry_after) = limiter.check(client, Instant::now()) {
+        return AppError::RateLimited(retry_after).into_response();
+    }
+    let response = next.run(request).await;
+    if response.status() == StatusCode::UNAUTHORIZED {
+        limiter.acquire(client, Instant::now()).ok();
+    }
+    response
+}
+
+fn address(request: &Request) -> Client {
+    Client::Address(request.extensions().get::<ConnectInfo<SocketAddr>>().map(|ConnectInfo(address)| address.ip()))
+}
+
+pub fn check_item_count(order: &Order, max_items: usize) -> Result<(), Vec<FieldError>> {
+    if order.items.len() <= max_items {
+        return Ok(());
+    }
+    Err(vec![FieldError {
+        field: String::from("items"),
+        rule: "max_items",
+        message: format!("must contain at most {} items", max_items),
+    }])
+}
+
+// JSON order body of POST and PUT /orders, a valid order. The body is
+// limited to server.body_limit bytes and the order to server.max_items items.
+pub struct OrderJson(pub Order);
+
+#[async_trait]
+impl<S: Send + Sync> FromRequest<S> for OrderJson {
+    type Rejection = AppError;
+
+    async fn from_request(request: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let max_items = request.extensions().get::<ServerConfig>().map(|server| server.max_items);
+        let Json(value) = Json::<Value>::from_request(request, state).await.map_err(|rejection| AppError::InvalidJson(Resource::Order, rejection))?;
+        let order = validation::order_from_value(value)?;
+        if let Some(max_items) = max_items {
+            check_item_count(&order, max_items).map_err(|errors| AppError::Validation(Resource::Order, errors))?;
+        }
+        Ok(OrderJson(order))
+    }
+}
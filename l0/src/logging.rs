@@ -0,0 +1,69 @@
+use std::time::Duration;
+use axum::{body::Body, extract::MatchedPath, http::{Request, Response}};
+use tracing::{field, Span, Subscriber};
+use tracing_subscriber::{fmt::MakeWriter, EnvFilter};
+
+use crate::config::{LogConfig, LogFormat};
+
+// Builds the subscriber described by `config`. Only events of this crate are
+// logged at `config.level`, dependencies log warnings and errors.
+pub fn subscriber<W>(config: &LogConfig, writer: W) -> Box<dyn Subscriber + Send + Sync>
+where
+    W: for<'writer> MakeWriter<'writer> + Send + Sync + 'static,
+{
+    let filter = EnvFilter::new(format!("warn,l0={}", config.level));
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(writer);
+
+    match config.format {
+        LogFormat::Text => Box::new(builder.finish()),
+        // every event carries the fields of its spans, e.g. request_id
+        LogFormat::Json => Box::new(builder.json().with_current_span(true).with_span_list(true).finish()),
+    }
+}
+
+pub fn init(config: &LogConfig) {
+    if let Err(err) = tracing::subscriber::set_global_default(subscriber(config, std::io::stdout)) {
+        eprintln!("Failed to initialize logging: {}", err);
+    }
+}
+
+// Span of an HTTP request, every event logged while handling the request
+// belongs to it. Handlers fill in `order_uid`, authentication `api_key`.
+pub fn request_span(request: &Request<Body>) -> Span {
+    let request_id = request.headers().get("x-request-id")
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    let route = request.extensions().get::<MatchedPath>()
+        .map(MatchedPath::as_str)
+        .unwrap_or_default();
+
+    tracing::info_span!(
+        "request",
+        request_id,
+        method = %request.method(),
+        path = %request.uri().path(),
+        route,
+        order_uid = field::Empty,
+        api_key = field::Empty,
+    )
+}
+
+pub fn log_response(response: &Response<Body>, latency: Duration, _span: &Span) {
+    tracing::info!(
+        status = response.status().as_u16(),
+        latency_ms = latency.as_secs_f64() * 1000.0,
+        "request completed"
+    );
+}
+
+// Attaches `order_uid` to the request being handled.
+pub fn record_order_uid(order_uid: &str) {
+    Span::current().record("order_uid", order_uid);
+}
+
+// Attaches the id of the API key the request was authenticated with.
+pub fn record_api_key(id: i64) {
+    Span::current().record("api_key", id);
+}
@@ -1,184 +1,506 @@
-use std::{sync::{Arc, Mutex}, vec};
+use std::{future::Future, io, net::SocketAddr, sync::Arc, time::Duration, vec};
 use serde::{Deserialize, Serialize};
-use axum::{extract::{Json, Path}, response::IntoResponse, routing::{get, Router}, http::StatusCode, Extension};
-use rusqlite::{Connection, Row};
-use serde_json::json;
+use utoipa::ToSchema;
+use axum::{extract::{rejection::{JsonRejection, QueryRejection}, DefaultBodyLimit, Json, Path, Query}, http::{header, StatusCode}, middleware, response::IntoResponse, routing::{delete, get, post, Router}, Extension};
+use serde_json::{json, Value};
+use tokio::{net::TcpListener, sync::watch};
+use tower::ServiceBuilder;
+use tower_http::{request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer}, trace::TraceLayer};
+use error::{AppError, Problem, Resource};
+use repository::{Inserted, OrderRepository};
+use auth::{Access, Auth, KeyRepository, Scope};
+use validation::FieldError;
+use cache::CachedOrderRepository;
+use config::{Backend, Config};
+use domain::{Currency, Locale, Money, Timestamp, UnixTime};
+use events::{OrderEvents, PublishingOrderRepository};
+use limits::{OrderJson, RateLimiter};
+use metrics::{MeteredOrderRepository, Metrics};
+use query::{OrderFilter, OrderPage, OrderQuery};
+use storage::Storage;
+use webhooks::WebhookRepository;
 
+mod auth;
+mod bulk;
+mod cache;
+mod config;
+mod db;
+mod domain;
+mod error;
+mod events;
+mod ingest;
+mod limits;
+mod logging;
+mod metrics;
+mod migrations;
+mod nats;
+mod openapi;
+#[cfg(feature = "postgres")]
+mod postgres;
+mod query;
+mod repository;
+mod stats;
+mod storage;
 mod tests;
+mod ui;
+mod validation;
+mod webhooks;
 
 #[tokio::main]
 async fn main() {
-    let connection: Connection = Connection::open("database.db").unwrap();
-    init_db(&connection).await;
-    println!("The database has been successfully initialized");
-    let connection: Arc<Mutex<Connection>> = Arc::new(Mutex::new(connection));
+    let config = match Config::load() {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    };
+    logging::init(&config.log);
+
+    let storage = match Storage::open(&config.database) {
+        Ok(storage) => storage,
+        Err(err) => {
+            tracing::error!(%err, backend = %config.database.backend, "Failed to open the database");
+            std::process::exit(1);
+        }
+    };
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("migrate") {
+        if let Err(err) = migrate_command(&storage, &args[1..]).await {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
 
-    let app: Router = init_app(&connection).await;
+    if let Err(err) = storage.migrate(None).await {
+        tracing::error!(%err, backend = %storage.backend(), "Failed to initialize the database");
+        std::process::exit(1);
+    }
+    let keys = storage.keys();
+    if args.first().map(String::as_str) == Some("keys") {
+        if storage.backend() == Backend::Memory {
+            eprintln!("The memory backend forgets keys on exit, run the server with authentication disabled instead");
+            std::process::exit(1);
+        }
+        if let Err(err) = keys_command(&keys, &args[1..]).await {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
+    tracing::info!(backend = %storage.backend(), "The database has been successfully initialized");
+    if storage.backend() == Backend::Memory {
+        tracing::warn!("Orders are kept in memory only and are lost on restart");
+    }
+    let metrics = Metrics::new();
+    let repository: Arc<dyn OrderRepository> = Arc::new(MeteredOrderRepository::new(storage.orders(), metrics.clone()));
+
+    // requests are accepted right away, /readyz reports the warm-up progress
+    // and stays failing should it fail
+    let cached = Arc::new(CachedOrderRepository::new(repository, config.cache, metrics.clone()));
+    tokio::spawn({
+        let cached = Arc::clone(&cached);
+        async move {
+            match cached.warm_up().await {
+                Ok(count) => tracing::info!(count, "The cache has been warmed up"),
+                Err(err) => tracing::error!(%err, "Failed to warm up the cache, the service is not ready"),
+            }
+        }
+    });
+    let events = OrderEvents::new(config.events);
+    let repository: Arc<dyn OrderRepository> = Arc::new(PublishingOrderRepository::new(cached, events.clone()));
+
+    let ingestion = config.nats.clone().map(|nats| {
+        tokio::spawn(ingest::run_nats(nats, Arc::clone(&repository), config.server.max_items))
+    });
+    let tls = match config.webhooks.tls_connector() {
+        Ok(tls) => tls,
+        Err(err) => {
+            tracing::error!(%err, "Failed to set up TLS for webhooks");
+            std::process::exit(1);
+        }
+    };
+    let webhooks = storage.webhooks();
+    let dispatcher = tokio::spawn(webhooks::run(config.webhooks.clone(), tls, Arc::clone(&webhooks), metrics.clone()));
+
+    if !config.auth.enabled {
+        tracing::warn!("Authentication is disabled, every request may read and change orders");
+    }
+    let app: Router = init_app(&repository, &keys, &webhooks, &config, &metrics, &events).await;
 
-    println!("The application listens for requests at localhost:3000");
-    init_server(app, "0.0.0.0:3000").await;
+    tracing::info!(address = %config.server.bind_address, "The application listens for requests");
+    init_server(app, &config.server.bind_address, Duration::from_secs(config.server.shutdown_timeout), events).await;
+
+    if let Some(ingestion) = ingestion {
+        ingestion.abort();
+    }
+    // an attempt cut short is repeated once its lease runs out
+    dispatcher.abort();
+    match storage.close() {
+        Ok(()) => tracing::info!("The database has been flushed"),
+        Err(err) => tracing::error!(%err, "Failed to flush the database"),
+    }
 }
 
-// init
-async fn init_db(connection: &Connection) {
-    connection.execute(CREATE_ORDERS, []).unwrap();
+// cli
+const MIGRATE_USAGE: &str = "Usage: l0 migrate status | l0 migrate up [VERSION]";
+
+async fn migrate_command(storage: &Storage, args: &[String]) -> Result<(), String> {
+    match args.iter().map(String::as_str).collect::<Vec<&str>>().as_slice() {
+        ["status"] => {
+            let status = storage.migration_status().await.map_err(|err| err.to_string())?;
+            let current = status.iter().filter(|migration| migration.applied_at.is_some()).map(|migration| migration.version).max().unwrap_or(0);
+            let latest = status.last().map(|migration| migration.version).unwrap_or(0);
+            println!("Current {} schema version: {} (latest {})", storage.backend(), current, latest);
+            for migration in status {
+                let applied_at = migration.applied_at.unwrap_or_else(|| String::from("pending"));
+                println!("{:>4}  {:<24} {}", migration.version, migration.name, applied_at);
+            }
+            Ok(())
+        }
+        ["up"] | ["up", _] => {
+            let target = match args.get(1) {
+                Some(version) => Some(version.parse::<u32>().map_err(|_| format!("Invalid version: {}\n{}", version, MIGRATE_USAGE))?),
+                None => None,
+            };
+            let applied = storage.migrate(target).await.map_err(|err| err.to_string())?;
+            if applied.is_empty() {
+                println!("The database is already up to date");
+            }
+            for version in applied {
+                println!("Applied migration {}", version);
+            }
+            Ok(())
+        }
+        _ => Err(String::from(MIGRATE_USAGE)),
+    }
 }
 
-async fn init_app(connection: &Arc<Mutex<Connection>>) -> Router {
-    Router::new()
+const KEYS_USAGE: &str = "Usage: l0 keys list | l0 keys issue NAME read|write|admin | l0 keys revoke ID";
+
+// Issues the first admin key, later ones can be managed over /admin/keys.
+async fn keys_command(keys: &Arc<dyn KeyRepository>, args: &[String]) -> Result<(), String> {
+    match args.iter().map(String::as_str).collect::<Vec<&str>>().as_slice() {
+        ["list"] => {
+            for api_key in keys.list().await.map_err(|err| err.to_string())? {
+                let revoked_at = api_key.revoked_at.map(|revoked_at| format!("revoked {}", revoked_at)).unwrap_or_default();
+                println!("{:>4}  {:<6} {:<12} {:<24} {} {}", api_key.id, api_key.scope, api_key.prefix, api_key.name, api_key.created_at, revoked_at);
+            }
+            Ok(())
+        }
+        ["issue", name, scope] => {
+            let scope: Scope = scope.parse().map_err(|err| format!("{}\n{}", err, KEYS_USAGE))?;
+            if name.trim().is_empty() {
+                return Err(String::from(KEYS_USAGE));
+            }
+            let issued = auth::issue_key(keys.as_ref(), name.trim(), scope).await.map_err(|err| err.to_string())?;
+            println!("Issued key {} with the {} scope, it is not shown again:\n{}", issued.id, issued.scope, issued.key);
+            Ok(())
+        }
+        ["revoke", id] => {
+            let id = id.parse::<i64>().map_err(|_| format!("Invalid key id: {}\n{}", id, KEYS_USAGE))?;
+            if !keys.revoke(id).await.map_err(|err| err.to_string())? {
+                return Err(format!("API key with id={} does not exist", id));
+            }
+            println!("Revoked key {}", id);
+            Ok(())
+        }
+        _ => Err(String::from(KEYS_USAGE)),
+    }
+}
+
+// init
+async fn init_app(repository: &Arc<dyn OrderRepository>, keys: &Arc<dyn KeyRepository>, webhooks: &Arc<dyn WebhookRepository>, config: &Config, metrics: &Metrics, events: &OrderEvents) -> Router {
+    let limiter = RateLimiter::new(config.rate_limit);
+    // routes that expose orders, keys or webhooks need an API key
+    let protected = Router::new()
         .route("/orders", get(get_orders).post(post_order))
-        .route("/orders/:order_uid", get(get_order))
-        .layer(Extension(Arc::clone(connection)))
+        .route("/orders/bulk", post(bulk::import_orders))
+        .route("/orders/export", get(bulk::export_orders))
+        .route("/orders/events", get(events::order_events))
+        .route("/orders/ws", get(events::order_socket))
+        .route("/orders/:order_uid", get(get_order).put(put_order).patch(patch_order).delete(delete_order))
+        .route("/stats/revenue", get(stats::get_revenue))
+        .route("/stats/brands", get(stats::get_brands))
+        .route("/stats/daily", get(stats::get_daily))
+        .route("/ui", get(ui::order_page))
+        .route("/admin/keys", get(auth::list_keys).post(auth::create_key))
+        .route("/admin/keys/:id", delete(auth::revoke_key))
+        .route("/admin/webhooks", get(webhooks::list_webhooks).post(webhooks::create_webhook))
+        .route("/admin/webhooks/:id", delete(webhooks::delete_webhook))
+        .route("/admin/webhooks/:id/deliveries", get(webhooks::list_deliveries))
+        .route("/admin/webhooks/:id/deliveries/:delivery_id/retry", post(webhooks::retry_delivery))
+        .route_layer(middleware::from_fn_with_state(limiter.clone(), limits::limit_requests))
+        // runs before the limit per API key, so the key is known
+        .route_layer(middleware::from_fn_with_state(Auth::new(config.auth, Arc::clone(keys)), auth::authenticate))
+        // runs first, addresses sending bad keys are limited without a key lookup
+        .route_layer(middleware::from_fn_with_state(limiter, limits::limit_unauthenticated));
+
+    Router::new()
+        .merge(protected)
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
+        .route("/metrics", get(get_metrics))
+        .merge(openapi::docs())
+        .layer(middleware::from_fn_with_state(metrics.clone(), metrics::track_requests))
+        .layer(DefaultBodyLimit::max(config.server.body_limit))
+        .layer(Extension(Arc::clone(repository)))
+        .layer(Extension(Arc::clone(keys)))
+        .layer(Extension(Arc::clone(webhooks)))
+        .layer(Extension(metrics.clone()))
+        .layer(Extension(config.server.clone()))
+        .layer(Extension(events.clone()))
+        .layer(
+            ServiceBuilder::new()
+                .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid))
+                .layer(TraceLayer::new_for_http()
+                    .make_span_with(logging::request_span)
+                    .on_response(logging::log_response)
+                    // failed requests are logged by AppError
+                    .on_failure(()))
+                .layer(PropagateRequestIdLayer::x_request_id()),
+        )
 }
 
-async fn init_server(app: Router, addr: &str) {
-    let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
-    axum::serve(listener, app)
-        .tcp_nodelay(true)
-        .await
-        .unwrap();
+async fn init_server(app: Router, addr: &str, drain_timeout: Duration, events: OrderEvents) {
+    let listener = match TcpListener::bind(addr).await {
+        Ok(listener) => listener,
+        Err(err) => {
+            tracing::error!(%err, address = addr, "Failed to listen for requests");
+            std::process::exit(1);
+        }
+    };
+    let shutdown = async move {
+        shutdown_signal().await;
+        // event streams never finish on their own
+        events.close();
+    };
+    match serve(listener, app, shutdown, drain_timeout).await {
+        Ok(()) => tracing::info!("The server has been stopped"),
+        Err(err) => tracing::warn!(%err, "The server has been stopped"),
+    }
 }
 
-// handlers
-enum AppError {
-    Unknown(String),
-    OrderAlreadyExists(String),
-    OrderNotExists(String),
+// Serves `app` until `shutdown` completes, then stops accepting connections
+// and gives in-flight requests `drain_timeout` to finish.
+async fn serve(listener: TcpListener, app: Router, shutdown: impl Future<Output = ()> + Send + 'static, drain_timeout: Duration) -> io::Result<()> {
+    let (draining, mut drain_started) = watch::channel(false);
+    // the rate limiter needs the address of the client
+    let server = axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>())
+        .tcp_nodelay(true)
+        .with_graceful_shutdown(async move {
+            shutdown.await;
+            draining.send_replace(true);
+        });
+
+    let deadline = async {
+        if drain_started.wait_for(|draining| *draining).await.is_ok() {
+            tokio::time::sleep(drain_timeout).await;
+        } else {
+            std::future::pending::<()>().await;
+        }
+    };
+
+    tokio::select! {
+        result = server => result,
+        _ = deadline => Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            format!("in-flight requests did not finish within {:?}", drain_timeout),
+        )),
+    }
 }
 
-impl IntoResponse for AppError {
-    fn into_response(self) -> axum::response::Response {
-        let (status, msg) = match self {
-            Self::Unknown(err) => (StatusCode::BAD_REQUEST, err),
-            Self::OrderAlreadyExists(order_uid) => (StatusCode::BAD_REQUEST, format!("Order with order_uid={} already exists", order_uid)),
-            Self::OrderNotExists(order_uid) => (StatusCode::BAD_REQUEST, format!("Order with order_uid={} does not exist", order_uid)),
-        };
-        return (status, msg).into_response();
+async fn shutdown_signal() {
+    let interrupt = async {
+        if let Err(err) = tokio::signal::ctrl_c().await {
+            tracing::error!(%err, "Failed to listen for SIGINT");
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(err) => {
+                tracing::error!(%err, "Failed to listen for SIGTERM");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = interrupt => {},
+        _ = terminate => {},
     }
+    tracing::info!("Shutting down, waiting for in-flight requests");
 }
 
-async fn get_orders(Extension(connection): Extension<Arc<Mutex<Connection>>>) -> Result<Json<Vec<Order>>, AppError> {
-    let connection = connection.lock().map_err(|err|{
-        AppError::Unknown(err.to_string())
-    })?;
-    let mut stmt = connection.prepare("SELECT * FROM orders").map_err(|err|{
-        AppError::Unknown(err.to_string())
-    })?;
-    
-    let orders: Vec<Order> = stmt.query_map([], |row| {
-        <&Row<'_> as TryInto<Order>>::try_into(row)
-    }).map_err(|err|{
-        AppError::Unknown(err.to_string())
-    })?.map(|value|{
-        value.unwrap()
-    }).collect();
-    
-    return Ok(Json(orders));
+// handlers
+#[utoipa::path(get, path = "/healthz", tag = "service",
+    responses((status = 200, description = "The process is running")))]
+async fn healthz() -> Json<Value> {
+    Json(json!({ "status": "ok" }))
 }
 
-async fn get_order(Path(order_uid): Path<String>, Extension(connection): Extension<Arc<Mutex<Connection>>>) -> Result<Json<Order>, AppError> {
-    let connection = connection.lock().map_err(|err|{
-        AppError::Unknown(err.to_string())
-    })?;
+#[utoipa::path(get, path = "/metrics", tag = "service",
+    responses(
+        (status = 200, description = "Prometheus text exposition format", content_type = "text/plain"),
+        (status = 500, body = Problem),
+    ))]
+async fn get_metrics(Extension(repository): Extension<Arc<dyn OrderRepository>>, Extension(metrics): Extension<Metrics>, Extension(events): Extension<OrderEvents>) -> Result<String, AppError> {
+    metrics.set_stored_orders(repository.count().await?);
+    metrics.set_event_subscribers(events.subscribers());
+    Ok(metrics.render())
+}
 
-    let query = &format!("SELECT * FROM orders WHERE order_uid = ('{}');", &order_uid);
-    let mut stmt = connection.prepare(query).map_err(|_|{
-        AppError::OrderNotExists(order_uid.clone())
-    })?;
+#[utoipa::path(get, path = "/readyz", tag = "service",
+    responses(
+        (status = 200, description = "The service can serve requests"),
+        (status = 503, body = Problem),
+    ))]
+async fn readyz(Extension(repository): Extension<Arc<dyn OrderRepository>>) -> Result<Json<Value>, AppError> {
+    repository.ready().await.map_err(|err| AppError::NotReady(err.to_string()))?;
+    Ok(Json(json!({ "status": "ready" })))
+}
 
-    if stmt.column_count() <= 0 {
-        return Err(AppError::OrderNotExists(order_uid.clone()));
-    }
+#[utoipa::path(get, path = "/orders", tag = "orders", params(OrderQuery),
+    responses(
+        (status = 200, body = OrderPage),
+        (status = 400, body = Problem),
+        (status = 500, body = Problem),
+    ))]
+async fn get_orders(Extension(repository): Extension<Arc<dyn OrderRepository>>, Extension(access): Extension<Access>, query: Result<Query<OrderQuery>, QueryRejection>) -> Result<Json<OrderPage>, AppError> {
+    let Query(query) = query?;
+    access.check_customer_filter(query.customer_id.as_ref())?;
+    let filter = OrderFilter::try_from(query).map_err(AppError::InvalidQuery)?;
+    let mut page = repository.page(&filter).await?;
+    page.orders.iter_mut().for_each(|order| access.redact(order));
+    Ok(Json(page))
+}
 
-    let order = stmt.query_row([], |row| {
-        <&Row<'_> as TryInto<Order>>::try_into(row)
-    }).map_err(|_|{
-        AppError::OrderNotExists(order_uid.clone())
-    })?;
-        
-    return Ok(Json(order));
+#[utoipa::path(get, path = "/orders/{order_uid}", tag = "orders", params(("order_uid" = String, Path)),
+    responses(
+        (status = 200, body = Order),
+        (status = 404, body = Problem),
+        (status = 500, body = Problem),
+    ))]
+async fn get_order(Path(order_uid): Path<String>, Extension(repository): Extension<Arc<dyn OrderRepository>>, Extension(access): Extension<Access>) -> Result<Json<Order>, AppError> {
+    logging::record_order_uid(&order_uid);
+    let mut order = repository.get(&order_uid).await?.ok_or(AppError::OrderNotFound(order_uid))?;
+    access.redact(&mut order);
+    Ok(Json(order))
 }
 
-async fn post_order(Extension(connection): Extension<Arc<Mutex<Connection>>>, Json(order): Json<Order>) -> Result<(), AppError> {
-    let connection = connection.lock().map_err(|err|{
-        AppError::Unknown(err.to_string())
-    })?;
+// 201 for a new order, 200 for a resend of an identical one.
+#[utoipa::path(post, path = "/orders", tag = "orders", request_body = Order,
+    responses(
+        (status = 201, description = "The order has been stored", headers(("location" = String))),
+        (status = 200, description = "An identical order is already stored", headers(("location" = String))),
+        (status = 400, body = Problem),
+        (status = 409, body = Problem),
+        (status = 422, body = Problem),
+        (status = 500, body = Problem),
+    ))]
+async fn post_order(Extension(repository): Extension<Arc<dyn OrderRepository>>, OrderJson(order): OrderJson) -> Result<impl IntoResponse, AppError> {
+    logging::record_order_uid(&order.order_uid);
+    let status = match repository.insert(&order).await? {
+        Inserted::Created => StatusCode::CREATED,
+        Inserted::Unchanged => StatusCode::OK,
+    };
+    Ok((status, [(header::LOCATION, format!("/orders/{}", order.order_uid))]))
+}
 
-    let query = &format!("SELECT * FROM orders WHERE order_uid = ('{}');", order.order_uid);
-    let mut stmt = connection.prepare(query).map_err(|err|{
-        AppError::Unknown(err.to_string())
-    })?;
+#[utoipa::path(put, path = "/orders/{order_uid}", tag = "orders", params(("order_uid" = String, Path)), request_body = Order,
+    responses(
+        (status = 200, body = Order),
+        (status = 400, body = Problem),
+        (status = 404, body = Problem),
+        (status = 422, body = Problem),
+        (status = 500, body = Problem),
+    ))]
+async fn put_order(Path(order_uid): Path<String>, Extension(repository): Extension<Arc<dyn OrderRepository>>, OrderJson(order): OrderJson) -> Result<Json<Order>, AppError> {
+    logging::record_order_uid(&order_uid);
+    if order.order_uid != order_uid {
+        return Err(AppError::Validation(Resource::Order, vec![FieldError {
+            field: String::from("order_uid"),
+            rule: "path_mismatch",
+            message: format!("must match the order_uid in the path {}", order_uid),
+        }]));
+    }
+    repository.update(&order).await?;
+    Ok(Json(order))
+}
 
-    let existing_order = stmt.query_row([], |row| {
-        <&Row<'_> as TryInto<Order>>::try_into(row)
-    });
+// Changes the status of the items with the given chrt_id.
+#[utoipa::path(patch, path = "/orders/{order_uid}", tag = "orders", params(("order_uid" = String, Path)), request_body = OrderPatch,
+    responses(
+        (status = 200, body = Order),
+        (status = 400, body = Problem),
+        (status = 404, body = Problem),
+        (status = 422, body = Problem),
+        (status = 500, body = Problem),
+    ))]
+async fn patch_order(Path(order_uid): Path<String>, Extension(repository): Extension<Arc<dyn OrderRepository>>, payload: Result<Json<OrderPatch>, JsonRejection>) -> Result<Json<Order>, AppError> {
+    logging::record_order_uid(&order_uid);
+    let Json(patch) = payload.map_err(|rejection| AppError::InvalidJson(Resource::OrderPatch, rejection))?;
+    let mut order = repository.get(&order_uid).await?.ok_or_else(|| AppError::OrderNotFound(order_uid.clone()))?;
 
-    if let Ok(_) = existing_order {
-        return Err(AppError::OrderAlreadyExists(order.order_uid));
+    let mut errors = Vec::new();
+    for (index, change) in patch.items.iter().enumerate() {
+        let mut found = false;
+        for item in order.items.iter_mut().filter(|item| item.chrt_id == change.chrt_id) {
+            item.status = change.status;
+            found = true;
+        }
+        if !found {
+            errors.push(FieldError {
+                field: format!("items[{}].chrt_id", index),
+                rule: "unknown_item",
+                message: format!("the order has no item with chrt_id {}", change.chrt_id),
+            });
+        }
+    }
+    if !errors.is_empty() {
+        return Err(AppError::Validation(Resource::OrderPatch, errors));
     }
 
-    connection.execute(
-        r#"INSERT INTO orders (order_uid, track_number, entry, delivery, payment, items, locale, internal_signature, customer_id, delivery_service, shardkey, sm_id, date_created, oof_shard)
-                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)"#,
-        [
-                order.order_uid,
-                order.track_number,
-                order.entry, 
-                serde_json::to_string(&order.delivery).unwrap(), 
-                serde_json::to_string(&order.payment).unwrap(), 
-                serde_json::to_string(&order.items).unwrap(),
-                order.locale, 
-                order.internal_signature, 
-                order.customer_id, 
-                order.delivery_service, 
-                order.shardkey, 
-                format!("{}", order.sm_id), 
-                order.date_created, 
-                order.oof_shard
-                ]
-    ).map_err(|err|{
-        AppError::Unknown(err.to_string())
-    })?;
-
-    return Ok(());    
+    repository.update(&order).await?;
+    Ok(Json(order))
 }
 
-// impls
-impl TryFrom<&Row<'_>> for Order {
-    type Error = rusqlite::Error;
-    fn try_from(value: &Row<'_>) -> Result<Self, Self::Error> {
-        let delivery: String = value.get(4)?;
-        let delivery: Delivery = serde_json::from_str(json!(delivery).as_str().unwrap()).unwrap();
-
-        let payment: String = value.get(5)?;
-        let payment: Payment = serde_json::from_str(json!(payment).as_str().unwrap()).unwrap();
-
-        let items: String = value.get(6)?;
-        let items: Vec<Item> = serde_json::from_str(json!(items).as_str().unwrap()).unwrap();
-
-        let date_created: String = value.get(13)?;
-
-        Ok(Order {
-            order_uid: value.get(1)?,
-            track_number: value.get(2)?, 
-            entry: value.get(3)?, 
-            delivery: delivery, 
-            payment: payment, 
-            items: items, 
-            locale: value.get(7)?, 
-            internal_signature: value.get(8)?, 
-            customer_id: value.get(9)?, 
-            delivery_service: value.get(10)?, 
-            shardkey: value.get(11)?, 
-            sm_id: value.get(12)?, 
-            date_created: date_created, 
-            oof_shard: value.get(14)?
-        })
-    }
+#[utoipa::path(delete, path = "/orders/{order_uid}", tag = "orders", params(("order_uid" = String, Path)),
+    responses(
+        (status = 204, description = "The order has been deleted"),
+        (status = 404, body = Problem),
+        (status = 500, body = Problem),
+    ))]
+async fn delete_order(Path(order_uid): Path<String>, Extension(repository): Extension<Arc<dyn OrderRepository>>) -> Result<StatusCode, AppError> {
+    logging::record_order_uid(&order_uid);
+    repository.delete(&order_uid).await?;
+    Ok(StatusCode::NO_CONTENT)
 }
 
 // models
-#[derive(Serialize, Deserialize, Default, PartialEq, Debug)]
+// Body of PATCH /orders/:order_uid.
+#[derive(Deserialize, ToSchema, Debug)]
+struct OrderPatch {
+    items: Vec<ItemStatus>,
+}
+
+#[derive(Deserialize, ToSchema, Debug)]
+struct ItemStatus {
+    chrt_id: i64,
+    status: i32,
+}
+
+#[derive(Serialize, Deserialize, ToSchema, Default, PartialEq, Clone, Debug)]
 struct Delivery {
     name: String,
     phone: String,
@@ -189,36 +511,38 @@ struct Delivery {
     email: String,
 }
 
-#[derive(Serialize, Deserialize, Default, PartialEq, Debug)]
+// Money of a payment and of the items of its order is in payment.currency.
+#[derive(Serialize, ToSchema, PartialEq, Clone, Debug)]
 struct Payment {
     transaction: String,
     request_id: String,
-    currency: String,
+    currency: Currency,
     provider: String,
-    amount: u32,
-    payment_dt: i64,
+    amount: Money,
+    payment_dt: UnixTime,
     bank: String,
-    delivery_cost: u32,
-    goods_total: u32,
-    custom_fee: u32,
+    delivery_cost: Money,
+    goods_total: Money,
+    custom_fee: Money,
 }
 
-#[derive(Serialize, Deserialize, Default, PartialEq, Clone, Debug)]
+#[derive(Serialize, ToSchema, PartialEq, Clone, Debug)]
 struct Item {
     chrt_id: i64,
     track_number: String,
-    price: u32,
+    price: Money,
     rid: String,
     name: String,
     sale: u32,
     size: String,
-    total_price: u32,
+    total_price: Money,
     nm_id: i64,
     brand: String,
     status: i32,
 }
 
-#[derive(Serialize, Deserialize, PartialEq, Debug)]
+#[derive(Serialize, Deserialize, ToSchema, PartialEq, Clone, Debug)]
+#[serde(try_from = "validation::SentOrder")]
 struct Order {
     order_uid: String,
     track_number: String,
@@ -226,54 +550,13 @@ struct Order {
     delivery: Delivery,
     payment: Payment,
     items: Vec<Item>,
-    locale: String,
+    locale: Locale,
     internal_signature: String,
     customer_id: String,
     delivery_service: String,
     shardkey: String,
     sm_id: i32,
-    date_created: String,
+    date_created: Timestamp,
     oof_shard: String,
 }
 
-impl Default for Order {
-    fn default() -> Self {
-        Order {
-            order_uid: String::default(),
-            track_number: String::default(), 
-            entry: String::default(), 
-            delivery: Delivery::default(), 
-            payment: Payment::default(), 
-            items: vec![Item::default().clone(); 2], 
-            locale: String::default(), 
-            internal_signature: String::default(), 
-            customer_id: String::default(), 
-            delivery_service: String::default(), 
-            shardkey: String::default(), 
-            sm_id: i32::default(), 
-            date_created: String::new(),
-            oof_shard: String::default() 
-        }
-    }
-}
-
-// consts
-const CREATE_ORDERS: &str = r#"
-    CREATE TABLE IF NOT EXISTS orders (
-        [id] SERIAL PRIMARY KEY,
-        [order_uid] VARCHAR(255) NOT NULL,
-        [track_number] VARCHAR(255) NOT NULL,
-        [entry] VARCHAR(255) NOT NULL,
-        [delivery] JSON NOT NULL,
-        [payment] JSON NOT NULL,
-        [items] JSON NOT NULL,
-        [locale] VARCHAR(255),
-        [internal_signature] VARCHAR(255),
-        [customer_id] VARCHAR(255),
-        [delivery_service] VARCHAR(255),
-        [shardkey] VARCHAR(255),
-        [sm_id] INTEGER,
-        [date_created] TIMESTAMP,
-        [oof_shard] VARCHAR(255)
-    );
-"#;
\ No newline at end of file
@@ -0,0 +1,217 @@
+use std::{sync::Arc, time::Instant};
+use async_trait::async_trait;
+use axum::{extract::{MatchedPath, Request, State}, middleware::Next, response::Response};
+use prometheus::{Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, Opts, Registry, TextEncoder};
+
+use crate::Order;
+use crate::query::{OrderFilter, OrderPage};
+use crate::repository::{Inserted, OrderRepository, RepositoryError};
+use crate::stats::{BrandTotal, DailyOrders, DateRange, Revenue};
+
+// Queries against a local SQLite file take from microseconds to milliseconds.
+const DB_BUCKETS: &[f64] = &[0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0];
+
+// Metrics of one server instance, exposed at GET /metrics. Clones share the
+// same registry.
+#[derive(Clone)]
+pub struct Metrics {
+    registry: Registry,
+    http_requests: IntCounterVec,
+    http_request_duration: HistogramVec,
+    db_query_duration: HistogramVec,
+    cache_requests: IntCounterVec,
+    stored_orders: IntGauge,
+    event_subscribers: IntGauge,
+    webhook_attempts: IntCounterVec,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let http_requests = IntCounterVec::new(
+            Opts::new("l0_http_requests_total", "HTTP requests by route and status"),
+            &["method", "route", "status"],
+        ).expect("valid metric");
+        let http_request_duration = HistogramVec::new(
+            HistogramOpts::new("l0_http_request_duration_seconds", "HTTP request latency by route"),
+            &["method", "route"],
+        ).expect("valid metric");
+        let db_query_duration = HistogramVec::new(
+            HistogramOpts::new("l0_db_query_duration_seconds", "Order storage latency by operation").buckets(DB_BUCKETS.to_vec()),
+            &["operation"],
+        ).expect("valid metric");
+        let cache_requests = IntCounterVec::new(
+            Opts::new("l0_cache_requests_total", "Order cache lookups by result, hit or miss"),
+            &["result"],
+        ).expect("valid metric");
+        let stored_orders = IntGauge::new("l0_stored_orders", "Orders in the storage").expect("valid metric");
+        let event_subscribers = IntGauge::new("l0_event_subscribers", "Open order event streams").expect("valid metric");
+        let webhook_attempts = IntCounterVec::new(
+            Opts::new("l0_webhook_attempts_total", "Webhook delivery attempts by result, delivered, failed or dead"),
+            &["result"],
+        ).expect("valid metric");
+
+        let registry = Registry::new();
+        registry.register(Box::new(http_requests.clone())).expect("unique metric");
+        registry.register(Box::new(http_request_duration.clone())).expect("unique metric");
+        registry.register(Box::new(db_query_duration.clone())).expect("unique metric");
+        registry.register(Box::new(cache_requests.clone())).expect("unique metric");
+        registry.register(Box::new(stored_orders.clone())).expect("unique metric");
+        registry.register(Box::new(event_subscribers.clone())).expect("unique metric");
+        registry.register(Box::new(webhook_attempts.clone())).expect("unique metric");
+
+        Metrics { registry, http_requests, http_request_duration, db_query_duration, cache_requests, stored_orders, event_subscribers, webhook_attempts }
+    }
+
+    pub fn cache_hit(&self) {
+        self.cache_requests.with_label_values(&["hit"]).inc();
+    }
+
+    pub fn cache_miss(&self) {
+        self.cache_requests.with_label_values(&["miss"]).inc();
+    }
+
+    // `result` is delivered, failed (retried later) or dead
+    pub fn webhook_attempt(&self, result: &str) {
+        self.webhook_attempts.with_label_values(&[result]).inc();
+    }
+
+    pub fn set_stored_orders(&self, count: u64) {
+        self.stored_orders.set(i64::try_from(count).unwrap_or(i64::MAX));
+    }
+
+    pub fn set_event_subscribers(&self, count: usize) {
+        self.event_subscribers.set(i64::try_from(count).unwrap_or(i64::MAX));
+    }
+
+    // Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut buffer = Vec::new();
+        if let Err(err) = TextEncoder::new().encode(&self.registry.gather(), &mut buffer) {
+            tracing::error!(%err, "Failed to encode metrics");
+        }
+        String::from_utf8(buffer).unwrap_or_default()
+    }
+}
+
+// Middleware counting requests and their latency. Routes are labelled by
+// their pattern, e.g. /orders/:order_uid, so order ids do not become labels.
+pub async fn track_requests(State(metrics): State<Metrics>, request: Request, next: Next) -> Response {
+    let method = request.method().to_string();
+    let route = request.extensions().get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_else(|| String::from("unmatched"));
+
+    let started = Instant::now();
+    let response = next.run(request).await;
+
+    metrics.http_request_duration
+        .with_label_values(&[&method, &route])
+        .observe(started.elapsed().as_secs_f64());
+    metrics.http_requests
+        .with_label_values(&[&method, &route, response.status().as_str()])
+        .inc();
+
+    response
+}
+
+// Records how long every call to the wrapped repository takes.
+pub struct MeteredOrderRepository {
+    inner: Arc<dyn OrderRepository>,
+    metrics: Metrics,
+}
+
+impl MeteredOrderRepository {
+    pub fn new(inner: Arc<dyn OrderRepository>, metrics: Metrics) -> Self {
+        MeteredOrderRepository { inner, metrics }
+    }
+
+    fn observe(&self, operation: &str, started: Instant) {
+        self.metrics.db_query_duration
+            .with_label_values(&[operation])
+            .observe(started.elapsed().as_secs_f64());
+    }
+}
+
+#[async_trait]
+impl OrderRepository for MeteredOrderRepository {
+    async fn list(&self) -> Result<Vec<Order>, RepositoryError> {
+        let started = Instant::now();
+        let result = self.inner.list().await;
+        self.observe("list", started);
+        result
+    }
+
+    async fn get(&self, order_uid: &str) -> Result<Option<Order>, RepositoryError> {
+        let started = Instant::now();
+        let result = self.inner.get(order_uid).await;
+        self.observe("get", started);
+        result
+    }
+
+    async fn page(&self, filter: &OrderFilter) -> Result<OrderPage, RepositoryError> {
+        let started = Instant::now();
+        let result = self.inner.page(filter).await;
+        self.observe("page", started);
+        result
+    }
+
+    async fn insert(&self, order: &Order) -> Result<Inserted, RepositoryError> {
+        let started = Instant::now();
+        let result = self.inner.insert(order).await;
+        self.observe("insert", started);
+        result
+    }
+
+    async fn insert_batch(&self, orders: &[Order]) -> Result<Vec<Result<Inserted, RepositoryError>>, RepositoryError> {
+        let started = Instant::now();
+        let result = self.inner.insert_batch(orders).await;
+        self.observe("insert_batch", started);
+        result
+    }
+
+    async fn update(&self, order: &Order) -> Result<(), RepositoryError> {
+        let started = Instant::now();
+        let result = self.inner.update(order).await;
+        self.observe("update", started);
+        result
+    }
+
+    async fn delete(&self, order_uid: &str) -> Result<(), RepositoryError> {
+        let started = Instant::now();
+        let result = self.inner.delete(order_uid).await;
+        self.observe("delete", started);
+        result
+    }
+
+    async fn count(&self) -> Result<u64, RepositoryError> {
+        let started = Instant::now();
+        let result = self.inner.count().await;
+        self.observe("count", started);
+        result
+    }
+
+    async fn revenue(&self, range: &DateRange) -> Result<Vec<Revenue>, RepositoryError> {
+        let started = Instant::now();
+        let result = self.inner.revenue(range).await;
+        self.observe("revenue", started);
+        result
+    }
+
+    async fn top_brands(&self, range: &DateRange, limit: usize) -> Result<Vec<BrandTotal>, RepositoryError> {
+        let started = Instant::now();
+        let result = self.inner.top_brands(range, limit).await;
+        self.observe("top_brands", started);
+        result
+    }
+
+    async fn daily_orders(&self, range: &DateRange) -> Result<Vec<DailyOrders>, RepositoryError> {
+        let started = Instant::now();
+        let result = self.inner.daily_orders(range).await;
+        self.observe("daily_orders", started);
+        result
+    }
+
+    async fn ready(&self) -> Result<(), RepositoryError> {
+        self.inner.ready().await
+    }
+}
@@ -0,0 +1,158 @@
+use std::fmt;
+use rusqlite::{params, Connection, OptionalExtension};
+
+pub struct Migration {
+    pub version: u32,
+    pub name: &'static str,
+    pub sql: &'static str,
+}
+
+// Up-migrations of SQLite in the order they are applied. Never edit a
+// migration that has been released; add a new one instead.
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "create_orders",
+        sql: include_str!("../migrations/0001_create_orders.sql"),
+    },
+    Migration {
+        version: 2,
+        name: "normalize_orders",
+        sql: include_str!("../migrations/0002_normalize_orders.sql"),
+    },
+    Migration {
+        version: 3,
+        name: "order_audit_timestamps",
+        sql: include_str!("../migrations/0003_order_audit_timestamps.sql"),
+    },
+    Migration {
+        version: 4,
+        name: "api_keys",
+        sql: include_str!("../migrations/0004_api_keys.sql"),
+    },
+    Migration {
+        version: 5,
+        name: "order_created_utc",
+        sql: include_str!("../migrations/0005_order_created_utc.sql"),
+    },
+    Migration {
+        version: 6,
+        name: "webhooks",
+        sql: include_str!("../migrations/0006_webhooks.sql"),
+    },
+    Migration {
+        version: 7,
+        name: "quarantine_invalid_orders",
+        sql: include_str!("../migrations/0007_quarantine_invalid_orders.sql"),
+    },
+    Migration {
+        version: 8,
+        name: "webhook_retention",
+        sql: include_str!("../migrations/0008_webhook_retention.sql"),
+    },
+];
+
+#[derive(Debug)]
+pub enum MigrationError {
+    UnknownVersion { version: u32, latest: u32 },
+    Downgrade { current: u32, target: u32 },
+    Storage(String),
+}
+
+impl fmt::Display for MigrationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownVersion { version, latest } => write!(f, "Unknown schema version {}, latest is {}", version, latest),
+            Self::Downgrade { current, target } => write!(f, "Cannot migrate down from schema version {} to {}", current, target),
+            Self::Storage(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl From<rusqlite::Error> for MigrationError {
+    fn from(err: rusqlite::Error) -> Self {
+        MigrationError::Storage(err.to_string())
+    }
+}
+
+impl From<r2d2::Error> for MigrationError {
+    fn from(err: r2d2::Error) -> Self {
+        MigrationError::Storage(err.to_string())
+    }
+}
+
+pub struct MigrationStatus {
+    pub version: u32,
+    pub name: &'static str,
+    pub applied_at: Option<String>,
+}
+
+const CREATE_SCHEMA_VERSION: &str = r#"
+    CREATE TABLE IF NOT EXISTS schema_version (
+        [version] INTEGER PRIMARY KEY,
+        [name] VARCHAR(255) NOT NULL,
+        [applied_at] TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+    );
+"#;
+
+pub fn latest(migrations: &[Migration]) -> u32 {
+    migrations.last().map(|migration| migration.version).unwrap_or(0)
+}
+
+// The migrations after `current` up to `target`, the latest one by default.
+// Shared by the storage backends, each has its own list.
+pub fn pending(migrations: &'static [Migration], current: u32, target: Option<u32>) -> Result<Vec<&'static Migration>, MigrationError> {
+    let latest = latest(migrations);
+    if current > latest {
+        return Err(MigrationError::UnknownVersion { version: current, latest });
+    }
+
+    let target = target.unwrap_or(latest);
+    if target > latest {
+        return Err(MigrationError::UnknownVersion { version: target, latest });
+    }
+    if target < current {
+        return Err(MigrationError::Downgrade { current, target });
+    }
+
+    Ok(migrations.iter().filter(|migration| migration.version > current && migration.version <= target).collect())
+}
+
+pub fn current_version(connection: &Connection) -> Result<u32, MigrationError> {
+    connection.execute_batch(CREATE_SCHEMA_VERSION)?;
+    let version: Option<u32> = connection.query_row(
+        "SELECT MAX(version) FROM schema_version",
+        [],
+        |row| row.get(0),
+    )?;
+    Ok(version.unwrap_or(0))
+}
+
+// Applies every pending migration up to `target` (the latest one by default),
+// each in its own transaction. Returns the versions that were applied.
+pub fn migrate(connection: &mut Connection, target: Option<u32>) -> Result<Vec<u32>, MigrationError> {
+    let current = current_version(connection)?;
+    let mut applied = Vec::new();
+    for migration in pending(MIGRATIONS, current, target)? {
+        let transaction = connection.transaction()?;
+        transaction.execute_batch(migration.sql)?;
+        transaction.execute(
+            "INSERT INTO schema_version (version, name) VALUES (?1, ?2)",
+            params![migration.version, migration.name],
+        )?;
+        transaction.commit()?;
+        applied.push(migration.version);
+    }
+
+    Ok(applied)
+}
+
+pub fn status(connection: &Connection) -> Result<Vec<MigrationStatus>, MigrationError> {
+    current_version(connection)?;
+    let mut stmt = connection.prepare("SELECT applied_at FROM schema_version WHERE version = ?1")?;
+
+    MIGRATIONS.iter().map(|migration| {
+        let applied_at: Option<String> = stmt.query_row(params![migration.version], |row| row.get(0)).optional()?;
+        Ok(MigrationStatus { version: migration.version, name: migration.name, applied_at })
+    }).collect()
+}
@@ -0,0 +1,178 @@
+use async_trait::async_trait;
+use serde::Deserialize;
+use tokio::{io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader}, net::{tcp::{OwnedReadHalf, OwnedWriteHalf}, TcpStream}};
+
+use crate::ingest::{Message, OrderSource, SourceError};
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct NatsConfig {
+    // nats://host:port
+    pub url: String,
+    pub subject: String,
+    // subscribers in the same queue group share the messages of the subject
+    pub queue_group: Option<String>,
+    pub dead_letter_subject: String,
+}
+
+impl Default for NatsConfig {
+    fn default() -> Self {
+        NatsConfig {
+            url: String::new(),
+            subject: String::from("orders"),
+            queue_group: None,
+            dead_letter_subject: String::from("orders.dead_letter"),
+        }
+    }
+}
+
+// A subscription over the NATS client protocol. Messages that carry a reply
+// subject (JetStream deliveries) are acknowledged with +ACK, -NAK and +TERM;
+// plain core NATS messages have nothing to acknowledge.
+pub struct NatsSource {
+    reader: BufReader<OwnedReadHalf>,
+    writer: OwnedWriteHalf,
+    dead_letter_subject: String,
+}
+
+impl NatsSource {
+    pub async fn connect(config: &NatsConfig) -> Result<Self, SourceError> {
+        let addr = config.url.strip_prefix("nats://").unwrap_or(&config.url);
+        let stream = TcpStream::connect(addr).await?;
+        stream.set_nodelay(true)?;
+        let (reader, writer) = stream.into_split();
+
+        let mut source = NatsSource {
+            reader: BufReader::new(reader),
+            writer,
+            dead_letter_subject: config.dead_letter_subject.clone(),
+        };
+
+        let info = source.read_line().await?;
+        if !info.starts_with("INFO") {
+            return Err(SourceError(format!("Unexpected greeting from the server: {}", info)));
+        }
+
+        let connect = r#"{"verbose":false,"pedantic":false,"lang":"rust","name":"l0","version":"1.0.0","protocol":1,"headers":true}"#;
+        let subscribe = match &config.queue_group {
+            Some(queue_group) => format!("SUB {} {} 1", config.subject, queue_group),
+            None => format!("SUB {} 1", config.subject),
+        };
+        source.write(format!("CONNECT {}\r\n{}\r\nPING\r\n", connect, subscribe).as_bytes()).await?;
+
+        // the server answers PING only after it has accepted CONNECT and SUB
+        loop {
+            let line = source.read_line().await?;
+            match line.as_str() {
+                "PONG" => break,
+                "+OK" => continue,
+                _ => return Err(SourceError(format!("Failed to subscribe to {}: {}", config.subject, line))),
+            }
+        }
+
+        Ok(source)
+    }
+
+    async fn read_line(&mut self) -> Result<String, SourceError> {
+        let mut line = String::new();
+        if self.reader.read_line(&mut line).await? == 0 {
+            return Err(SourceError(String::from("Connection closed by the server")));
+        }
+        Ok(line.trim_end().to_string())
+    }
+
+    // Reads `len` bytes followed by the trailing CRLF.
+    async fn read_payload(&mut self, len: usize) -> Result<Vec<u8>, SourceError> {
+        let mut payload = vec![0; len + 2];
+        self.reader.read_exact(&mut payload).await?;
+        payload.truncate(len);
+        Ok(payload)
+    }
+
+    async fn write(&mut self, bytes: &[u8]) -> Result<(), SourceError> {
+        self.writer.write_all(bytes).await?;
+        self.writer.flush().await?;
+        Ok(())
+    }
+
+    async fn publish(&mut self, subject: &str, payload: &[u8]) -> Result<(), SourceError> {
+        let mut bytes = format!("PUB {} {}\r\n", subject, payload.len()).into_bytes();
+        bytes.extend_from_slice(payload);
+        bytes.extend_from_slice(b"\r\n");
+        self.write(&bytes).await
+    }
+
+    async fn reply(&mut self, message: &Message, payload: &[u8]) -> Result<(), SourceError> {
+        match &message.ack_token {
+            Some(reply) => self.publish(reply, payload).await,
+            None => Ok(()),
+        }
+    }
+}
+
+fn parse_len(value: Option<&&str>, line: &str) -> Result<usize, SourceError> {
+    value.and_then(|value| value.parse().ok()).ok_or_else(|| {
+        SourceError(format!("Malformed message from the server: {}", line))
+    })
+}
+
+#[async_trait]
+impl OrderSource for NatsSource {
+    async fn next(&mut self) -> Result<Option<Message>, SourceError> {
+        loop {
+            let line = self.read_line().await?;
+            let args: Vec<&str> = line.split_whitespace().collect();
+
+            match args.first().copied() {
+                // MSG <subject> <sid> [reply-to] <#bytes>
+                Some("MSG") => {
+                    let len = parse_len(args.last(), &line)?;
+                    let ack_token = (args.len() == 5).then(|| args[3].to_string());
+                    let payload = self.read_payload(len).await?;
+                    return Ok(Some(Message { payload, ack_token }));
+                }
+                // HMSG <subject> <sid> [reply-to] <#header bytes> <#total bytes>
+                Some("HMSG") => {
+                    let headers_len = parse_len(args.get(args.len().saturating_sub(2)), &line)?;
+                    let len = parse_len(args.last(), &line)?;
+                    if headers_len > len {
+                        return Err(SourceError(format!("Malformed message from the server: {}", line)));
+                    }
+                    let ack_token = (args.len() == 6).then(|| args[3].to_string());
+                    let mut payload = self.read_payload(len).await?;
+                    payload.drain(..headers_len);
+                    return Ok(Some(Message { payload, ack_token }));
+                }
+                Some("PING") => self.write(b"PONG\r\n").await?,
+                Some("-ERR") => return Err(SourceError(line)),
+                _ => continue,
+            }
+        }
+    }
+
+    async fn ack(&mut self, message: &Message) -> Result<(), SourceError> {
+        self.reply(message, b"+ACK").await
+    }
+
+    async fn nack(&mut self, message: &Message) -> Result<(), SourceError> {
+        self.reply(message, b"-NAK").await
+    }
+
+    async fn dead_letter(&mut self, message: &Message, reason: &str) -> Result<(), SourceError> {
+        let reason = reason.replace(['\r', '\n'], " ");
+        let headers = format!("NATS/1.0\r\nL0-Dead-Letter-Reason: {}\r\n\r\n", reason);
+
+        let mut bytes = format!(
+            "HPUB {} {} {}\r\n",
+            self.dead_letter_subject,
+            headers.len(),
+            headers.len() + message.payload.len()
+        ).into_bytes();
+        bytes.extend_from_slice(headers.as_bytes());
+        bytes.extend_from_slice(&message.payload);
+        bytes.extend_from_slice(b"\r\n");
+        self.write(&bytes).await?;
+
+        self.reply(message, b"+TERM").await
+    }
+}
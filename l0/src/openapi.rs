@@ -0,0 +1,80 @@
+use utoipa::{openapi::{security::{Http, HttpAuthScheme, SecurityRequirement, SecurityScheme}, ContentBuilder, Ref, ResponseBuilder}, Modify, OpenApi};
+use utoipa_swagger_ui::SwaggerUi;
+
+// paths that do not need an API key
+const PUBLIC_PATHS: &[&str] = &["/healthz", "/readyz", "/metrics"];
+
+// OpenAPI 3 document of the HTTP API, generated from the handlers and the
+// serde models. tests.rs checks it against the JSON the service produces.
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "L0", description = "Demo service that stores and shows orders"),
+    paths(
+        crate::get_orders,
+        crate::post_order,
+        crate::bulk::import_orders,
+        crate::bulk::export_orders,
+        crate::events::order_events,
+        crate::events::order_socket,
+        crate::get_order,
+        crate::put_order,
+        crate::patch_order,
+        crate::delete_order,
+        crate::stats::get_revenue,
+        crate::stats::get_brands,
+        crate::stats::get_daily,
+        crate::auth::create_key,
+        crate::auth::list_keys,
+        crate::auth::revoke_key,
+        crate::webhooks::create_webhook,
+        crate::webhooks::list_webhooks,
+        crate::webhooks::delete_webhook,
+        crate::webhooks::list_deliveries,
+        crate::webhooks::retry_delivery,
+        crate::healthz,
+        crate::readyz,
+        crate::get_metrics,
+    ),
+    components(schemas(crate::validation::FieldError, crate::events::OrderEvent, crate::webhooks::WebhookEvent)),
+    modifiers(&ApiKeySecurity),
+    tags(
+        (name = "orders", description = "Orders stored by the service"),
+        (name = "stats", description = "Aggregates over stored orders"),
+        (name = "admin", description = "API keys and webhooks, need the admin scope"),
+        (name = "service", description = "Health checks and metrics"),
+    ),
+)]
+pub struct ApiDoc;
+
+// Documents the API key every path but PUBLIC_PATHS needs, and the errors
+// `auth::authenticate` and `limits::limit_requests` respond with.
+struct ApiKeySecurity;
+
+impl Modify for ApiKeySecurity {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        openapi.components.get_or_insert_with(Default::default)
+            .add_security_scheme("api_key", SecurityScheme::Http(Http::new(HttpAuthScheme::Bearer)));
+
+        let problem = |description: &str| ResponseBuilder::new()
+            .description(description)
+            .content("application/json", ContentBuilder::new().schema(Some(Ref::from_schema_name("Problem"))).build())
+            .build();
+        for (path, item) in openapi.paths.paths.iter_mut() {
+            if PUBLIC_PATHS.contains(&path.as_str()) {
+                continue;
+            }
+            for operation in [&mut item.get, &mut item.put, &mut item.post, &mut item.patch, &mut item.delete].into_iter().flatten() {
+                operation.security = Some(vec![SecurityRequirement::new("api_key", Vec::<String>::new())]);
+                let responses = &mut operation.responses.responses;
+                responses.entry(String::from("401")).or_insert_with(|| problem("No API key, or an unknown or revoked one").into());
+                responses.entry(String::from("403")).or_insert_with(|| problem("The API key lacks the scope").into());
+                responses.entry(String::from("429")).or_insert_with(|| problem("Too many requests, see the Retry-After header").into());
+            }
+        }
+    }
+}
+
+// GET /openapi.json and the Swagger UI at /docs, the UI files are compiled in.
+pub fn docs() -> SwaggerUi {
+    SwaggerUi::new("/docs").url("/openapi.json", ApiDoc::openapi())
+}
@@ -0,0 +1,220 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::Order;
+use crate::domain::{Locale, Timestamp};
+
+pub const DEFAULT_LIMIT: usize = 50;
+pub const MAX_LIMIT: usize = 1000;
+
+// Query string of GET /orders.
+#[derive(Deserialize, IntoParams, Default, Debug)]
+#[into_params(parameter_in = Query)]
+pub struct OrderQuery {
+    pub limit: Option<usize>,
+    // `next_cursor` of the previous page
+    pub after: Option<String>,
+    pub customer_id: Option<String>,
+    pub delivery_service: Option<String>,
+    pub locale: Option<Locale>,
+    // inclusive date_created range, RFC 3339
+    pub created_from: Option<String>,
+    pub created_to: Option<String>,
+    #[serde(default)]
+    pub sort: SortField,
+    #[serde(default)]
+    pub order: SortOrder,
+}
+
+#[derive(Deserialize, ToSchema, Default, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum SortField {
+    #[default]
+    DateCreated,
+    SmId,
+}
+
+#[derive(Deserialize, ToSchema, Default, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    #[default]
+    Asc,
+    Desc,
+}
+
+// A validated OrderQuery. Orders are sorted by `sort` and then by order_uid,
+// so every order has a unique position a cursor can point at.
+#[derive(Default, Debug)]
+pub struct OrderFilter {
+    pub limit: usize,
+    pub after: Option<Cursor>,
+    pub customer_id: Option<String>,
+    pub delivery_service: Option<String>,
+    pub locale: Option<Locale>,
+    pub created_from: Option<Timestamp>,
+    pub created_to: Option<Timestamp>,
+    pub sort: SortField,
+    pub order: SortOrder,
+}
+
+impl TryFrom<OrderQuery> for OrderFilter {
+    type Error = String;
+
+    fn try_from(query: OrderQuery) -> Result<Self, Self::Error> {
+        let limit = query.limit.unwrap_or(DEFAULT_LIMIT);
+        if limit == 0 || limit > MAX_LIMIT {
+            return Err(format!("limit must be between 1 and {}", MAX_LIMIT));
+        }
+
+        let after = match &query.after {
+            Some(cursor) => Some(Cursor::decode(cursor, query.sort)?),
+            None => None,
+        };
+
+        Ok(OrderFilter {
+            limit,
+            after,
+            customer_id: query.customer_id,
+            delivery_service: query.delivery_service,
+            locale: query.locale,
+            created_from: parse_timestamp("created_from", query.created_from)?,
+            created_to: parse_timestamp("created_to", query.created_to)?,
+            sort: query.sort,
+            order: query.order,
+        })
+    }
+}
+
+// Parses a bound of an inclusive date_created range.
+pub fn parse_timestamp(name: &str, value: Option<String>) -> Result<Option<Timestamp>, String> {
+    value.map(|value| value.parse().map_err(|_| {
+        format!("{} must be an RFC 3339 timestamp, e.g. 2021-11-26T06:22:19Z", name)
+    })).transpose()
+}
+
+// Used by the in-memory storage, the SQL backends build queries instead.
+impl OrderFilter {
+    pub fn matches(&self, order: &Order) -> bool {
+        fn equals<T: PartialEq>(expected: &Option<T>, value: &T) -> bool {
+            expected.as_ref().is_none_or(|expected| expected == value)
+        }
+
+        equals(&self.customer_id, &order.customer_id)
+            && equals(&self.delivery_service, &order.delivery_service)
+            && equals(&self.locale, &order.locale)
+            && self.created_from.as_ref().is_none_or(|from| order.date_created.utc() >= from.utc())
+            && self.created_to.as_ref().is_none_or(|to| order.date_created.utc() <= to.utc())
+            && self.after.as_ref().is_none_or(|cursor| self.compare(&cursor.position(), order).is_lt())
+    }
+
+    // Compares positions of orders in the requested sort order.
+    pub fn compare(&self, a: &(SortKey, String), b: &Order) -> std::cmp::Ordering {
+        let b = (SortKey::of(b, self.sort), b.order_uid.clone());
+        match self.order {
+            SortOrder::Asc => a.cmp(&b),
+            SortOrder::Desc => b.cmp(a),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum SortKey {
+    DateCreated(Timestamp),
+    SmId(i32),
+}
+
+// Orders created at the same instant are at the same position whatever
+// offsets they were sent with, like created_utc of the SQL backends.
+impl Ord for SortKey {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        match (self, other) {
+            (SortKey::DateCreated(a), SortKey::DateCreated(b)) => a.utc().cmp(&b.utc()),
+            (SortKey::SmId(a), SortKey::SmId(b)) => a.cmp(b),
+            (SortKey::DateCreated(_), SortKey::SmId(_)) => std::cmp::Ordering::Less,
+            (SortKey::SmId(_), SortKey::DateCreated(_)) => std::cmp::Ordering::Greater,
+        }
+    }
+}
+
+impl PartialOrd for SortKey {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for SortKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other).is_eq()
+    }
+}
+
+impl Eq for SortKey {}
+
+impl SortKey {
+    pub fn of(order: &Order, sort: SortField) -> Self {
+        match sort {
+            SortField::DateCreated => SortKey::DateCreated(order.date_created.clone()),
+            SortField::SmId => SortKey::SmId(order.sm_id),
+        }
+    }
+}
+
+// Position of the last order of a page, handed to clients as an opaque string.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct Cursor {
+    pub key: SortKey,
+    pub order_uid: String,
+}
+
+impl Cursor {
+    pub fn encode(&self) -> String {
+        URL_SAFE_NO_PAD.encode(serde_json::to_vec(self).unwrap_or_default())
+    }
+
+    pub fn decode(value: &str, sort: SortField) -> Result<Self, String> {
+        let cursor: Cursor = URL_SAFE_NO_PAD.decode(value).ok()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .ok_or_else(|| String::from("after is not a valid cursor"))?;
+
+        let matches_sort = matches!(
+            (&cursor.key, sort),
+            (SortKey::DateCreated(_), SortField::DateCreated) | (SortKey::SmId(_), SortField::SmId)
+        );
+        if !matches_sort {
+            return Err(String::from("after is a cursor for a different sort"));
+        }
+        Ok(cursor)
+    }
+}
+
+impl Cursor {
+    fn position(&self) -> (SortKey, String) {
+        (self.key.clone(), self.order_uid.clone())
+    }
+}
+
+#[derive(Serialize, Deserialize, ToSchema, PartialEq, Debug)]
+pub struct OrderPage {
+    pub orders: Vec<Order>,
+    // `None` on the last page
+    pub next_cursor: Option<String>,
+}
+
+impl OrderPage {
+    // Builds a page from up to `filter.limit + 1` sorted orders, the extra one
+    // only tells whether there is a next page.
+    pub fn new(mut orders: Vec<Order>, filter: &OrderFilter) -> Self {
+        let next_cursor = if orders.len() > filter.limit {
+            orders.truncate(filter.limit);
+            orders.last().map(|order| Cursor {
+                key: SortKey::of(order, filter.sort),
+                order_uid: order.order_uid.clone(),
+            }.encode())
+        } else {
+            None
+        };
+        OrderPage { orders, next_cursor }
+    }
+}
@@ -0,0 +1,689 @@
+use std::{collections::HashMap, fmt, sync::{Arc, Mutex, MutexGuard}};
+use async_trait::async_trait;
+use rusqlite::{params, params_from_iter, types::Value, Connection, OptionalExtension, Params, Row, TransactionBehavior};
+
+use crate::db::{self, Pool};
+use crate::domain::Currency;
+use crate::{Delivery, Item, Order, Payment};
+use crate::query::{OrderFilter, OrderPage, SortField, SortKey, SortOrder};
+use crate::stats::{self, BrandTotal, DailyOrders, DateRange, Revenue};
+use crate::webhooks::{self, OrderChange, Outbox};
+
+pub enum RepositoryError {
+    AlreadyExists(String),
+    NotFound(String),
+    Storage(String),
+}
+
+impl fmt::Display for RepositoryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::AlreadyExists(order_uid) => write!(f, "Order with order_uid={} already exists", order_uid),
+            Self::NotFound(order_uid) => write!(f, "Order with order_uid={} does not exist", order_uid),
+            Self::Storage(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl From<rusqlite::Error> for RepositoryError {
+    fn from(err: rusqlite::Error) -> Self {
+        RepositoryError::Storage(err.to_string())
+    }
+}
+
+impl From<r2d2::Error> for RepositoryError {
+    fn from(err: r2d2::Error) -> Self {
+        RepositoryError::Storage(err.to_string())
+    }
+}
+
+// Outcome of storing an order that may have been stored before.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Inserted {
+    Created,
+    // an identical order is already stored
+    Unchanged,
+}
+
+#[async_trait]
+pub trait OrderRepository: Send + Sync {
+    async fn list(&self) -> Result<Vec<Order>, RepositoryError>;
+    async fn get(&self, order_uid: &str) -> Result<Option<Order>, RepositoryError>;
+    async fn page(&self, filter: &OrderFilter) -> Result<OrderPage, RepositoryError>;
+    // Fails with AlreadyExists if a different or deleted order has the same order_uid.
+    // Every change of an order adds an event to the outbox of the webhooks in
+    // the same transaction.
+    async fn insert(&self, order: &Order) -> Result<Inserted, RepositoryError>;
+    // Inserts orders in one go, with an outcome per order. Fails as a whole
+    // only if the storage does.
+    async fn insert_batch(&self, orders: &[Order]) -> Result<Vec<Result<Inserted, RepositoryError>>, RepositoryError> {
+        let mut results = Vec::with_capacity(orders.len());
+        for order in orders {
+            results.push(self.insert(order).await);
+        }
+        Ok(results)
+    }
+    // Replaces a stored order, fails with NotFound if there is none.
+    async fn update(&self, order: &Order) -> Result<(), RepositoryError>;
+    // Marks an order as deleted, it is kept for audit but no longer returned.
+    async fn delete(&self, order_uid: &str) -> Result<(), RepositoryError>;
+    async fn count(&self) -> Result<u64, RepositoryError>;
+
+    // Aggregates behind GET /stats/*, by default computed from `list`.
+    async fn revenue(&self, range: &DateRange) -> Result<Vec<Revenue>, RepositoryError> {
+        Ok(stats::revenue(&self.list().await?, range))
+    }
+
+    async fn top_brands(&self, range: &DateRange, limit: usize) -> Result<Vec<BrandTotal>, RepositoryError> {
+        Ok(stats::top_brands(&self.list().await?, range, limit))
+    }
+
+    async fn daily_orders(&self, range: &DateRange) -> Result<Vec<DailyOrders>, RepositoryError> {
+        Ok(stats::daily_orders(&self.list().await?, range))
+    }
+
+    // Fails while the repository cannot serve requests, backs GET /readyz.
+    async fn ready(&self) -> Result<(), RepositoryError> {
+        Ok(())
+    }
+}
+
+// sqlite
+pub struct SqliteOrderRepository {
+    pool: Pool,
+}
+
+impl SqliteOrderRepository {
+    pub fn new(pool: Pool) -> Self {
+        SqliteOrderRepository { pool }
+    }
+
+    async fn with_connection<T, F>(&self, operation: &'static str, f: F) -> Result<T, RepositoryError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> Result<T, RepositoryError> + Send + 'static,
+    {
+        db::with_connection(&self.pool, operation, f).await
+    }
+}
+
+#[async_trait]
+impl OrderRepository for SqliteOrderRepository {
+    async fn list(&self) -> Result<Vec<Order>, RepositoryError> {
+        self.with_connection("list", |connection| {
+            let orders = load_orders(connection, &format!("WHERE {} ORDER BY orders.id", NOT_DELETED), [])?;
+            Ok(orders)
+        }).await
+    }
+
+    async fn page(&self, filter: &OrderFilter) -> Result<OrderPage, RepositoryError> {
+        let column = match filter.sort {
+            SortField::DateCreated => "orders.created_utc",
+            SortField::SmId => "orders.sm_id",
+        };
+        let (operator, direction) = match filter.order {
+            SortOrder::Asc => (">", "ASC"),
+            SortOrder::Desc => ("<", "DESC"),
+        };
+
+        let mut values: Vec<Value> = Vec::new();
+        let mut bind = |value: Value| {
+            values.push(value);
+            format!("?{}", values.len())
+        };
+
+        let mut conditions: Vec<String> = vec![String::from(NOT_DELETED)];
+        if let Some(customer_id) = &filter.customer_id {
+            conditions.push(format!("orders.customer_id = {}", bind(Value::from(customer_id.clone()))));
+        }
+        if let Some(delivery_service) = &filter.delivery_service {
+            conditions.push(format!("orders.delivery_service = {}", bind(Value::from(delivery_service.clone()))));
+        }
+        if let Some(locale) = &filter.locale {
+            conditions.push(format!("orders.locale = {}", bind(Value::from(locale.to_string()))));
+        }
+        if let Some(created_from) = &filter.created_from {
+            conditions.push(format!("orders.created_utc >= {}", bind(Value::from(created_from.sort_key()))));
+        }
+        if let Some(created_to) = &filter.created_to {
+            conditions.push(format!("orders.created_utc <= {}", bind(Value::from(created_to.sort_key()))));
+        }
+        if let Some(cursor) = &filter.after {
+            let key = bind(match &cursor.key {
+                SortKey::DateCreated(date_created) => Value::from(date_created.sort_key()),
+                SortKey::SmId(sm_id) => Value::from(*sm_id),
+            });
+            let order_uid = bind(Value::from(cursor.order_uid.clone()));
+            conditions.push(format!(
+                "({column} {operator} {key} OR ({column} = {key} AND orders.order_uid {operator} {order_uid}))",
+            ));
+        }
+
+        let clause = format!(
+            "WHERE {} ORDER BY {column} {direction}, orders.order_uid {direction} LIMIT {}",
+            conditions.join(" AND "),
+            filter.limit + 1
+        );
+
+        let orders = self.with_connection("page", move |connection| {
+            let orders = load_orders(connection, &clause, params_from_iter(values.iter()))?;
+            Ok(orders)
+        }).await?;
+        Ok(OrderPage::new(orders, filter))
+    }
+
+    async fn get(&self, order_uid: &str) -> Result<Option<Order>, RepositoryError> {
+        let order_uid = order_uid.to_string();
+        self.with_connection("get", move |connection| {
+            let orders = load_orders(connection, &format!("WHERE orders.order_uid = ?1 AND {}", NOT_DELETED), params![order_uid])?;
+            Ok(orders.into_iter().next())
+        }).await
+    }
+
+    async fn insert(&self, order: &Order) -> Result<Inserted, RepositoryError> {
+        let order = order.clone();
+        self.with_connection("insert", move |connection| {
+            // take the write lock up front, a deferred transaction could fail
+            // to upgrade its read lock while another connection writes
+            let transaction = connection.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            let inserted = insert_new_order(&transaction, &order)?;
+            transaction.commit()?;
+            Ok(inserted)
+        }).await
+    }
+
+    async fn insert_batch(&self, orders: &[Order]) -> Result<Vec<Result<Inserted, RepositoryError>>, RepositoryError> {
+        let orders = orders.to_vec();
+        self.with_connection("insert_batch", move |connection| {
+            let mut transaction = connection.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            let mut results = Vec::with_capacity(orders.len());
+            for order in &orders {
+                // a failed order must not leave half of its rows behind
+                let savepoint = transaction.savepoint()?;
+                let result = insert_new_order(&savepoint, order);
+                if result.is_ok() {
+                    savepoint.commit()?;
+                }
+                results.push(result);
+            }
+            transaction.commit()?;
+            Ok(results)
+        }).await
+    }
+
+    async fn update(&self, order: &Order) -> Result<(), RepositoryError> {
+        let order = order.clone();
+        self.with_connection("update", move |connection| {
+            let transaction = connection.transaction_with_behavior(TransactionBehavior::Immediate)?;
+
+            let updated = transaction.execute(
+                &format!(
+                    r#"UPDATE orders SET track_number = ?2, entry = ?3, locale = ?4, internal_signature = ?5, customer_id = ?6,
+                            delivery_service = ?7, shardkey = ?8, sm_id = ?9, date_created = ?10, oof_shard = ?11, created_utc = ?12,
+                            updated_at = CURRENT_TIMESTAMP
+                        WHERE order_uid = ?1 AND {}"#,
+                    NOT_DELETED
+                ),
+                params![
+                    order.order_uid,
+                    order.track_number,
+                    order.entry,
+                    order.locale,
+                    order.internal_signature,
+                    order.customer_id,
+                    order.delivery_service,
+                    order.shardkey,
+                    order.sm_id,
+                    order.date_created,
+                    order.oof_shard,
+                    order.date_created.sort_key()
+                ],
+            )?;
+            if updated == 0 {
+                return Err(RepositoryError::NotFound(order.order_uid.clone()));
+            }
+
+            for table in ["deliveries", "payments", "items"] {
+                transaction.execute(&format!("DELETE FROM {} WHERE order_uid = ?1", table), params![order.order_uid])?;
+            }
+            insert_order_details(&transaction, &order)?;
+            webhooks::insert_outbox_event(&transaction, OrderChange::Updated, &order)?;
+            transaction.commit()?;
+
+            Ok(())
+        }).await
+    }
+
+    async fn delete(&self, order_uid: &str) -> Result<(), RepositoryError> {
+        let order_uid = order_uid.to_string();
+        self.with_connection("delete", move |connection| {
+            let transaction = connection.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            let orders = load_orders(&transaction, &format!("WHERE orders.order_uid = ?1 AND {}", NOT_DELETED), params![order_uid])?;
+            let Some(order) = orders.first() else {
+                return Err(RepositoryError::NotFound(order_uid));
+            };
+            transaction.execute("UPDATE orders SET deleted_at = CURRENT_TIMESTAMP WHERE order_uid = ?1", params![order_uid])?;
+            webhooks::insert_outbox_event(&transaction, OrderChange::Deleted, order)?;
+            transaction.commit()?;
+            Ok(())
+        }).await
+    }
+
+    async fn count(&self) -> Result<u64, RepositoryError> {
+        self.with_connection("count", |connection| {
+            let count = connection.query_row(&format!("SELECT COUNT(*) FROM orders WHERE {}", NOT_DELETED), [], |row| row.get(0))?;
+            Ok(count)
+        }).await
+    }
+
+    async fn revenue(&self, range: &DateRange) -> Result<Vec<Revenue>, RepositoryError> {
+        let (conditions, values) = range_conditions(range);
+        self.with_connection("revenue", move |connection| {
+            let mut stmt = connection.prepare(&format!(
+                r#"SELECT orders.delivery_service, payments.currency, COUNT(*), SUM(payments.amount)
+                    FROM orders JOIN payments ON payments.order_uid = orders.order_uid
+                    WHERE {conditions}
+                    GROUP BY orders.delivery_service, payments.currency
+                    ORDER BY orders.delivery_service, payments.currency"#
+            ))?;
+            let rows = stmt.query_map(params_from_iter(values.iter()), |row| Ok(Revenue {
+                delivery_service: row.get(0)?,
+                currency: row.get(1)?,
+                orders: row.get(2)?,
+                amount: row.get(3)?,
+            }))?.collect::<rusqlite::Result<Vec<Revenue>>>()?;
+            Ok(rows)
+        }).await
+    }
+
+    async fn top_brands(&self, range: &DateRange, limit: usize) -> Result<Vec<BrandTotal>, RepositoryError> {
+        let (conditions, values) = range_conditions(range);
+        self.with_connection("top_brands", move |connection| {
+            let mut stmt = connection.prepare(&format!(
+                r#"SELECT brand, currency, items, total_price FROM (
+                        SELECT items.brand AS brand, payments.currency AS currency, COUNT(*) AS items, SUM(items.total_price) AS total_price,
+                            ROW_NUMBER() OVER (PARTITION BY payments.currency ORDER BY SUM(items.total_price) DESC, items.brand) AS rank
+                        FROM orders
+                            JOIN payments ON payments.order_uid = orders.order_uid
+                            JOIN items ON items.order_uid = orders.order_uid
+                        WHERE {conditions}
+                        GROUP BY items.brand, payments.currency
+                    )
+                    WHERE rank <= {limit}
+                    ORDER BY currency, rank"#
+            ))?;
+            let rows = stmt.query_map(params_from_iter(values.iter()), |row| Ok(BrandTotal {
+                brand: row.get(0)?,
+                currency: row.get(1)?,
+                items: row.get(2)?,
+                total_price: row.get(3)?,
+            }))?.collect::<rusqlite::Result<Vec<BrandTotal>>>()?;
+            Ok(rows)
+        }).await
+    }
+
+    async fn daily_orders(&self, range: &DateRange) -> Result<Vec<DailyOrders>, RepositoryError> {
+        let (conditions, values) = range_conditions(range);
+        self.with_connection("daily_orders", move |connection| {
+            let mut stmt = connection.prepare(&format!(
+                r#"SELECT date(orders.created_utc) AS day, payments.currency, COUNT(*), SUM(payments.amount)
+                    FROM orders JOIN payments ON payments.order_uid = orders.order_uid
+                    WHERE {conditions} AND day IS NOT NULL
+                    GROUP BY day, payments.currency
+                    ORDER BY day, payments.currency"#
+            ))?;
+            let rows = stmt.query_map(params_from_iter(values.iter()), |row| Ok(DailyOrders {
+                day: row.get(0)?,
+                currency: row.get(1)?,
+                orders: row.get(2)?,
+                amount: row.get(3)?,
+            }))?.collect::<rusqlite::Result<Vec<DailyOrders>>>()?;
+            Ok(rows)
+        }).await
+    }
+
+    async fn ready(&self) -> Result<(), RepositoryError> {
+        self.with_connection("ready", |connection| {
+            connection.query_row("SELECT 1", [], |_| Ok(()))?;
+            Ok(())
+        }).await
+    }
+}
+
+// Conditions on stored orders in `range` with their parameters.
+fn range_conditions(range: &DateRange) -> (String, Vec<Value>) {
+    let mut conditions = vec![String::from(NOT_DELETED)];
+    let mut values = Vec::new();
+    if let Some(created_from) = &range.created_from {
+        values.push(Value::from(created_from.sort_key()));
+        conditions.push(format!("orders.created_utc >= ?{}", values.len()));
+    }
+    if let Some(created_to) = &range.created_to {
+        values.push(Value::from(created_to.sort_key()));
+        conditions.push(format!("orders.created_utc <= ?{}", values.len()));
+    }
+    (conditions.join(" AND "), values)
+}
+
+// Stores `order` unless its order_uid is taken, like `OrderRepository::insert`.
+fn insert_new_order(connection: &Connection, order: &Order) -> Result<Inserted, RepositoryError> {
+    let deleted: Option<bool> = connection.query_row(
+        "SELECT deleted_at IS NOT NULL FROM orders WHERE order_uid = ?1",
+        params![order.order_uid],
+        |row| row.get(0),
+    ).optional()?;
+
+    match deleted {
+        None => {}
+        // a deleted order keeps its order_uid taken
+        Some(true) => return Err(RepositoryError::AlreadyExists(order.order_uid.clone())),
+        Some(false) => {
+            let stored = load_orders(connection, "WHERE orders.order_uid = ?1", params![order.order_uid])?;
+            return if stored.first() == Some(order) {
+                Ok(Inserted::Unchanged)
+            } else {
+                Err(RepositoryError::AlreadyExists(order.order_uid.clone()))
+            };
+        }
+    }
+
+    insert_order(connection, order)?;
+    webhooks::insert_outbox_event(connection, OrderChange::Created, order)?;
+    Ok(Inserted::Created)
+}
+
+fn insert_order(connection: &Connection, order: &Order) -> rusqlite::Result<()> {
+    connection.execute(
+        r#"INSERT INTO orders (order_uid, track_number, entry, locale, internal_signature, customer_id, delivery_service, shardkey, sm_id, date_created, oof_shard, created_utc)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)"#,
+        params![
+            order.order_uid,
+            order.track_number,
+            order.entry,
+            order.locale,
+            order.internal_signature,
+            order.customer_id,
+            order.delivery_service,
+            order.shardkey,
+            order.sm_id,
+            order.date_created,
+            order.oof_shard,
+            order.date_created.sort_key()
+        ],
+    )?;
+
+    insert_order_details(connection, order)
+}
+
+fn insert_order_details(connection: &Connection, order: &Order) -> rusqlite::Result<()> {
+    let delivery = &order.delivery;
+    connection.execute(
+        r#"INSERT INTO deliveries (order_uid, name, phone, zip, city, address, region, email)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)"#,
+        params![
+            order.order_uid,
+            delivery.name,
+            delivery.phone,
+            delivery.zip,
+            delivery.city,
+            delivery.address,
+            delivery.region,
+            delivery.email
+        ],
+    )?;
+
+    let payment = &order.payment;
+    connection.execute(
+        r#"INSERT INTO payments (order_uid, [transaction], request_id, currency, provider, amount, payment_dt, bank, delivery_cost, goods_total, custom_fee)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)"#,
+        params![
+            order.order_uid,
+            payment.transaction,
+            payment.request_id,
+            payment.currency,
+            payment.provider,
+            payment.amount,
+            payment.payment_dt,
+            payment.bank,
+            payment.delivery_cost,
+            payment.goods_total,
+            payment.custom_fee
+        ],
+    )?;
+
+    let mut stmt = connection.prepare(
+        r#"INSERT INTO items (order_uid, position, chrt_id, track_number, price, rid, name, sale, size, total_price, nm_id, brand, status)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)"#,
+    )?;
+    for (position, item) in order.items.iter().enumerate() {
+        stmt.execute(params![
+            order.order_uid,
+            position,
+            item.chrt_id,
+            item.track_number,
+            item.price,
+            item.rid,
+            item.name,
+            item.sale,
+            item.size,
+            item.total_price,
+            item.nm_id,
+            item.brand,
+            item.status
+        ])?;
+    }
+
+    Ok(())
+}
+
+// loader
+// Condition every query for stored orders includes, deleted orders are kept for audit only.
+const NOT_DELETED: &str = "orders.deleted_at IS NULL";
+
+const SELECT_ORDERS: &str = r#"
+    SELECT orders.order_uid, orders.track_number, orders.entry, orders.locale, orders.internal_signature,
+           orders.customer_id, orders.delivery_service, orders.shardkey, orders.sm_id, orders.date_created, orders.oof_shard,
+           deliveries.name, deliveries.phone, deliveries.zip, deliveries.city, deliveries.address, deliveries.region, deliveries.email,
+           payments.[transaction], payments.request_id, payments.currency, payments.provider, payments.amount, payments.payment_dt,
+           payments.bank, payments.delivery_cost, payments.goods_total, payments.custom_fee
+    FROM orders
+    JOIN deliveries ON deliveries.order_uid = orders.order_uid
+    JOIN payments ON payments.order_uid = orders.order_uid
+"#;
+
+const SELECT_ITEMS: &str = r#"
+    SELECT items.order_uid, items.chrt_id, items.track_number, items.price, items.rid, items.name, items.sale,
+           items.size, items.total_price, items.nm_id, items.brand, items.status, payments.currency
+    FROM items
+    JOIN payments ON payments.order_uid = items.order_uid
+    WHERE items.order_uid IN (SELECT orders.order_uid FROM orders
+"#;
+
+// Loads orders selected by `clause` (WHERE, ORDER BY and LIMIT over the `orders`
+// table) together with their delivery, payment and items.
+pub fn load_orders<P: Params + Clone>(connection: &Connection, clause: &str, params: P) -> Result<Vec<Order>, RepositoryError> {
+    let mut stmt = connection.prepare(&format!("{} {}", SELECT_ORDERS, clause))?;
+    let mut orders = Vec::new();
+    let mut rows = stmt.query(params.clone())?;
+    while let Some(row) = rows.next()? {
+        let order = order_from_row(row).map_err(|err| unreadable_order(row.get(0).ok(), &err))?;
+        orders.push(order);
+    }
+
+    let mut stmt = connection.prepare(&format!("{} {}) ORDER BY items.order_uid, items.position", SELECT_ITEMS, clause))?;
+    let mut items: HashMap<String, Vec<Item>> = HashMap::new();
+    let mut rows = stmt.query(params)?;
+    while let Some(row) = rows.next()? {
+        let order_uid: String = row.get(0)?;
+        items.entry(order_uid).or_default().push(item_from_row(row)?);
+    }
+
+    for order in orders.iter_mut() {
+        order.items = items.remove(&order.order_uid).unwrap_or_default();
+    }
+
+    Ok(orders)
+}
+
+// A stored order whose values the types reject, e.g. one changed by hand
+// after quarantine_invalid_orders has moved the others aside, fails the read
+// instead of silently going missing from pages, exports and the cache.
+pub fn unreadable_order(order_uid: Option<String>, err: &dyn fmt::Display) -> RepositoryError {
+    let order_uid = order_uid.unwrap_or_else(|| String::from("?"));
+    RepositoryError::Storage(format!("Stored order with order_uid={} cannot be loaded, fix or quarantine it: {}", order_uid, err))
+}
+
+fn order_from_row(row: &Row<'_>) -> rusqlite::Result<Order> {
+    let currency: Currency = row.get(20)?;
+    Ok(Order {
+        order_uid: row.get(0)?,
+        track_number: row.get(1)?,
+        entry: row.get(2)?,
+        locale: row.get(3)?,
+        internal_signature: row.get(4)?,
+        customer_id: row.get(5)?,
+        delivery_service: row.get(6)?,
+        shardkey: row.get(7)?,
+        sm_id: row.get(8)?,
+        date_created: row.get(9)?,
+        oof_shard: row.get(10)?,
+        delivery: Delivery {
+            name: row.get(11)?,
+            phone: row.get(12)?,
+            zip: row.get(13)?,
+            city: row.get(14)?,
+            address: row.get(15)?,
+            region: row.get(16)?,
+            email: row.get(17)?,
+        },
+        payment: Payment {
+            transaction: row.get(18)?,
+            request_id: row.get(19)?,
+            currency,
+            provider: row.get(21)?,
+            amount: currency.money(row.get(22)?),
+            payment_dt: row.get(23)?,
+            bank: row.get(24)?,
+            delivery_cost: currency.money(row.get(25)?),
+            goods_total: currency.money(row.get(26)?),
+            custom_fee: currency.money(row.get(27)?),
+        },
+        items: Vec::new(),
+    })
+}
+
+// Amounts are in the currency of the payment, the last column.
+fn item_from_row(row: &Row<'_>) -> rusqlite::Result<Item> {
+    let currency: Currency = row.get(12)?;
+    Ok(Item {
+        chrt_id: row.get(1)?,
+        track_number: row.get(2)?,
+        price: currency.money(row.get(3)?),
+        rid: row.get(4)?,
+        name: row.get(5)?,
+        sale: row.get(6)?,
+        size: row.get(7)?,
+        total_price: currency.money(row.get(8)?),
+        nm_id: row.get(9)?,
+        brand: row.get(10)?,
+        status: row.get(11)?,
+    })
+}
+
+// in-memory
+// Orders in a map, for tests and demos: nothing survives a restart.
+#[derive(Default)]
+pub struct InMemoryOrderRepository {
+    orders: Mutex<HashMap<String, StoredOrder>>,
+    // written while `orders` is locked
+    outbox: Arc<Outbox>,
+}
+
+struct StoredOrder {
+    // insertion order, like the id of SQL tables
+    id: u64,
+    order: Order,
+    // deleted orders keep their order_uid taken
+    deleted: bool,
+}
+
+impl InMemoryOrderRepository {
+    pub fn new() -> Self {
+        InMemoryOrderRepository::default()
+    }
+
+    pub fn outbox(&self) -> Arc<Outbox> {
+        Arc::clone(&self.outbox)
+    }
+
+    fn lock(&self) -> Result<MutexGuard<'_, HashMap<String, StoredOrder>>, RepositoryError> {
+        self.orders.lock().map_err(|err| RepositoryError::Storage(err.to_string()))
+    }
+}
+
+#[async_trait]
+impl OrderRepository for InMemoryOrderRepository {
+    async fn list(&self) -> Result<Vec<Order>, RepositoryError> {
+        let orders = self.lock()?;
+        let mut stored: Vec<&StoredOrder> = orders.values().filter(|stored| !stored.deleted).collect();
+        stored.sort_by_key(|stored| stored.id);
+        Ok(stored.into_iter().map(|stored| stored.order.clone()).collect())
+    }
+
+    async fn get(&self, order_uid: &str) -> Result<Option<Order>, RepositoryError> {
+        let orders = self.lock()?;
+        Ok(orders.get(order_uid).filter(|stored| !stored.deleted).map(|stored| stored.order.clone()))
+    }
+
+    async fn page(&self, filter: &OrderFilter) -> Result<OrderPage, RepositoryError> {
+        let orders = self.lock()?;
+
+        let mut orders: Vec<Order> = orders.values()
+            .filter(|stored| !stored.deleted && filter.matches(&stored.order))
+            .map(|stored| stored.order.clone())
+            .collect();
+        orders.sort_by(|a, b| filter.compare(&(SortKey::of(a, filter.sort), a.order_uid.clone()), b));
+        orders.truncate(filter.limit + 1);
+
+        Ok(OrderPage::new(orders, filter))
+    }
+
+    async fn insert(&self, order: &Order) -> Result<Inserted, RepositoryError> {
+        let mut orders = self.lock()?;
+        if let Some(stored) = orders.get(&order.order_uid) {
+            if !stored.deleted && stored.order == *order {
+                return Ok(Inserted::Unchanged);
+            }
+            return Err(RepositoryError::AlreadyExists(order.order_uid.clone()));
+        }
+        self.outbox.push(OrderChange::Created, order)?;
+        let id = orders.len() as u64 + 1;
+        orders.insert(order.order_uid.clone(), StoredOrder { id, order: order.clone(), deleted: false });
+        Ok(Inserted::Created)
+    }
+
+    async fn update(&self, order: &Order) -> Result<(), RepositoryError> {
+        let mut orders = self.lock()?;
+        let stored = orders.get_mut(&order.order_uid)
+            .filter(|stored| !stored.deleted)
+            .ok_or_else(|| RepositoryError::NotFound(order.order_uid.clone()))?;
+        self.outbox.push(OrderChange::Updated, order)?;
+        stored.order = order.clone();
+        Ok(())
+    }
+
+    async fn delete(&self, order_uid: &str) -> Result<(), RepositoryError> {
+        let mut orders = self.lock()?;
+        let stored = orders.get_mut(order_uid)
+            .filter(|stored| !stored.deleted)
+            .ok_or_else(|| RepositoryError::NotFound(order_uid.to_string()))?;
+        self.outbox.push(OrderChange::Deleted, &stored.order)?;
+        stored.deleted = true;
+        Ok(())
+    }
+
+    async fn count(&self) -> Result<u64, RepositoryError> {
+        let orders = self.lock()?;
+        Ok(orders.values().filter(|stored| !stored.deleted).count() as u64)
+    }
+}
@@ -0,0 +1,187 @@
+use std::{collections::BTreeMap, sync::Arc};
+use axum::{extract::{rejection::QueryRejection, Query}, Extension, Json};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::Order;
+use crate::domain::Timestamp;
+use crate::error::{AppError, Problem};
+use crate::query::parse_timestamp;
+use crate::repository::OrderRepository;
+
+pub const DEFAULT_BRANDS: usize = 10;
+pub const MAX_BRANDS: usize = 100;
+
+// Inclusive date_created range of the orders a statistic is computed over.
+#[derive(Default, Clone, Debug)]
+pub struct DateRange {
+    pub created_from: Option<Timestamp>,
+    pub created_to: Option<Timestamp>,
+}
+
+// Query string of GET /stats/revenue and GET /stats/daily.
+#[derive(Deserialize, IntoParams, Default, Debug)]
+#[into_params(parameter_in = Query)]
+pub struct RangeQuery {
+    // RFC 3339
+    pub created_from: Option<String>,
+    pub created_to: Option<String>,
+}
+
+impl TryFrom<RangeQuery> for DateRange {
+    type Error = String;
+
+    fn try_from(query: RangeQuery) -> Result<Self, Self::Error> {
+        Ok(DateRange {
+            created_from: parse_timestamp("created_from", query.created_from)?,
+            created_to: parse_timestamp("created_to", query.created_to)?,
+        })
+    }
+}
+
+impl DateRange {
+    pub fn contains(&self, order: &Order) -> bool {
+        self.created_from.as_ref().is_none_or(|from| order.date_created.utc() >= from.utc())
+            && self.created_to.as_ref().is_none_or(|to| order.date_created.utc() <= to.utc())
+    }
+}
+
+// Query string of GET /stats/brands.
+#[derive(Deserialize, IntoParams, Default, Debug)]
+#[into_params(parameter_in = Query)]
+pub struct BrandsQuery {
+    pub created_from: Option<String>,
+    pub created_to: Option<String>,
+    // brands per currency
+    pub limit: Option<usize>,
+}
+
+// Sum of payment.amount of the orders of a delivery service in one currency,
+// amounts in different currencies are never added together.
+#[derive(Serialize, Deserialize, ToSchema, PartialEq, Debug)]
+pub struct Revenue {
+    pub delivery_service: String,
+    pub currency: String,
+    pub orders: u64,
+    pub amount: u64,
+}
+
+// Sum of total_price of the items of a brand.
+#[derive(Serialize, Deserialize, ToSchema, PartialEq, Debug)]
+pub struct BrandTotal {
+    pub brand: String,
+    pub currency: String,
+    pub items: u64,
+    pub total_price: u64,
+}
+
+// Orders created on a day, UTC.
+#[derive(Serialize, Deserialize, ToSchema, PartialEq, Debug)]
+pub struct DailyOrders {
+    // YYYY-MM-DD
+    pub day: String,
+    pub currency: String,
+    pub orders: u64,
+    pub amount: u64,
+}
+
+// The functions below back the default implementations of the repository
+// methods and produce rows in the same order as the SQL queries.
+
+pub fn revenue(orders: &[Order], range: &DateRange) -> Vec<Revenue> {
+    let mut totals: BTreeMap<(&str, &str), (u64, u64)> = BTreeMap::new();
+    for order in orders.iter().filter(|order| range.contains(order)) {
+        let total = totals.entry((&order.delivery_service, order.payment.currency.as_str())).or_default();
+        total.0 += 1;
+        total.1 += u64::from(order.payment.amount);
+    }
+    totals.into_iter()
+        .map(|((delivery_service, currency), (orders, amount))| Revenue {
+            delivery_service: delivery_service.to_string(),
+            currency: currency.to_string(),
+            orders,
+            amount,
+        })
+        .collect()
+}
+
+// The `limit` brands with the highest total per currency.
+pub fn top_brands(orders: &[Order], range: &DateRange, limit: usize) -> Vec<BrandTotal> {
+    let mut totals: BTreeMap<&str, BTreeMap<&str, (u64, u64)>> = BTreeMap::new();
+    for order in orders.iter().filter(|order| range.contains(order)) {
+        let brands = totals.entry(order.payment.currency.as_str()).or_default();
+        for item in &order.items {
+            let total = brands.entry(&item.brand).or_default();
+            total.0 += 1;
+            total.1 += u64::from(item.total_price);
+        }
+    }
+
+    let mut rows = Vec::new();
+    for (currency, brands) in totals {
+        let mut brands: Vec<(&str, (u64, u64))> = brands.into_iter().collect();
+        brands.sort_by(|(a, (_, a_total)), (b, (_, b_total))| b_total.cmp(a_total).then(a.cmp(b)));
+        rows.extend(brands.into_iter().take(limit).map(|(brand, (items, total_price))| BrandTotal {
+            brand: brand.to_string(),
+            currency: currency.to_string(),
+            items,
+            total_price,
+        }));
+    }
+    rows
+}
+
+pub fn daily_orders(orders: &[Order], range: &DateRange) -> Vec<DailyOrders> {
+    let mut totals: BTreeMap<(String, &str), (u64, u64)> = BTreeMap::new();
+    for order in orders.iter().filter(|order| range.contains(order)) {
+        let day = order.date_created.utc().date_naive().to_string();
+        let total = totals.entry((day, order.payment.currency.as_str())).or_default();
+        total.0 += 1;
+        total.1 += u64::from(order.payment.amount);
+    }
+    totals.into_iter()
+        .map(|((day, currency), (orders, amount))| DailyOrders { day, currency: currency.to_string(), orders, amount })
+        .collect()
+}
+
+// handlers
+#[utoipa::path(get, path = "/stats/revenue", tag = "stats", params(RangeQuery),
+    responses(
+        (status = 200, body = Vec<Revenue>),
+        (status = 400, body = Problem),
+        (status = 500, body = Problem),
+    ))]
+pub async fn get_revenue(Extension(repository): Extension<Arc<dyn OrderRepository>>, query: Result<Query<RangeQuery>, QueryRejection>) -> Result<Json<Vec<Revenue>>, AppError> {
+    let Query(query) = query?;
+    let range = DateRange::try_from(query).map_err(AppError::InvalidQuery)?;
+    Ok(Json(repository.revenue(&range).await?))
+}
+
+#[utoipa::path(get, path = "/stats/brands", tag = "stats", params(BrandsQuery),
+    responses(
+        (status = 200, body = Vec<BrandTotal>),
+        (status = 400, body = Problem),
+        (status = 500, body = Problem),
+    ))]
+pub async fn get_brands(Extension(repository): Extension<Arc<dyn OrderRepository>>, query: Result<Query<BrandsQuery>, QueryRejection>) -> Result<Json<Vec<BrandTotal>>, AppError> {
+    let Query(query) = query?;
+    let limit = query.limit.unwrap_or(DEFAULT_BRANDS);
+    if limit == 0 || limit > MAX_BRANDS {
+        return Err(AppError::InvalidQuery(format!("limit must be between 1 and {}", MAX_BRANDS)));
+    }
+    let range = DateRange::try_from(RangeQuery { created_from: query.created_from, created_to: query.created_to })
+        .map_err(AppError::InvalidQuery)?;
+    Ok(Json(repository.top_brands(&range, limit).await?))
+}
+
+#[utoipa::path(get, path = "/stats/daily", tag = "stats", params(RangeQuery),
+    responses(
+        (status = 200, body = Vec<DailyOrders>),
+        (status = 400, body = Problem),
+        (status = 500, body = Problem),
+    ))]
+pub async fn get_daily(Extension(repository): Extension<Arc<dyn OrderRepository>>, query: Result<Query<RangeQuery>, QueryRejection>) -> Result<Json<Vec<DailyOrders>>, AppError> {
+    let Query(query) = query?;
+    let range = DateRange::try_from(query).map_err(AppError::InvalidQuery)?;
+    Ok(Json(repository.daily_orders(&range).await?))
+}
@@ -0,0 +1,109 @@
+use std::sync::Arc;
+
+use crate::auth::{InMemoryKeyRepository, KeyRepository, SqliteKeyRepository};
+use crate::config::{Backend, DatabaseConfig};
+use crate::db;
+use crate::migrations::{self, MigrationError, MigrationStatus};
+#[cfg(feature = "postgres")]
+use crate::postgres::{self, PostgresKeyRepository, PostgresOrderRepository, PostgresWebhookRepository};
+use crate::repository::{InMemoryOrderRepository, OrderRepository, RepositoryError, SqliteOrderRepository};
+use crate::webhooks::{InMemoryWebhookRepository, SqliteWebhookRepository, WebhookRepository};
+
+// The storage backend chosen by database.backend. Orders, API keys and
+// webhooks are always kept by the same backend, the outbox of the webhooks
+// is written with the orders.
+pub enum Storage {
+    Sqlite(db::Pool),
+    #[cfg(feature = "postgres")]
+    Postgres(postgres::Pool),
+    Memory(Arc<InMemoryOrderRepository>, Arc<InMemoryKeyRepository>, Arc<InMemoryWebhookRepository>),
+}
+
+impl Storage {
+    // Connects to the database, without migrating it.
+    pub fn open(config: &DatabaseConfig) -> Result<Self, String> {
+        match config.backend {
+            Backend::Sqlite => db::open_pool(&config.path, config.pool_size)
+                .map(Storage::Sqlite)
+                .map_err(|err| err.to_string()),
+            #[cfg(feature = "postgres")]
+            Backend::Postgres => postgres::open_pool(config.url.as_deref().unwrap_or_default(), config.pool_size).map(Storage::Postgres),
+            #[cfg(not(feature = "postgres"))]
+            Backend::Postgres => Err(String::from("l0 is built without the postgres feature")),
+            Backend::Memory => {
+                let orders = Arc::new(InMemoryOrderRepository::new());
+                let webhooks = Arc::new(InMemoryWebhookRepository::new(orders.outbox()));
+                Ok(Storage::Memory(orders, Arc::new(InMemoryKeyRepository::new()), webhooks))
+            }
+        }
+    }
+
+    pub fn backend(&self) -> Backend {
+        match self {
+            Storage::Sqlite(_) => Backend::Sqlite,
+            #[cfg(feature = "postgres")]
+            Storage::Postgres(_) => Backend::Postgres,
+            Storage::Memory(..) => Backend::Memory,
+        }
+    }
+
+    pub fn orders(&self) -> Arc<dyn OrderRepository> {
+        match self {
+            Storage::Sqlite(pool) => Arc::new(SqliteOrderRepository::new(pool.clone())),
+            #[cfg(feature = "postgres")]
+            Storage::Postgres(pool) => Arc::new(PostgresOrderRepository::new(pool.clone())),
+            Storage::Memory(orders, ..) => Arc::clone(orders) as Arc<dyn OrderRepository>,
+        }
+    }
+
+    pub fn keys(&self) -> Arc<dyn KeyRepository> {
+        match self {
+            Storage::Sqlite(pool) => Arc::new(SqliteKeyRepository::new(pool.clone())),
+            #[cfg(feature = "postgres")]
+            Storage::Postgres(pool) => Arc::new(PostgresKeyRepository::new(pool.clone())),
+            Storage::Memory(_, keys, _) => Arc::clone(keys) as Arc<dyn KeyRepository>,
+        }
+    }
+
+    pub fn webhooks(&self) -> Arc<dyn WebhookRepository> {
+        match self {
+            Storage::Sqlite(pool) => Arc::new(SqliteWebhookRepository::new(pool.clone())),
+            #[cfg(feature = "postgres")]
+            Storage::Postgres(pool) => Arc::new(PostgresWebhookRepository::new(pool.clone())),
+            Storage::Memory(.., webhooks) => Arc::clone(webhooks) as Arc<dyn WebhookRepository>,
+        }
+    }
+
+    // Applies the pending migrations up to `target`, the latest one by
+    // default. The in-memory storage has no schema.
+    pub async fn migrate(&self, target: Option<u32>) -> Result<Vec<u32>, MigrationError> {
+        match self {
+            Storage::Sqlite(pool) => migrations::migrate(&mut *pool.get()?, target),
+            #[cfg(feature = "postgres")]
+            Storage::Postgres(pool) => postgres::migrate(pool, target).await,
+            Storage::Memory(..) => Ok(Vec::new()),
+        }
+    }
+
+    pub async fn migration_status(&self) -> Result<Vec<MigrationStatus>, MigrationError> {
+        match self {
+            Storage::Sqlite(pool) => migrations::status(&*pool.get()?),
+            #[cfg(feature = "postgres")]
+            Storage::Postgres(pool) => postgres::status(pool).await,
+            Storage::Memory(..) => Ok(Vec::new()),
+        }
+    }
+
+    // Called once the server has stopped.
+    pub fn close(&self) -> Result<(), RepositoryError> {
+        match self {
+            Storage::Sqlite(pool) => db::checkpoint(pool),
+            #[cfg(feature = "postgres")]
+            Storage::Postgres(pool) => {
+                pool.close();
+                Ok(())
+            }
+            Storage::Memory(..) => Ok(()),
+        }
+    }
+}
@@ -1,59 +1,913 @@
+#[cfg(test)]
 use crate::*;
 
 #[cfg(test)]
+#[allow(clippy::module_inception)]
 mod tests {
-    use axum::{body::Body, http::Request};
+    use axum::{body::Body, http::{Request, StatusCode}};
     use tower::{Service, ServiceExt};
     use http_body_util::BodyExt;
     use super::*;
 
-    async fn drop_db(connection: &Connection) {
-        connection.execute("DROP TABLE IF EXISTS orders;", []).unwrap();
+    use std::{collections::HashMap, sync::atomic::{AtomicUsize, Ordering}};
+    use async_trait::async_trait;
+    use chrono::{TimeDelta, Utc};
+    use base64::{engine::general_purpose::STANDARD, Engine};
+    use tokio::{io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader}, net::TcpListener};
+    use crate::auth::{self, hash_key, ApiKey, InMemoryKeyRepository, IssuedApiKey, KeyRepository, Scope, REDACTED};
+    use crate::cache::{CacheConfig, EvictionPolicy, OrderCache};
+    use crate::config::{ConfigError, LogConfig, LogFormat, LogLevel};
+    use crate::error::Problem;
+    use crate::events::{EventFilter, EventsConfig, OrderEvent, OrderEventKind, PublishingOrderRepository};
+    use crate::ingest::{self, broker::InMemoryBroker};
+    use crate::limits::{Client, RateLimitConfig, RateLimiter, MAX_CLIENTS};
+    use crate::metrics::MeteredOrderRepository;
+    use crate::nats::{NatsConfig, NatsSource};
+    use utoipa::OpenApi;
+    use proptest::prelude::*;
+    use crate::repository::{InMemoryOrderRepository, RepositoryError, SqliteOrderRepository};
+    use crate::config::DatabaseConfig;
+    use rusqlite::Connection;
+    use crate::webhooks::{self, CreatedWebhook, DeliveryStatus, InMemoryWebhookRepository, OrderChange, Outbox, Webhook, WebhookDelivery, WebhookEvent, WebhooksConfig};
+
+    // Authentication and rate limiting have their own tests, the others send
+    // no API key and as many requests as they need.
+    fn test_config() -> Config {
+        let mut config = Config::default();
+        config.auth.enabled = false;
+        config.rate_limit.burst = 0;
+        config
+    }
+
+    fn test_keys() -> Arc<dyn KeyRepository> {
+        Arc::new(InMemoryKeyRepository::new())
+    }
+
+    fn test_webhooks() -> Arc<dyn WebhookRepository> {
+        Arc::new(InMemoryWebhookRepository::new(Arc::new(Outbox::default())))
     }
 
     async fn app() -> Router {
-        let connection: Connection = Connection::open("test_database.db").unwrap();
-        drop_db(&connection).await;
-        init_db(&connection).await;
-        let connection: Arc<Mutex<Connection>> = Arc::new(Mutex::new(connection));
-        init_app(&connection).await
+        let repository: Arc<dyn OrderRepository> = Arc::new(InMemoryOrderRepository::new());
+        init_app(&repository, &test_keys(), &test_webhooks(), &test_config(), &Metrics::new(), &OrderEvents::new(EventsConfig::default())).await
+    }
+
+    // Pooled connections to `:memory:` would each see their own database, so
+    // sqlite tests run against a fresh file in the temp directory.
+    fn temp_pool(size: u32) -> db::Pool {
+        static NEXT_DATABASE: AtomicUsize = AtomicUsize::new(0);
+
+        let path = std::env::temp_dir().join(format!(
+            "l0-test-{}-{}.db",
+            std::process::id(),
+            NEXT_DATABASE.fetch_add(1, Ordering::SeqCst)
+        ));
+        for suffix in ["", "-wal", "-shm"] {
+            std::fs::remove_file(format!("{}{}", path.display(), suffix)).ok();
+        }
+        db::open_pool(path.to_str().unwrap(), size).unwrap()
+    }
+
+    async fn sqlite_pool() -> db::Pool {
+        let pool = temp_pool(4);
+        migrations::migrate(&mut pool.get().unwrap(), None).unwrap();
+        pool
+    }
+
+    async fn sqlite_app() -> Router {
+        let repository: Arc<dyn OrderRepository> = Arc::new(SqliteOrderRepository::new(sqlite_pool().await));
+        init_app(&repository, &test_keys(), &test_webhooks(), &test_config(), &Metrics::new(), &OrderEvents::new(EventsConfig::default())).await
+    }
+
+    // Every schema gets a name of its own, like the sqlite files. The
+    // database is L0_TEST_DATABASE_URL, it has to exist.
+    #[cfg(feature = "postgres")]
+    async fn postgres_storage() -> Storage {
+        static NEXT_SCHEMA: AtomicUsize = AtomicUsize::new(0);
+
+        let url = std::env::var("L0_TEST_DATABASE_URL").unwrap_or_else(|_| String::from("postgres://postgres@localhost/l0_test"));
+        let schema = format!("l0_test_{}_{}", std::process::id(), NEXT_SCHEMA.fetch_add(1, Ordering::SeqCst));
+        let pool = postgres::open_pool(&url, 1).unwrap();
+        pool.get().await.unwrap()
+            .batch_execute(&format!("DROP SCHEMA IF EXISTS {schema} CASCADE; CREATE SCHEMA {schema};"))
+            .await
+            .unwrap();
+        let separator = if url.contains('?') { '&' } else { '?' };
+        Storage::Postgres(postgres::open_pool(&format!("{url}{separator}options=-csearch_path%3D{schema}"), 4).unwrap())
+    }
+
+    // A fresh, migrated storage of every backend, PostgreSQL only with the
+    // postgres feature. Tests that do not depend on the backend run on each.
+    async fn test_storages() -> Vec<Storage> {
+        let memory = DatabaseConfig { backend: Backend::Memory, ..DatabaseConfig::default() };
+        #[allow(unused_mut)]
+        let mut storages = vec![Storage::open(&memory).unwrap(), Storage::Sqlite(temp_pool(4))];
+        #[cfg(feature = "postgres")]
+        storages.push(postgres_storage().await);
+        for storage in &storages {
+            storage.migrate(None).await.unwrap();
+        }
+        storages
+    }
+
+    async fn storage_app(storage: &Storage, config: &Config) -> Router {
+        init_app(&storage.orders(), &storage.keys(), &storage.webhooks(), config, &Metrics::new(), &OrderEvents::new(EventsConfig::default())).await
+    }
+
+    async fn backend_apps() -> Vec<Router> {
+        let mut apps = Vec::new();
+        for storage in test_storages().await {
+            apps.push(storage_app(&storage, &test_config()).await);
+        }
+        apps
     }
 
     #[tokio::test]
     async fn get_empty_orders_test() {
-        let app = app().await;
+        for app in backend_apps().await {
+            let response = app
+                .oneshot(
+                    Request::builder()
+                    .uri("/orders")
+                    .body(Body::empty())
+                    .unwrap()
+                )
+                .await
+                .unwrap();
+
+            assert_eq!(response.status(), StatusCode::OK);
+
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            let body: OrderPage = serde_json::from_slice(&body).unwrap();
+
+            assert!(body.orders.is_empty());
+            assert_eq!(body.next_cursor, None);
+        }
+    }
+
+    #[tokio::test]
+    async fn post_get_order_test() {
+        for app in backend_apps().await {
+            let mut app = app.into_service();
+
+            let order = sample_order("1");
+
+            // post order
+            let request = Request::builder()
+                .method("POST")
+                .uri("/orders")
+                .header("content-type", "application/json")
+                .body(Body::new(serde_json::to_string(&order).unwrap()))
+                .unwrap();
+
+            let response = ServiceExt::<Request<Body>>::ready(&mut app)
+                .await
+                .unwrap()
+                .call(request)
+                .await
+                .unwrap();
+
+            assert_eq!(response.status(), StatusCode::CREATED);
+
+            // get order
+            let request = Request::builder()
+                .uri("/orders/1")
+                .body(Body::empty())
+                .unwrap();
+
+            let response = ServiceExt::<Request<Body>>::ready(&mut app)
+                .await
+                .unwrap()
+                .call(request)
+                .await
+                .unwrap();
+
+            assert_eq!(response.status(), StatusCode::OK);
+
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            let body: Order = serde_json::from_slice(&body).unwrap();
+
+            assert_eq!(order, body);
+        }
+    }
+
+    #[tokio::test]
+    async fn get_existing_order_test() {
+        for app in backend_apps().await {
+            let mut app = app.into_service();
+
+            let order = sample_order("1");
+            let content = serde_json::to_string(&order).unwrap();
+
+            // post order
+            let request = Request::builder()
+                .method("POST")
+                .uri("/orders")
+                .header("content-type", "application/json")
+                .body(Body::new(content.clone()))
+                .unwrap();
+
+            let response = ServiceExt::<Request<Body>>::ready(&mut app)
+                .await
+                .unwrap()
+                .call(request)
+                .await
+                .unwrap();
+
+            assert_eq!(response.status(), StatusCode::CREATED);
+            assert_eq!(response.headers()["location"], "/orders/1");
+
+            // post the same order again
+            let request = Request::builder()
+                .method("POST")
+                .uri("/orders")
+                .header("content-type", "application/json")
+                .body(Body::new(content))
+                .unwrap();
+
+            let response = ServiceExt::<Request<Body>>::ready(&mut app)
+                .await
+                .unwrap()
+                .call(request)
+                .await
+                .unwrap();
+
+            assert_eq!(response.status(), StatusCode::OK);
+
+            // post a different order with the same order_uid
+            let mut conflicting = order.clone();
+            conflicting.customer_id = String::from("someone else");
+            let request = Request::builder()
+                .method("POST")
+                .uri("/orders")
+                .header("content-type", "application/json")
+                .body(Body::new(serde_json::to_string(&conflicting).unwrap()))
+                .unwrap();
+
+            let response = ServiceExt::<Request<Body>>::ready(&mut app)
+                .await
+                .unwrap()
+                .call(request)
+                .await
+                .unwrap();
+
+            assert_eq!(response.status(), StatusCode::CONFLICT);
 
-        let response = app
-            .oneshot(
-                Request::builder()
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            let body: Problem = serde_json::from_slice(&body).unwrap();
+
+            assert_eq!(body.code, "order_already_exists");
+        }
+    }
+
+    #[tokio::test]
+    async fn get_missing_order_test() {
+        for app in backend_apps().await {
+            let response = app
+                .oneshot(
+                    Request::builder()
+                    .uri("/orders/1")
+                    .body(Body::empty())
+                    .unwrap()
+                )
+                .await
+                .unwrap();
+
+            assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            let body: Problem = serde_json::from_slice(&body).unwrap();
+
+            assert_eq!(body, Problem {
+                code: String::from("order_not_found"),
+                message: String::from("Order with order_uid=1 does not exist"),
+                detail: None,
+            });
+        }
+    }
+
+    #[tokio::test]
+    async fn post_malformed_order_test() {
+        for app in backend_apps().await {
+            let response = app
+                .oneshot(
+                    Request::builder()
+                    .method("POST")
+                    .uri("/orders")
+                    .header("content-type", "application/json")
+                    .body(Body::new(String::from("{\"order_uid\": ")))
+                    .unwrap()
+                )
+                .await
+                .unwrap();
+
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            let body: Problem = serde_json::from_slice(&body).unwrap();
+
+            assert_eq!(body.code, "invalid_json");
+            assert!(body.detail.is_some());
+        }
+    }
+
+    #[tokio::test]
+    async fn post_get_order_with_quotes_test() {
+        for app in backend_apps().await {
+            let mut app = app.into_service();
+
+            let order = sample_order("1'); DROP TABLE orders; --");
+
+            // post order
+            let request = Request::builder()
+                .method("POST")
+                .uri("/orders")
+                .header("content-type", "application/json")
+                .body(Body::new(serde_json::to_string(&order).unwrap()))
+                .unwrap();
+
+            let response = ServiceExt::<Request<Body>>::ready(&mut app)
+                .await
+                .unwrap()
+                .call(request)
+                .await
+                .unwrap();
+
+            assert_eq!(response.status(), StatusCode::CREATED);
+
+            // get order
+            let request = Request::builder()
+                .uri("/orders/1'%29%3B%20DROP%20TABLE%20orders%3B%20--")
+                .body(Body::empty())
+                .unwrap();
+
+            let response = ServiceExt::<Request<Body>>::ready(&mut app)
+                .await
+                .unwrap()
+                .call(request)
+                .await
+                .unwrap();
+
+            assert_eq!(response.status(), StatusCode::OK);
+
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            let body: Order = serde_json::from_slice(&body).unwrap();
+
+            assert_eq!(order, body);
+
+            // get orders
+            let request = Request::builder()
+                .uri("/orders")
+                .body(Body::empty())
+                .unwrap();
+
+            let response = ServiceExt::<Request<Body>>::ready(&mut app)
+                .await
+                .unwrap()
+                .call(request)
+                .await
+                .unwrap();
+
+            assert_eq!(response.status(), StatusCode::OK);
+
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            let body: OrderPage = serde_json::from_slice(&body).unwrap();
+
+            assert_eq!(vec![order], body.orders);
+        }
+    }
+
+    // `order` as JSON with the value at `pointer` replaced, for values the
+    // typed fields of Order cannot hold.
+    fn order_json_with(order: &Order, pointer: &str, value: Value) -> String {
+        let mut json = serde_json::to_value(order).unwrap();
+        *json.pointer_mut(pointer).unwrap() = value;
+        json.to_string()
+    }
+
+    fn sample_order(order_uid: &str) -> Order {
+        let usd: Currency = "USD".parse().unwrap();
+        let item = |chrt_id: i64, brand: &str| Item {
+            chrt_id,
+            track_number: String::from("WBILMTESTTRACK"),
+            price: usd.money(453),
+            rid: format!("{}-{}", order_uid, chrt_id),
+            name: String::from("Mascaras"),
+            sale: 30,
+            size: String::from("0"),
+            total_price: usd.money(317),
+            nm_id: 2389212,
+            brand: String::from(brand),
+            status: 202,
+        };
+
+        Order {
+            order_uid: String::from(order_uid),
+            track_number: String::from("WBILMTESTTRACK"),
+            entry: String::from("WBIL"),
+            delivery: Delivery {
+                name: String::from("Test Testov"),
+                phone: String::from("+9720000000"),
+                zip: String::from("2639809"),
+                city: String::from("Kiryat Mozkin"),
+                address: String::from("Ploshad Mira 15"),
+                region: String::from("Kraiot"),
+                email: String::from("test@gmail.com"),
+            },
+            payment: Payment {
+                transaction: String::from(order_uid),
+                request_id: String::new(),
+                currency: usd,
+                provider: String::from("wbpay"),
+                amount: usd.money(1817),
+                payment_dt: UnixTime::from_seconds(1637907727).unwrap(),
+                bank: String::from("alpha"),
+                delivery_cost: usd.money(1500),
+                goods_total: usd.money(317),
+                custom_fee: usd.money(0),
+            },
+            items: vec![item(9934930, "Vivienne Sabo"), item(9934931, "Maybelline")],
+            locale: Locale::En,
+            internal_signature: String::new(),
+            customer_id: String::from("test"),
+            delivery_service: String::from("meest"),
+            shardkey: String::from("9"),
+            sm_id: 99,
+            date_created: "2021-11-26T06:22:19Z".parse().unwrap(),
+            oof_shard: String::from("1"),
+        }
+    }
+
+    #[tokio::test]
+    async fn post_get_orders_test() {
+        for app in backend_apps().await {
+            let mut app = app.into_service();
+
+            let orders = vec![sample_order("b563feb7b2b84b6test"), sample_order("c663feb7b2b84b6test")];
+
+            for order in orders.iter() {
+                // post order
+                let request = Request::builder()
+                    .method("POST")
+                    .uri("/orders")
+                    .header("content-type", "application/json")
+                    .body(Body::new(serde_json::to_string(order).unwrap()))
+                    .unwrap();
+
+                let response = ServiceExt::<Request<Body>>::ready(&mut app)
+                    .await
+                    .unwrap()
+                    .call(request)
+                    .await
+                    .unwrap();
+
+                assert_eq!(response.status(), StatusCode::CREATED);
+            }
+
+            // post the same order again, then a different one with the same order_uid
+            let mut conflicting = orders[0].clone();
+            conflicting.items[1].status = 0;
+            for (order, status) in [(&orders[0], StatusCode::OK), (&conflicting, StatusCode::CONFLICT)] {
+                let request = Request::builder()
+                    .method("POST")
+                    .uri("/orders")
+                    .header("content-type", "application/json")
+                    .body(Body::new(serde_json::to_string(order).unwrap()))
+                    .unwrap();
+
+                let response = ServiceExt::<Request<Body>>::ready(&mut app)
+                    .await
+                    .unwrap()
+                    .call(request)
+                    .await
+                    .unwrap();
+
+                assert_eq!(response.status(), status);
+            }
+
+            // get orders
+            let request = Request::builder()
                 .uri("/orders")
                 .body(Body::empty())
+                .unwrap();
+
+            let response = ServiceExt::<Request<Body>>::ready(&mut app)
+                .await
                 .unwrap()
-            )
+                .call(request)
+                .await
+                .unwrap();
+
+            assert_eq!(response.status(), StatusCode::OK);
+
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            let body: OrderPage = serde_json::from_slice(&body).unwrap();
+
+            assert_eq!(orders, body.orders);
+        }
+    }
+
+    #[tokio::test]
+    async fn migrate_legacy_orders_test() {
+        let pool = temp_pool(1);
+        let mut connection = pool.get().unwrap();
+        assert_eq!(migrations::migrate(&mut connection, Some(1)).unwrap(), vec![1]);
+
+        // legacy rows keep delivery, payment and items as JSON
+        let order = sample_order("b563feb7b2b84b6test");
+        connection.execute(
+            r#"INSERT INTO orders (order_uid, track_number, entry, delivery, payment, items, locale, internal_signature, customer_id, delivery_service, shardkey, sm_id, date_created, oof_shard)
+                    VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)"#,
+            rusqlite::params![
+                order.order_uid,
+                order.track_number,
+                order.entry,
+                serde_json::to_string(&order.delivery).unwrap(),
+                serde_json::to_string(&order.payment).unwrap(),
+                serde_json::to_string(&order.items).unwrap(),
+                order.locale,
+                order.internal_signature,
+                order.customer_id,
+                order.delivery_service,
+                order.shardkey,
+                order.sm_id,
+                order.date_created,
+                order.oof_shard
+            ],
+        ).unwrap();
+
+        migrations::migrate(&mut connection, None).unwrap();
+        assert_eq!(migrations::current_version(&connection).unwrap(), migrations::latest(migrations::MIGRATIONS));
+        let created_utc: String = connection.query_row("SELECT created_utc FROM orders", [], |row| row.get(0)).unwrap();
+        assert_eq!(created_utc, order.date_created.sort_key());
+
+        drop(connection);
+
+        let repository = SqliteOrderRepository::new(pool);
+        let migrated = repository.get(&order.order_uid).await.ok().flatten();
+
+        assert_eq!(Some(order), migrated);
+    }
+
+    #[tokio::test]
+    async fn migrate_invalid_legacy_orders_test() {
+        let pool = temp_pool(2);
+        let mut connection = pool.get().unwrap();
+        assert_eq!(migrations::migrate(&mut connection, Some(1)).unwrap(), vec![1]);
+
+        // values stored before the fields were typed
+        let legacy = [
+            ("a", "en", "USD", "2021-11-26T06:22:19Z"),
+            ("b", " EN ", "usd", "2021-11-26T06:22:19Z"),
+            ("c", "", "USD", "2021-11-26T06:22:19Z"),
+            ("d", "en", "", "yesterday"),
+        ];
+        for (order_uid, locale, currency, date_created) in legacy {
+            let order = serde_json::from_str::<Value>(&order_json_with(&sample_order(order_uid), "/payment/currency", json!(currency))).unwrap();
+            connection.execute(
+                r#"INSERT INTO orders (order_uid, track_number, entry, delivery, payment, items, locale, internal_signature, customer_id, delivery_service, shardkey, sm_id, date_created, oof_shard)
+                        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)"#,
+                rusqlite::params![
+                    order_uid,
+                    order["track_number"].as_str(),
+                    order["entry"].as_str(),
+                    order["delivery"].to_string(),
+                    order["payment"].to_string(),
+                    order["items"].to_string(),
+                    locale,
+                    order["internal_signature"].as_str(),
+                    order["customer_id"].as_str(),
+                    order["delivery_service"].as_str(),
+                    order["shardkey"].as_str(),
+                    order["sm_id"].as_i64(),
+                    date_created,
+                    order["oof_shard"].as_str()
+                ],
+            ).unwrap();
+        }
+
+        // values that differ in case or spaces are fixed, the other orders are moved aside
+        migrations::migrate(&mut connection, None).unwrap();
+        let mut stmt = connection.prepare("SELECT order_uid, reason, [order] FROM quarantined_orders ORDER BY order_uid").unwrap();
+        let quarantined: Vec<(String, String, String)> = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        let reasons: Vec<(&str, &str)> = quarantined.iter().map(|(order_uid, reason, _)| (order_uid.as_str(), reason.as_str())).collect();
+        assert_eq!(reasons, [("c", "locale"), ("d", "payment.currency date_created")]);
+        // the JSON of a quarantined order is imported again once it is corrected
+        let Err(validation::InvalidOrder::Rules(errors)) = validation::parse_order(quarantined[0].2.as_bytes()) else {
+            panic!("{} is not an order with an invalid locale", quarantined[0].2);
+        };
+        let errors: Vec<(&str, &str)> = errors.iter().map(|error| (error.field.as_str(), error.rule)).collect();
+        assert_eq!(errors, [("locale", "locale")]);
+        let mut fixed: Value = serde_json::from_str(&quarantined[0].2).unwrap();
+        fixed["locale"] = json!("en");
+        assert_eq!(validation::parse_order(fixed.to_string().as_bytes()).ok(), Some(sample_order("c")));
+        drop(stmt);
+
+        let repository = Arc::new(SqliteOrderRepository::new(pool.clone()));
+        let orders = repository.list().await.unwrap_or_else(|err| panic!("{}", err));
+        assert_eq!(orders, [sample_order("a"), sample_order("b")]);
+        let cached = CachedOrderRepository::new(repository.clone(), CacheConfig::default(), Metrics::new());
+        assert_eq!(cached.warm_up().await.ok(), Some(2));
+
+        // an order broken after the migration fails every read of it with its order_uid
+        connection.execute("UPDATE orders SET locale = 'xx' WHERE order_uid = 'a'", []).unwrap();
+        let cached = CachedOrderRepository::new(repository.clone(), CacheConfig::default(), Metrics::new());
+        assert!(matches!(cached.warm_up().await, Err(RepositoryError::Storage(err)) if err.contains("order_uid=a ")));
+        // the service keeps running but is not ready
+        assert!(matches!(cached.ready().await, Err(RepositoryError::Storage(err)) if err.starts_with("the cache could not be warmed up") && err.contains("order_uid=a ")));
+        assert!(matches!(repository.get("a").await, Err(RepositoryError::Storage(err)) if err.contains("order_uid=a ")));
+        let filter = OrderFilter::try_from(OrderQuery { limit: Some(1), ..OrderQuery::default() }).unwrap();
+        assert!(matches!(repository.page(&filter).await, Err(RepositoryError::Storage(err)) if err.contains("order_uid=a ")));
+    }
+
+    #[cfg(feature = "postgres")]
+    #[tokio::test]
+    async fn postgres_migrate_test() {
+        let storage = postgres_storage().await;
+        let status = storage.migration_status().await.unwrap();
+        assert_eq!(status.len(), postgres::MIGRATIONS.len());
+        assert!(status.iter().all(|migration| migration.applied_at.is_none()));
+
+        // servers starting at once apply each migration once
+        let (first, second) = tokio::join!(storage.migrate(None), storage.migrate(None));
+        let mut applied = [first.unwrap(), second.unwrap()].concat();
+        applied.sort();
+        assert_eq!(applied, (1..=migrations::latest(postgres::MIGRATIONS)).collect::<Vec<u32>>());
+        assert!(storage.migration_status().await.unwrap().iter().all(|migration| migration.applied_at.is_some()));
+
+        assert!(matches!(storage.migrate(Some(1)).await, Err(migrations::MigrationError::Downgrade { .. })));
+        assert!(matches!(
+            storage.migrate(Some(migrations::latest(postgres::MIGRATIONS) + 1)).await,
+            Err(migrations::MigrationError::UnknownVersion { .. })
+        ));
+
+        // orders get serial ids, details are JSONB documents
+        let repository = storage.orders();
+        for order_uid in ["b", "a"] {
+            assert!(repository.insert(&sample_order(order_uid)).await.is_ok());
+        }
+        let Storage::Postgres(pool) = &storage else {
+            unreachable!();
+        };
+        let rows = pool.get().await.unwrap()
+            .query("SELECT id, order_uid, jsonb_typeof(items), items->0->>'brand' FROM orders ORDER BY id", &[])
             .await
             .unwrap();
+        let rows: Vec<(i64, String, String, String)> = rows.iter().map(|row| (row.get(0), row.get(1), row.get(2), row.get(3))).collect();
+        let brand = sample_order("a").items[0].brand.clone();
+        assert_eq!(rows, [(1, String::from("b"), String::from("array"), brand.clone()), (2, String::from("a"), String::from("array"), brand)]);
+    }
 
-        assert_eq!(response.status(), StatusCode::OK);
+    #[cfg(feature = "postgres")]
+    #[tokio::test]
+    async fn postgres_migrate_invalid_orders_test() {
+        let storage = postgres_storage().await;
+        storage.migrate(Some(4)).await.unwrap();
+        let Storage::Postgres(pool) = &storage else {
+            unreachable!();
+        };
 
-        let body = response.into_body().collect().await.unwrap().to_bytes();
-        let body: [Order;0] = serde_json::from_slice(&body).unwrap();
+        // values stored before the fields were typed
+        let legacy = [
+            ("a", "en", "USD", "2021-11-26T06:22:19Z"),
+            ("b", " EN ", "usd", "2021-11-26T06:22:19Z"),
+            ("c", "", "USD", "2021-11-26T06:22:19Z"),
+            ("d", "en", "", "yesterday"),
+        ];
+        let client = pool.get().await.unwrap();
+        for (order_uid, locale, currency, date_created) in legacy {
+            let mut order = serde_json::to_value(sample_order(order_uid)).unwrap();
+            order["locale"] = json!(locale);
+            order["payment"]["currency"] = json!(currency);
+            order["date_created"] = json!(date_created);
+            client.execute(
+                r#"INSERT INTO orders (order_uid, track_number, entry, delivery, payment, items, locale, internal_signature, customer_id,
+                                       delivery_service, shardkey, sm_id, date_created, created_utc, oof_shard)
+                   SELECT o->>'order_uid', o->>'track_number', o->>'entry', o->'delivery', o->'payment', o->'items', o->>'locale',
+                          o->>'internal_signature', o->>'customer_id', o->>'delivery_service', o->>'shardkey', (o->>'sm_id')::integer,
+                          o->>'date_created', now(), o->>'oof_shard'
+                   FROM (SELECT $1::jsonb AS o) AS input"#,
+                &[&order],
+            ).await.unwrap();
+        }
 
-        assert!(body.is_empty());
+        storage.migrate(None).await.unwrap();
+        let rows = client.query("SELECT order_uid, reason, \"order\"->>'locale' FROM quarantined_orders ORDER BY order_uid", &[]).await.unwrap();
+        let quarantined: Vec<(String, String, String)> = rows.iter().map(|row| (row.get(0), row.get(1), row.get(2))).collect();
+        assert_eq!(quarantined, [
+            (String::from("c"), String::from("locale"), String::new()),
+            (String::from("d"), String::from("payment.currency date_created"), String::from("en")),
+        ]);
+        let orders = storage.orders().list().await.unwrap_or_else(|err| panic!("{}", err));
+        assert_eq!(orders, [sample_order("a"), sample_order("b")]);
+
+        // an order broken after the migration fails every read of it with its order_uid
+        client.execute("UPDATE orders SET locale = 'xx' WHERE order_uid = 'a'", &[]).await.unwrap();
+        let cached = CachedOrderRepository::new(storage.orders(), CacheConfig::default(), Metrics::new());
+        assert!(matches!(cached.warm_up().await, Err(RepositoryError::Storage(err)) if err.contains("order_uid=a ")));
+        assert!(matches!(storage.orders().get("a").await, Err(RepositoryError::Storage(err)) if err.contains("order_uid=a ")));
     }
 
     #[tokio::test]
-    async fn post_get_order_test() {
-        let mut app = app().await.into_service();
+    async fn migrate_status_test() {
+        let mut connection: Connection = Connection::open_in_memory().unwrap();
+
+        let status = migrations::status(&connection).unwrap();
+        assert!(status.iter().all(|migration| migration.applied_at.is_none()));
+
+        migrations::migrate(&mut connection, None).unwrap();
+        assert!(migrations::migrate(&mut connection, None).unwrap().is_empty());
+
+        let status = migrations::status(&connection).unwrap();
+        assert_eq!(status.len(), migrations::MIGRATIONS.len());
+        assert!(status.iter().all(|migration| migration.applied_at.is_some()));
+
+        assert!(matches!(
+            migrations::migrate(&mut connection, Some(1)),
+            Err(migrations::MigrationError::Downgrade { .. })
+        ));
+        assert!(matches!(
+            migrations::migrate(&mut connection, Some(migrations::latest(migrations::MIGRATIONS) + 1)),
+            Err(migrations::MigrationError::UnknownVersion { .. })
+        ));
+    }
+
+    #[test]
+    fn cache_eviction_test() {
+        for (policy, evicted) in [(EvictionPolicy::Lru, "2"), (EvictionPolicy::Fifo, "1")] {
+            let cache = OrderCache::new(CacheConfig { capacity: 2, policy });
+            cache.fill().put(sample_order("1"));
+            cache.fill().put(sample_order("2"));
+            assert!(cache.get("1").is_some());
+
+            cache.fill().put(sample_order("3"));
+
+            assert_eq!(cache.len(), 2);
+            assert!(cache.get(evicted).is_none(), "{:?} should evict order {}", policy, evicted);
+            assert!(cache.get("3").is_some());
+        }
+    }
+
+    #[test]
+    fn cache_invalidation_test() {
+        let cache = OrderCache::new(CacheConfig::default());
+        let mut changed = sample_order("1");
+        changed.customer_id = String::from("changed");
+
+        // a read that began before a change of the order does not cache it
+        let stale = cache.fill();
+        cache.remove("1");
+        stale.put(sample_order("1"));
+        assert!(cache.get("1").is_none());
+        // a read that began after the change does, other orders are not affected
+        let fresh = cache.fill();
+        fresh.put(changed.clone());
+        stale.put(sample_order("2"));
+        assert_eq!(cache.get("1"), Some(changed));
+        assert!(cache.get("2").is_some());
+        drop((stale, fresh));
+
+        // invalidations are forgotten once no read is in flight
+        cache.remove("1");
+        cache.fill().put(sample_order("1"));
+        assert_eq!(cache.get("1"), Some(sample_order("1")));
+    }
+
+    // Holds `get` of the order_uid until `release` is notified.
+    struct PausedRepository {
+        inner: InMemoryOrderRepository,
+        paused: String,
+        reading: tokio::sync::Notify,
+        release: tokio::sync::Notify,
+    }
+
+    #[async_trait]
+    impl OrderRepository for PausedRepository {
+        async fn list(&self) -> Result<Vec<Order>, RepositoryError> {
+            self.inner.list().await
+        }
+
+        async fn page(&self, filter: &OrderFilter) -> Result<OrderPage, RepositoryError> {
+            self.inner.page(filter).await
+        }
+
+        async fn count(&self) -> Result<u64, RepositoryError> {
+            self.inner.count().await
+        }
+
+        async fn get(&self, order_uid: &str) -> Result<Option<Order>, RepositoryError> {
+            let order = self.inner.get(order_uid).await;
+            if order_uid == self.paused {
+                self.reading.notify_one();
+                self.release.notified().await;
+            }
+            order
+        }
+
+        async fn insert(&self, order: &Order) -> Result<Inserted, RepositoryError> {
+            self.inner.insert(order).await
+        }
+
+        async fn update(&self, order: &Order) -> Result<(), RepositoryError> {
+            self.inner.update(order).await
+        }
+
+        async fn delete(&self, order_uid: &str) -> Result<(), RepositoryError> {
+            self.inner.delete(order_uid).await
+        }
+    }
+
+    #[tokio::test]
+    async fn cached_concurrent_change_test() {
+        for change in ["update", "delete"] {
+            let paused = Arc::new(PausedRepository {
+                inner: InMemoryOrderRepository::new(),
+                paused: String::from("1"),
+                reading: tokio::sync::Notify::new(),
+                release: tokio::sync::Notify::new(),
+            });
+            assert!(paused.insert(&sample_order("1")).await.is_ok());
+            let cached = Arc::new(CachedOrderRepository::new(paused.clone(), CacheConfig::default(), Metrics::new()));
+
+            // a get misses the cache and has read the order when it is changed
+            let reader = tokio::spawn({
+                let cached = Arc::clone(&cached);
+                async move { cached.get("1").await.ok() }
+            });
+            paused.reading.notified().await;
+            let mut changed = sample_order("1");
+            changed.customer_id = String::from("changed");
+            match change {
+                "update" => assert!(cached.update(&changed).await.is_ok()),
+                _ => assert!(cached.delete("1").await.is_ok()),
+            }
+            paused.release.notify_one();
+            assert_eq!(reader.await.unwrap(), Some(Some(sample_order("1"))));
+
+            // the order it read is not put back into the cache
+            let expected = (change == "update").then_some(changed);
+            paused.release.notify_one();
+            assert_eq!(cached.get("1").await.ok(), Some(expected), "{}", change);
+        }
+    }
+
+    struct CountingRepository {
+        inner: InMemoryOrderRepository,
+        gets: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl OrderRepository for CountingRepository {
+        async fn list(&self) -> Result<Vec<Order>, RepositoryError> {
+            self.inner.list().await
+        }
+
+        async fn page(&self, filter: &OrderFilter) -> Result<OrderPage, RepositoryError> {
+            self.inner.page(filter).await
+        }
+
+        async fn count(&self) -> Result<u64, RepositoryError> {
+            self.inner.count().await
+        }
+
+        async fn get(&self, order_uid: &str) -> Result<Option<Order>, RepositoryError> {
+            self.gets.fetch_add(1, Ordering::SeqCst);
+            self.inner.get(order_uid).await
+        }
+
+        async fn insert(&self, order: &Order) -> Result<Inserted, RepositoryError> {
+            self.inner.insert(order).await
+        }
+
+        async fn update(&self, order: &Order) -> Result<(), RepositoryError> {
+            self.inner.update(order).await
+        }
+
+        async fn delete(&self, order_uid: &str) -> Result<(), RepositoryError> {
+            self.inner.delete(order_uid).await
+        }
+    }
+
+    #[tokio::test]
+    async fn cached_get_order_test() {
+        let counting = Arc::new(CountingRepository { inner: InMemoryOrderRepository::new(), gets: AtomicUsize::new(0) });
+        counting.insert(&sample_order("1")).await.ok();
 
-        let mut order = Order::default();
-        order.order_uid = String::from("1");
+        let cached = CachedOrderRepository::new(counting.clone(), CacheConfig::default(), Metrics::new());
+        assert_eq!(cached.warm_up().await.ok(), Some(1));
+
+        let repository: Arc<dyn OrderRepository> = Arc::new(cached);
+        let mut app = init_app(&repository, &test_keys(), &test_webhooks(), &test_config(), &Metrics::new(), &OrderEvents::new(EventsConfig::default())).await.into_service();
 
         // post order
         let request = Request::builder()
             .method("POST")
             .uri("/orders")
             .header("content-type", "application/json")
-            .body(Body::new(serde_json::to_string(&order).unwrap()))
+            .body(Body::new(serde_json::to_string(&sample_order("2")).unwrap()))
             .unwrap();
 
         let response = ServiceExt::<Request<Body>>::ready(&mut app)
@@ -63,43 +917,162 @@ mod tests {
             .await
             .unwrap();
 
-        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.status(), StatusCode::CREATED);
 
-        // get order
-        let request = Request::builder()
-            .uri("/orders/1")
-            .body(Body::empty())
-            .unwrap();
+        // get warmed up and posted orders
+        for order_uid in ["1", "2"] {
+            let request = Request::builder()
+                .uri(format!("/orders/{}", order_uid))
+                .body(Body::empty())
+                .unwrap();
 
-        let response = ServiceExt::<Request<Body>>::ready(&mut app)
-            .await
-            .unwrap()
-            .call(request)
-            .await
-            .unwrap();
+            let response = ServiceExt::<Request<Body>>::ready(&mut app)
+                .await
+                .unwrap()
+                .call(request)
+                .await
+                .unwrap();
 
-        assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(response.status(), StatusCode::OK);
 
-        let body = response.into_body().collect().await.unwrap().to_bytes();
-        let body: Order = serde_json::from_slice(&body).unwrap();
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            let body: Order = serde_json::from_slice(&body).unwrap();
+
+            assert_eq!(sample_order(order_uid), body);
+        }
 
-        assert_eq!(order, body);
+        assert_eq!(counting.gets.load(Ordering::SeqCst), 0);
     }
 
     #[tokio::test]
-    async fn get_existing_order_test() {
+    async fn ingest_orders_test() {
+        let repository: Arc<dyn OrderRepository> = Arc::new(InMemoryOrderRepository::new());
+        let broker = InMemoryBroker::new();
+        let mut source = broker.subscribe();
+
+        let order = serde_json::to_vec(&sample_order("1")).unwrap();
+        broker.publish(order.clone());
+        broker.publish("{\"order_uid\": \"2\"}");
+        broker.publish(order_json_with(&sample_order("3"), "/date_created", json!("yesterday")));
+        // redelivery of an order that is already stored
+        broker.publish(order.clone());
+        // a different order with a taken order_uid
+        let mut conflicting = sample_order("1");
+        conflicting.customer_id = String::from("someone else");
+        broker.publish(serde_json::to_vec(&conflicting).unwrap());
+        // more items than server.max_items
+        let mut large = sample_order("4");
+        large.items = vec![large.items[0].clone(); 3];
+        broker.publish(serde_json::to_vec(&large).unwrap());
+        broker.close();
+
+        ingest::run(&mut source, &repository, 2).await.ok().unwrap();
+
+        assert_eq!(broker.acked(), vec![order.clone(), order]);
+        assert!(broker.nacked().is_empty());
+
+        let dead_letters = broker.dead_letters();
+        assert_eq!(dead_letters.len(), 4);
+        assert_eq!(dead_letters[0].0, b"{\"order_uid\": \"2\"}".to_vec());
+        assert!(dead_letters[1].1.starts_with("date_created: "));
+        assert!(dead_letters[2].1.contains("already exists"));
+        assert_eq!(dead_letters[3].1, "items: must contain at most 2 items");
+
+        assert_eq!(repository.get("1").await.ok().flatten(), Some(sample_order("1")));
+        assert_eq!(repository.list().await.ok().map(|orders| orders.len()), Some(1));
+    }
+
+    #[tokio::test]
+    async fn ingest_orders_from_nats_test() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("nats://{}", listener.local_addr().unwrap());
+        let order = serde_json::to_string(&sample_order("1")).unwrap();
+
+        // a NATS server that delivers two JetStream messages and records the replies
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (reader, mut writer) = stream.into_split();
+            let mut reader = BufReader::new(reader);
+
+            writer.write_all(b"INFO {\"server_id\":\"test\",\"headers\":true}\r\n").await.unwrap();
+            let mut handshake = Vec::new();
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).await.unwrap();
+                if line.starts_with("PING") {
+                    break;
+                }
+                handshake.push(line.trim_end().to_string());
+            }
+            writer.write_all(b"PONG\r\n").await.unwrap();
+
+            let invalid = "not an order";
+            let messages = format!(
+                "PING\r\nMSG orders 1 $JS.ACK.1 {}\r\n{}\r\nMSG orders 1 $JS.ACK.2 {}\r\n{}\r\n",
+                order.len(), order, invalid.len(), invalid
+            );
+            writer.write_all(messages.as_bytes()).await.unwrap();
+
+            let mut replies = Vec::new();
+            while replies.len() < 4 {
+                let mut line = String::new();
+                reader.read_line(&mut line).await.unwrap();
+                let line = line.trim_end().to_string();
+                let args: Vec<&str> = line.split_whitespace().collect();
+                let len: usize = match args[0] {
+                    "PUB" | "HPUB" => args.last().unwrap().parse().unwrap(),
+                    _ => {
+                        replies.push(line);
+                        continue;
+                    }
+                };
+                let mut payload = vec![0; len + 2];
+                reader.read_exact(&mut payload).await.unwrap();
+                payload.truncate(len);
+                replies.push(format!("{} {}", args[1], String::from_utf8(payload).unwrap()));
+            }
+            (handshake, replies)
+        });
+
+        let config = NatsConfig {
+            url,
+            subject: String::from("orders"),
+            queue_group: Some(String::from("l0")),
+            dead_letter_subject: String::from("orders.dead_letter"),
+        };
+        let repository: Arc<dyn OrderRepository> = Arc::new(InMemoryOrderRepository::new());
+        let mut source = NatsSource::connect(&config).await.ok().unwrap();
+
+        // runs until the server hangs up
+        assert!(ingest::run(&mut source, &repository, test_config().server.max_items).await.is_err());
+        let (handshake, replies) = server.await.unwrap();
+
+        assert!(handshake[0].starts_with("CONNECT {"));
+        assert_eq!(handshake[1], "SUB orders l0 1");
+        assert_eq!(replies[0], "PONG");
+        assert_eq!(replies[1], "$JS.ACK.1 +ACK");
+        assert!(replies[2].starts_with("orders.dead_letter NATS/1.0\r\nL0-Dead-Letter-Reason: "));
+        assert!(replies[2].ends_with("\r\n\r\nnot an order"));
+        assert_eq!(replies[3], "$JS.ACK.2 +TERM");
+
+        assert_eq!(repository.get("1").await.ok().flatten(), Some(sample_order("1")));
+    }
+
+    #[tokio::test]
+    async fn post_invalid_order_test() {
         let mut app = app().await.into_service();
 
-        let mut order = Order::default();
-        order.order_uid = String::from("1");
-        let content = serde_json::to_string(&order).unwrap();
+        let mut order = sample_order("");
+        order.payment.transaction = String::from("b563feb7b2b84b6test");
+        order.payment.amount = order.payment.currency.money(1);
+        order.items[1].track_number = String::from("WBILMOTHERTRACK");
 
         // post order
         let request = Request::builder()
             .method("POST")
             .uri("/orders")
             .header("content-type", "application/json")
-            .body(Body::new(content.clone()))
+            .body(Body::new(order_json_with(&order, "/date_created", json!("yesterday"))))
             .unwrap();
 
         let response = ServiceExt::<Request<Body>>::ready(&mut app)
@@ -109,14 +1082,26 @@ mod tests {
             .await
             .unwrap();
 
-        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["code"], "validation_failed");
+        let errors: Vec<(&str, &str)> = body["detail"].as_array().unwrap().iter().map(|error| {
+            (error["field"].as_str().unwrap(), error["rule"].as_str().unwrap())
+        }).collect();
+
+        assert_eq!(errors, vec![
+            ("order_uid", "required"),
+            ("date_created", "timestamp"),
+            ("payment.amount", "payment_total"),
+            ("items[1].track_number", "track_number_mismatch"),
+        ]);
 
-        // post order again
+        // get orders
         let request = Request::builder()
-            .method("POST")
             .uri("/orders")
-            .header("content-type", "application/json")
-            .body(Body::new(content))
+            .body(Body::empty())
             .unwrap();
 
         let response = ServiceExt::<Request<Body>>::ready(&mut app)
@@ -126,6 +1111,2112 @@ mod tests {
             .await
             .unwrap();
 
-        assert_ne!(response.status(), StatusCode::OK);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let body: OrderPage = serde_json::from_slice(&body).unwrap();
+
+        assert!(body.orders.is_empty());
+    }
+
+    #[tokio::test]
+    async fn typed_fields_test() {
+        for app in backend_apps().await {
+            let mut app = app.into_service();
+
+            // the JSON keeps its shape, date_created keeps its offset
+            let mut order = sample_order("a");
+            order.date_created = "2021-11-26T23:30:00.5-03:00".parse().unwrap();
+            let (status, _) = send_json(&mut app, "POST", "/orders", Some(serde_json::to_string(&order).unwrap())).await;
+            assert_eq!(status, StatusCode::CREATED);
+            let (_, body) = send_json(&mut app, "GET", "/orders/a", None).await;
+            let body: Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(body["date_created"], "2021-11-26T23:30:00.5-03:00");
+            assert_eq!(body["locale"], "en");
+            assert_eq!(body["payment"]["currency"], "USD");
+            assert_eq!(body["payment"]["payment_dt"], 1637907727);
+            assert_eq!(body["payment"]["amount"], 1817);
+            assert_eq!(body["items"][0]["total_price"], 317);
+
+            // the same instant at another offset is another date_created
+            order.date_created = "2021-11-27T02:30:00.5Z".parse().unwrap();
+            let (status, _) = send_json(&mut app, "POST", "/orders", Some(serde_json::to_string(&order).unwrap())).await;
+            assert_eq!(status, StatusCode::CONFLICT);
+
+            // ranges and sorting compare instants, not the text
+            let mut order = sample_order("b");
+            order.date_created = "2021-11-27T01:00:00+00:00".parse().unwrap();
+            let (status, _) = send_json(&mut app, "POST", "/orders", Some(serde_json::to_string(&order).unwrap())).await;
+            assert_eq!(status, StatusCode::CREATED);
+            let (_, page) = get_orders_page(&mut app, "").await;
+            assert_eq!(order_uids(&page), vec!["b", "a"]);
+            assert_eq!(page["orders"][0]["date_created"], "2021-11-27T01:00:00+00:00");
+            let (_, page) = get_orders_page(&mut app, "created_from=2021-11-27T02:00:00Z").await;
+            assert_eq!(order_uids(&page), vec!["a"]);
+            let (_, page) = get_orders_page(&mut app, "limit=1").await;
+            let cursor = page["next_cursor"].as_str().unwrap().to_string();
+            let (_, page) = get_orders_page(&mut app, &format!("limit=1&after={}", cursor)).await;
+            assert_eq!(order_uids(&page), vec!["a"]);
+
+            // values the types cannot hold are reported with the other rules
+            let invalid = [
+                ("/date_created", json!("yesterday"), "date_created", "timestamp"),
+                ("/locale", json!("xx"), "locale", "locale"),
+                ("/payment/currency", json!("usd"), "payment.currency", "currency"),
+                ("/payment/payment_dt", json!(i64::MAX), "payment.payment_dt", "unix_time"),
+            ];
+            for (pointer, value, field, rule) in invalid {
+                let mut order = sample_order("c");
+                order.payment.amount = order.payment.currency.money(1);
+                let body = order_json_with(&order, pointer, value);
+                let (status, body) = send_json(&mut app, "POST", "/orders", Some(body)).await;
+                assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY, "{}", field);
+                let problem = serde_json::from_slice::<Problem>(&body).unwrap();
+                assert_eq!(problem.code, "validation_failed");
+                let detail = problem.detail.unwrap();
+                assert_eq!((detail[0]["field"].as_str(), detail[0]["rule"].as_str()), (Some(field), Some(rule)));
+                assert_eq!(detail[1]["rule"], "payment_total");
+            }
+            // each in its place among the other fields
+            let mut order = serde_json::to_value(sample_order("c")).unwrap();
+            order["date_created"] = json!("yesterday");
+            order["locale"] = json!("xx");
+            order["delivery"]["name"] = json!("");
+            order["payment"]["currency"] = json!("usd");
+            order["payment"]["payment_dt"] = json!(i64::MAX);
+            order["payment"]["provider"] = json!("");
+            let (_, body) = send_json(&mut app, "POST", "/orders", Some(order.to_string())).await;
+            let detail = serde_json::from_slice::<Problem>(&body).unwrap().detail.unwrap();
+            let fields: Vec<&str> = detail.as_array().unwrap().iter().map(|error| error["field"].as_str().unwrap()).collect();
+            assert_eq!(fields, ["date_created", "locale", "delivery.name", "payment.provider", "payment.currency", "payment.payment_dt"]);
+
+            // a value of another JSON type is not an order
+            let body = order_json_with(&sample_order("c"), "/date_created", json!(1637907727));
+            let (status, body) = send_json(&mut app, "POST", "/orders", Some(body)).await;
+            assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+            let problem = serde_json::from_slice::<Problem>(&body).unwrap();
+            assert_eq!(problem.code, "invalid_json");
+            assert!(problem.detail.unwrap().as_str().unwrap().contains("date_created: "));
+
+            let (status, _) = get_orders_page(&mut app, "locale=xx").await;
+            assert_eq!(status, StatusCode::BAD_REQUEST);
+        }
+    }
+
+    async fn get_orders_page(app: &mut axum::routing::RouterIntoService<Body>, query: &str) -> (StatusCode, serde_json::Value) {
+        let request = Request::builder()
+            .uri(format!("/orders?{}", query))
+            .body(Body::empty())
+            .unwrap();
+
+        let response = ServiceExt::<Request<Body>>::ready(app)
+            .await
+            .unwrap()
+            .call(request)
+            .await
+            .unwrap();
+
+        let status = response.status();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    fn order_uids(page: &serde_json::Value) -> Vec<&str> {
+        page["orders"].as_array().unwrap().iter().map(|order| order["order_uid"].as_str().unwrap()).collect()
+    }
+
+    #[tokio::test]
+    async fn get_orders_pagination_test() {
+        for app in backend_apps().await {
+            let mut app = app.into_service();
+
+            let orders = [
+                ("a", "alice", "meest", 5, "2021-11-26T06:22:19Z"),
+                ("b", "bob", "meest", 3, "2021-11-24T06:22:19Z"),
+                ("c", "alice", "dhl", 3, "2021-11-25T06:22:19Z"),
+                ("d", "alice", "meest", 1, "2021-11-27T06:22:19Z"),
+                ("e", "bob", "dhl", 4, "2021-11-23T06:22:19Z"),
+            ];
+            for (order_uid, customer_id, delivery_service, sm_id, date_created) in orders {
+                let mut order = sample_order(order_uid);
+                order.customer_id = String::from(customer_id);
+                order.delivery_service = String::from(delivery_service);
+                order.sm_id = sm_id;
+                order.date_created = date_created.parse().unwrap();
+
+                let request = Request::builder()
+                    .method("POST")
+                    .uri("/orders")
+                    .header("content-type", "application/json")
+                    .body(Body::new(serde_json::to_string(&order).unwrap()))
+                    .unwrap();
+
+                let response = ServiceExt::<Request<Body>>::ready(&mut app)
+                    .await
+                    .unwrap()
+                    .call(request)
+                    .await
+                    .unwrap();
+
+                assert_eq!(response.status(), StatusCode::CREATED);
+            }
+
+            // default sort by date_created
+            let (status, page) = get_orders_page(&mut app, "").await;
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(order_uids(&page), vec!["e", "b", "c", "a", "d"]);
+            assert!(page["next_cursor"].is_null());
+
+            // walk through pages sorted by sm_id, ties are broken by order_uid
+            let mut walked = Vec::new();
+            let mut query = String::from("limit=2&sort=sm_id&order=desc");
+            loop {
+                let (status, page) = get_orders_page(&mut app, &query).await;
+                assert_eq!(status, StatusCode::OK);
+                walked.extend(order_uids(&page).into_iter().map(String::from));
+                match page["next_cursor"].as_str() {
+                    Some(cursor) => query = format!("limit=2&sort=sm_id&order=desc&after={}", cursor),
+                    None => break,
+                }
+            }
+            assert_eq!(walked, vec!["a", "e", "c", "b", "d"]);
+
+            // filters
+            let (_, page) = get_orders_page(&mut app, "customer_id=alice&delivery_service=meest").await;
+            assert_eq!(order_uids(&page), vec!["a", "d"]);
+
+            let (_, page) = get_orders_page(&mut app, "created_from=2021-11-24T00:00:00Z&created_to=2021-11-26T06:22:19Z&order=desc").await;
+            assert_eq!(order_uids(&page), vec!["a", "c", "b"]);
+
+            let (_, page) = get_orders_page(&mut app, "locale=ru").await;
+            assert!(order_uids(&page).is_empty());
+
+            // invalid queries
+            for query in ["limit=0", "limit=abc", "sort=price", "after=garbage", "created_from=yesterday"] {
+                let (status, body) = get_orders_page(&mut app, query).await;
+                assert_eq!(status, StatusCode::BAD_REQUEST, "{}", query);
+                assert_eq!(body["code"], "invalid_query");
+            }
+
+            let (_, page) = get_orders_page(&mut app, "limit=1").await;
+            let cursor = page["next_cursor"].as_str().unwrap().to_string();
+            let (status, _) = get_orders_page(&mut app, &format!("sort=sm_id&after={}", cursor)).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST);
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn sqlite_concurrent_get_orders_test() {
+        let pool = sqlite_pool().await;
+        let repository: Arc<dyn OrderRepository> = Arc::new(SqliteOrderRepository::new(pool.clone()));
+        for order_uid in ["1", "2", "3"] {
+            assert!(repository.insert(&sample_order(order_uid)).await.is_ok());
+        }
+        let app = init_app(&repository, &test_keys(), &test_webhooks(), &test_config(), &Metrics::new(), &OrderEvents::new(EventsConfig::default())).await;
+
+        // a writer holds the write lock and one pooled connection for the whole test
+        let writer = pool.get().unwrap();
+        writer.execute_batch("BEGIN IMMEDIATE;").unwrap();
+
+        let mut requests = tokio::task::JoinSet::new();
+        for index in 0..64 {
+            let app = app.clone();
+            requests.spawn(async move {
+                let uri = match index % 4 {
+                    0 => String::from("/orders?limit=2"),
+                    index => format!("/orders/{}", index),
+                };
+                let request = Request::builder().uri(uri).body(Body::empty()).unwrap();
+                app.oneshot(request).await.unwrap().status()
+            });
+        }
+
+        let statuses = tokio::time::timeout(std::time::Duration::from_secs(10), requests.join_all())
+            .await
+            .expect("concurrent GETs did not make progress");
+        assert!(statuses.iter().all(|status| *status == StatusCode::OK));
+
+        writer.execute_batch("ROLLBACK;").unwrap();
+    }
+
+    async fn get_ui_page(app: &mut axum::routing::RouterIntoService<Body>, uri: &str) -> (StatusCode, String) {
+        let request = Request::builder().uri(uri).body(Body::empty()).unwrap();
+
+        let response = ServiceExt::<Request<Body>>::ready(app)
+            .await
+            .unwrap()
+            .call(request)
+            .await
+            .unwrap();
+
+        let status = response.status();
+        assert_eq!(response.headers()["content-type"], "text/html; charset=utf-8");
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn ui_order_page_test() {
+        let repository: Arc<dyn OrderRepository> = Arc::new(InMemoryOrderRepository::new());
+        let mut order = sample_order("b563feb7b2b84b6test");
+        order.delivery.name = String::from("<script>alert(1)</script>");
+        assert!(repository.insert(&order).await.is_ok());
+        let mut app = init_app(&repository, &test_keys(), &test_webhooks(), &test_config(), &Metrics::new(), &OrderEvents::new(EventsConfig::default())).await.into_service();
+
+        // search form only
+        let (status, body) = get_ui_page(&mut app, "/ui").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains(r#"name="order_uid""#));
+        assert!(!body.contains("<table>"));
+
+        // delivery, payment and items of the found order
+        let (status, body) = get_ui_page(&mut app, "/ui?order_uid=b563feb7b2b84b6test").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains("Kiryat Mozkin"));
+        assert!(body.contains("18.17 USD"));
+        // amounts are in the minor units of the currency
+        for (currency, amount, formatted) in [("JPY", 1817, "1817 JPY"), ("KWD", 1817, "1.817 KWD"), ("USD", 5, "0.05 USD")] {
+            assert_eq!(currency.parse::<Currency>().unwrap().money(amount).format(), formatted);
+        }
+        // amounts are read in payment.currency and never added up across currencies
+        let order: Order = serde_json::from_str(&order_json_with(&sample_order("1"), "/payment/currency", json!("JPY"))).unwrap();
+        assert_eq!((order.payment.amount.format().as_str(), order.items[0].price.format().as_str()), ("1817 JPY", "453 JPY"));
+        assert_eq!(order.payment.amount.checked_add(sample_order("1").payment.amount), None);
+        assert!(body.contains("2021-11-26 06:22:07 UTC"));
+        assert!(body.contains("Vivienne Sabo"));
+        assert!(body.contains("Maybelline"));
+        assert!(!body.contains("<script>"));
+        assert!(body.contains("&lt;script&gt;"));
+
+        // friendly message for a missing order
+        let (status, body) = get_ui_page(&mut app, "/ui?order_uid=missing").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert!(body.contains("Заказ missing не найден"));
+        assert!(body.contains(r#"value="missing""#));
+    }
+
+    #[tokio::test]
+    async fn ui_auth_test() {
+        let keys = test_keys();
+        let reader = auth::issue_key(keys.as_ref(), "support", Scope::Read).await.unwrap_or_else(|err| panic!("{}", err)).key;
+        let repository: Arc<dyn OrderRepository> = Arc::new(InMemoryOrderRepository::new());
+        assert!(repository.insert(&sample_order("1")).await.is_ok());
+        let mut app = init_app(&repository, &keys, &test_webhooks(), &Config::default(), &Metrics::new(), &OrderEvents::new(EventsConfig::default())).await.into_service();
+
+        // the browser is asked for the key and sends it as the password of HTTP Basic
+        let (status, headers, _) = send_with_key(&mut app, "GET", "/ui?order_uid=1", None, None).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let challenges: Vec<&str> = headers.get_all("www-authenticate").iter().map(|value| value.to_str().unwrap()).collect();
+        assert_eq!(challenges, ["Bearer", r#"Basic realm="l0", charset="UTF-8""#]);
+
+        for (credentials, expected) in [
+            (format!("support:{}", reader), StatusCode::OK),
+            (format!(":{}", reader), StatusCode::OK),
+            (String::from("support:l0_unknown"), StatusCode::UNAUTHORIZED),
+            (reader.clone(), StatusCode::UNAUTHORIZED),
+        ] {
+            let request = Request::builder()
+                .uri("/ui?order_uid=1")
+                .header("authorization", format!("Basic {}", STANDARD.encode(credentials)))
+                .body(Body::empty())
+                .unwrap();
+            let response = ServiceExt::<Request<Body>>::ready(&mut app).await.unwrap().call(request).await.unwrap();
+            assert_eq!(response.status(), expected);
+            if expected == StatusCode::OK {
+                let body = response.into_body().collect().await.unwrap().to_bytes();
+                let body = String::from_utf8(body.to_vec()).unwrap();
+                // the read-only key sees the order without the contact details of the customer
+                assert!(body.contains("Maybelline"));
+                assert!(!body.contains("Kiryat Mozkin"));
+                assert!(body.contains(REDACTED));
+            }
+        }
+    }
+
+    #[test]
+    fn config_test() {
+        let file = r#"
+            [server]
+            bind_address = "127.0.0.1:8080"
+
+            [database]
+            path = "orders.db"
+            pool_size = 2
+
+            [cache]
+            policy = "fifo"
+
+            [nats]
+            url = "nats://localhost:4222"
+        "#;
+        let env = |name: &str| match name {
+            "L0_DB_POOL_SIZE" => Some(String::from("3")),
+            "L0_LOG_LEVEL" => Some(String::from("DEBUG")),
+            "L0_NATS_QUEUE_GROUP" => Some(String::from("l0")),
+            "L0_AUTH_ENABLED" => Some(String::from("false")),
+            "L0_DATABASE_BACKEND" => Some(String::from("Memory")),
+            "L0_WEBHOOKS_MAX_ATTEMPTS" => Some(String::from("3")),
+            _ => None,
+        };
+
+        let config = Config::from_sources(Some(("l0.toml", file)), env).unwrap();
+        assert_eq!(config.server.bind_address, "127.0.0.1:8080");
+        assert_eq!(config.server.body_limit, 2 * 1024 * 1024);
+        assert_eq!(config.database.path, "orders.db");
+        assert_eq!(config.database.pool_size, 3);
+        assert_eq!(config.database.backend, Backend::Memory);
+        assert_eq!(config.cache.capacity, 10_000);
+        assert_eq!(config.cache.policy, EvictionPolicy::Fifo);
+        assert_eq!(config.log.level, LogLevel::Debug);
+        assert!(!config.auth.enabled);
+        assert_eq!(config.webhooks.max_attempts, 3);
+        assert_eq!(config.webhooks.backoff_base_ms, 5000);
+
+        let nats = config.nats.unwrap();
+        assert_eq!(nats.url, "nats://localhost:4222");
+        assert_eq!(nats.subject, "orders");
+        assert_eq!(nats.queue_group.as_deref(), Some("l0"));
+
+        // ingestion stays disabled without a NATS url
+        let config = Config::from_sources(None, |_| None).unwrap();
+        assert!(config.nats.is_none());
+        assert!(config.auth.enabled);
+        assert_eq!(config.database.backend, Backend::Sqlite);
+
+        // the postgres backend needs a connection URL
+        let file = r#"
+            [database]
+            backend = "postgres"
+        "#;
+        let Err(ConfigError::Invalid(errors)) = Config::from_sources(Some(("l0.toml", file)), |_| None) else {
+            panic!("expected an invalid configuration");
+        };
+        assert_eq!(errors.len(), 1, "{:?}", errors);
+        assert!(errors[0].starts_with("database."));
+        if cfg!(feature = "postgres") {
+            let env = |name: &str| (name == "L0_DATABASE_URL").then(|| String::from("postgres://localhost/l0"));
+            let config = Config::from_sources(Some(("l0.toml", file)), env).unwrap();
+            assert_eq!(config.database.url.as_deref(), Some("postgres://localhost/l0"));
+        }
+    }
+
+    #[test]
+    fn invalid_config_test() {
+        let file = r#"
+            [database]
+            pool_size = 0
+        "#;
+        let env = |name: &str| match name {
+            "L0_BIND_ADDRESS" => Some(String::from("localhost")),
+            "L0_CACHE_CAPACITY" => Some(String::from("lots")),
+            "L0_LOG_LEVEL" => Some(String::from("verbose")),
+            "L0_RATE_LIMIT_PER_SECOND" => Some(String::from("1e-300")),
+            "L0_DATABASE_BACKEND" => Some(String::from("mongodb")),
+            "L0_WEBHOOKS_BACKOFF_MAX_MS" => Some(String::from("1000")),
+            _ => None,
+        };
+
+        // every invalid setting is reported
+        let Err(ConfigError::Invalid(errors)) = Config::from_sources(Some(("l0.toml", file)), env) else {
+            panic!("expected an invalid configuration");
+        };
+        assert_eq!(errors.len(), 7, "{:?}", errors);
+        assert!(errors.iter().any(|err| err.starts_with("L0_DATABASE_BACKEND")));
+        assert!(errors.iter().any(|err| err.starts_with("L0_CACHE_CAPACITY")));
+        assert!(errors.iter().any(|err| err.starts_with("L0_LOG_LEVEL")));
+        assert!(errors.iter().any(|err| err.starts_with("server.bind_address")));
+        assert!(errors.iter().any(|err| err.starts_with("database.pool_size")));
+        assert!(errors.iter().any(|err| err.starts_with("rate_limit.per_second")));
+        assert!(errors.iter().any(|err| err.starts_with("webhooks.backoff_max_ms")));
+
+        // typos in the file are not silently ignored
+        let file = r#"
+            [database]
+            pool = 4
+        "#;
+        assert!(matches!(Config::from_sources(Some(("l0.toml", file)), |_| None), Err(ConfigError::Parse { .. })));
+    }
+
+    #[tokio::test]
+    async fn post_order_body_limit_test() {
+        let repository: Arc<dyn OrderRepository> = Arc::new(InMemoryOrderRepository::new());
+        let mut config = test_config();
+        config.server.body_limit = 256;
+        let mut app = init_app(&repository, &test_keys(), &test_webhooks(), &config, &Metrics::new(), &OrderEvents::new(EventsConfig::default())).await.into_service();
+
+        let request = Request::builder()
+            .method("POST")
+            .uri("/orders")
+            .header("content-type", "application/json")
+            .body(Body::new(serde_json::to_string(&sample_order("1")).unwrap()))
+            .unwrap();
+
+        let response = ServiceExt::<Request<Body>>::ready(&mut app)
+            .await
+            .unwrap()
+            .call(request)
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    async fn get_status(app: &mut axum::routing::RouterIntoService<Body>, uri: &str) -> (StatusCode, serde_json::Value) {
+        let request = Request::builder().uri(uri).body(Body::empty()).unwrap();
+
+        let response = ServiceExt::<Request<Body>>::ready(app)
+            .await
+            .unwrap()
+            .call(request)
+            .await
+            .unwrap();
+
+        let status = response.status();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn health_ready_test() {
+        let repository: Arc<dyn OrderRepository> = Arc::new(SqliteOrderRepository::new(sqlite_pool().await));
+        let cached = Arc::new(CachedOrderRepository::new(repository, CacheConfig::default(), Metrics::new()));
+        let repository: Arc<dyn OrderRepository> = cached.clone();
+        let mut app = init_app(&repository, &test_keys(), &test_webhooks(), &test_config(), &Metrics::new(), &OrderEvents::new(EventsConfig::default())).await.into_service();
+
+        let (status, body) = get_status(&mut app, "/healthz").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["status"], "ok");
+
+        // not ready until the cache is warmed up
+        let (status, body) = get_status(&mut app, "/readyz").await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["code"], "not_ready");
+
+        assert_eq!(cached.warm_up().await.ok(), Some(0));
+
+        let (status, body) = get_status(&mut app, "/readyz").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["status"], "ready");
+    }
+
+    // Sends a GET over a raw connection and returns the whole response.
+    async fn http_get(addr: std::net::SocketAddr, uri: &str) -> std::io::Result<String> {
+        let mut stream = tokio::net::TcpStream::connect(addr).await?;
+        stream.write_all(format!("GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n", uri).as_bytes()).await?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await?;
+        Ok(response)
+    }
+
+    fn slow_app(delay: std::time::Duration) -> Router {
+        Router::new().route("/slow", get(move || async move {
+            tokio::time::sleep(delay).await;
+            "done"
+        }))
+    }
+
+    #[tokio::test]
+    async fn graceful_shutdown_test() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (shutdown, shutdown_requested) = tokio::sync::oneshot::channel::<()>();
+        let app = slow_app(std::time::Duration::from_millis(300));
+
+        let server = tokio::spawn(serve(listener, app, async { shutdown_requested.await.ok(); }, std::time::Duration::from_secs(10)));
+
+        let request = tokio::spawn(http_get(addr, "/slow"));
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        shutdown.send(()).unwrap();
+
+        // the in-flight request completes, then the server stops
+        let response = request.await.unwrap().unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.ends_with("done"));
+        assert!(server.await.unwrap().is_ok());
+
+        // and no longer accepts connections
+        assert!(http_get(addr, "/slow").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn graceful_shutdown_timeout_test() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (shutdown, shutdown_requested) = tokio::sync::oneshot::channel::<()>();
+        let app = slow_app(std::time::Duration::from_secs(60));
+
+        let server = tokio::spawn(serve(listener, app, async { shutdown_requested.await.ok(); }, std::time::Duration::from_millis(100)));
+
+        let _request = tokio::spawn(http_get(addr, "/slow"));
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        shutdown.send(()).unwrap();
+
+        let result = tokio::time::timeout(std::time::Duration::from_secs(5), server).await.unwrap().unwrap();
+        assert_eq!(result.unwrap_err().kind(), std::io::ErrorKind::TimedOut);
+    }
+
+    #[tokio::test]
+    async fn metrics_test() {
+        let metrics = Metrics::new();
+        let repository: Arc<dyn OrderRepository> = Arc::new(SqliteOrderRepository::new(sqlite_pool().await));
+        let repository: Arc<dyn OrderRepository> = Arc::new(MeteredOrderRepository::new(repository, metrics.clone()));
+        let repository: Arc<dyn OrderRepository> = Arc::new(CachedOrderRepository::new(repository, CacheConfig::default(), metrics.clone()));
+        let mut app = init_app(&repository, &test_keys(), &test_webhooks(), &test_config(), &metrics, &OrderEvents::new(EventsConfig::default())).await.into_service();
+
+        let request = Request::builder()
+            .method("POST")
+            .uri("/orders")
+            .header("content-type", "application/json")
+            .body(Body::new(serde_json::to_string(&sample_order("1")).unwrap()))
+            .unwrap();
+
+        let response = ServiceExt::<Request<Body>>::ready(&mut app)
+            .await
+            .unwrap()
+            .call(request)
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::CREATED);
+
+        for uri in ["/orders/1", "/orders/1", "/orders/2", "/missing"] {
+            let request = Request::builder().uri(uri).body(Body::empty()).unwrap();
+            ServiceExt::<Request<Body>>::ready(&mut app)
+                .await
+                .unwrap()
+                .call(request)
+                .await
+                .unwrap();
+        }
+
+        let request = Request::builder().uri("/metrics").body(Body::empty()).unwrap();
+        let response = ServiceExt::<Request<Body>>::ready(&mut app)
+            .await
+            .unwrap()
+            .call(request)
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        let lines: Vec<&str> = body.lines().collect();
+
+        for expected in [
+            r#"l0_http_requests_total{method="POST",route="/orders",status="201"} 1"#,
+            r#"l0_http_requests_total{method="GET",route="/orders/:order_uid",status="200"} 2"#,
+            r#"l0_http_requests_total{method="GET",route="/orders/:order_uid",status="404"} 1"#,
+            r#"l0_http_requests_total{method="GET",route="unmatched",status="404"} 1"#,
+            r#"l0_http_request_duration_seconds_count{method="GET",route="/orders/:order_uid"} 3"#,
+            r#"l0_cache_requests_total{result="hit"} 2"#,
+            r#"l0_cache_requests_total{result="miss"} 1"#,
+            r#"l0_db_query_duration_seconds_count{operation="insert"} 1"#,
+            r#"l0_db_query_duration_seconds_count{operation="get"} 1"#,
+            r#"l0_db_query_duration_seconds_count{operation="count"} 1"#,
+            "l0_stored_orders 1",
+        ] {
+            assert!(lines.contains(&expected), "{} is missing from\n{}", expected, body);
+        }
+    }
+
+    // Collects log output in memory.
+    #[derive(Clone, Default)]
+    struct LogBuffer(Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl std::io::Write for LogBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl<'writer> tracing_subscriber::fmt::MakeWriter<'writer> for LogBuffer {
+        type Writer = LogBuffer;
+
+        fn make_writer(&'writer self) -> Self::Writer {
+            self.clone()
+        }
+    }
+
+    impl LogBuffer {
+        fn contents(&self) -> String {
+            String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+        }
+    }
+
+    #[tokio::test]
+    async fn request_logging_test() {
+        let logs = LogBuffer::default();
+        let config = LogConfig { level: LogLevel::Info, format: LogFormat::Json };
+        let _subscriber = tracing::subscriber::set_default(logging::subscriber(&config, logs.clone()));
+
+        let mut app = app().await.into_service();
+
+        // a request id sent by the client is kept and echoed
+        let request = Request::builder()
+            .uri("/orders/missing")
+            .header("x-request-id", "test-request")
+            .body(Body::empty())
+            .unwrap();
+
+        let response = ServiceExt::<Request<Body>>::ready(&mut app)
+            .await
+            .unwrap()
+            .call(request)
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(response.headers()["x-request-id"], "test-request");
+
+        // otherwise one is generated
+        let request = Request::builder().uri("/healthz").body(Body::empty()).unwrap();
+        let response = ServiceExt::<Request<Body>>::ready(&mut app)
+            .await
+            .unwrap()
+            .call(request)
+            .await
+            .unwrap();
+
+        let request_id = response.headers()["x-request-id"].to_str().unwrap().to_string();
+        assert_eq!(request_id.len(), 36);
+
+        let events: Vec<serde_json::Value> = logs.contents()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+
+        let rejected = events.iter().find(|event| event["fields"]["message"] == "request rejected").unwrap();
+        assert_eq!(rejected["fields"]["code"], "order_not_found");
+        assert_eq!(rejected["span"]["request_id"], "test-request");
+
+        let completed: Vec<&serde_json::Value> = events.iter()
+            .filter(|event| event["fields"]["message"] == "request completed")
+            .collect();
+        assert_eq!(completed.len(), 2);
+
+        assert_eq!(completed[0]["fields"]["status"], 404);
+        assert!(completed[0]["fields"]["latency_ms"].is_number());
+        assert_eq!(completed[0]["span"]["request_id"], "test-request");
+        assert_eq!(completed[0]["span"]["method"], "GET");
+        assert_eq!(completed[0]["span"]["path"], "/orders/missing");
+        assert_eq!(completed[0]["span"]["route"], "/orders/:order_uid");
+        assert_eq!(completed[0]["span"]["order_uid"], "missing");
+
+        assert_eq!(completed[1]["fields"]["status"], 200);
+        assert_eq!(completed[1]["span"]["request_id"], request_id.as_str());
+    }
+
+    async fn send_json(app: &mut axum::routing::RouterIntoService<Body>, method: &str, uri: &str, body: Option<String>) -> (StatusCode, Vec<u8>) {
+        let (status, _, body) = send_with_key(app, method, uri, None, body).await;
+        (status, body)
+    }
+
+    // Sends `key` as a bearer token.
+    async fn send_with_key(app: &mut axum::routing::RouterIntoService<Body>, method: &str, uri: &str, key: Option<&str>, body: Option<String>) -> (StatusCode, axum::http::HeaderMap, Vec<u8>) {
+        let mut request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("content-type", "application/json");
+        if let Some(key) = key {
+            request = request.header("authorization", format!("Bearer {}", key));
+        }
+        let request = request.body(body.map(Body::new).unwrap_or_else(Body::empty)).unwrap();
+
+        let response = ServiceExt::<Request<Body>>::ready(app)
+            .await
+            .unwrap()
+            .call(request)
+            .await
+            .unwrap();
+
+        let status = response.status();
+        let headers = response.headers().clone();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        (status, headers, body.to_vec())
+    }
+
+    async fn order_lifecycle(app: Router) {
+        let mut app = app.into_service();
+        let order = sample_order("1");
+
+        let (status, _) = send_json(&mut app, "POST", "/orders", Some(serde_json::to_string(&order).unwrap())).await;
+        assert_eq!(status, StatusCode::CREATED);
+
+        // replace the order
+        let mut updated = order.clone();
+        updated.delivery.city = String::from("Haifa");
+        updated.items.pop();
+        updated.payment.goods_total = updated.payment.currency.money(317);
+        let (status, body) = send_json(&mut app, "PUT", "/orders/1", Some(serde_json::to_string(&updated).unwrap())).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(serde_json::from_slice::<Order>(&body).unwrap(), updated);
+
+        let (_, body) = send_json(&mut app, "GET", "/orders/1", None).await;
+        assert_eq!(serde_json::from_slice::<Order>(&body).unwrap(), updated);
+
+        // the body must describe the order in the path and be valid
+        let (status, body) = send_json(&mut app, "PUT", "/orders/2", Some(serde_json::to_string(&updated).unwrap())).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(serde_json::from_slice::<Problem>(&body).unwrap().detail.unwrap()[0]["rule"], "path_mismatch");
+
+        let missing = sample_order("2");
+        let (status, _) = send_json(&mut app, "PUT", "/orders/2", Some(serde_json::to_string(&missing).unwrap())).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, _) = send_json(&mut app, "PUT", "/orders/2", Some(order_json_with(&missing, "/payment/currency", json!("usd")))).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+
+        // change an item status
+        let patch = r#"{"items": [{"chrt_id": 9934930, "status": 300}]}"#;
+        let (status, body) = send_json(&mut app, "PATCH", "/orders/1", Some(String::from(patch))).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(serde_json::from_slice::<Order>(&body).unwrap().items[0].status, 300);
+
+        let (_, body) = send_json(&mut app, "GET", "/orders/1", None).await;
+        assert_eq!(serde_json::from_slice::<Order>(&body).unwrap().items[0].status, 300);
+
+        let patch = r#"{"items": [{"chrt_id": 9934930, "status": 301}, {"chrt_id": 1, "status": 301}]}"#;
+        let (status, body) = send_json(&mut app, "PATCH", "/orders/1", Some(String::from(patch))).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        let detail = serde_json::from_slice::<Problem>(&body).unwrap().detail.unwrap();
+        assert_eq!(detail[0]["field"], "items[1].chrt_id");
+        assert_eq!(detail[0]["rule"], "unknown_item");
+
+        let (status, _) = send_json(&mut app, "PATCH", "/orders/2", Some(String::from(patch))).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        // delete the order
+        let (status, body) = send_json(&mut app, "DELETE", "/orders/1", None).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        assert!(body.is_empty());
+
+        let (status, _) = send_json(&mut app, "GET", "/orders/1", None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (_, body) = send_json(&mut app, "GET", "/orders", None).await;
+        assert!(serde_json::from_slice::<OrderPage>(&body).unwrap().orders.is_empty());
+        let (status, _) = send_json(&mut app, "DELETE", "/orders/1", None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, _) = send_json(&mut app, "PUT", "/orders/1", Some(serde_json::to_string(&updated).unwrap())).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        // the order_uid of a deleted order stays taken
+        let (status, _) = send_json(&mut app, "POST", "/orders", Some(serde_json::to_string(&order).unwrap())).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+    }
+
+    #[tokio::test]
+    async fn order_lifecycle_test() {
+        for app in backend_apps().await {
+            order_lifecycle(app).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn sqlite_order_lifecycle_test() {
+        let pool = sqlite_pool().await;
+        let repository: Arc<dyn OrderRepository> = Arc::new(SqliteOrderRepository::new(pool.clone()));
+        let repository: Arc<dyn OrderRepository> = Arc::new(CachedOrderRepository::new(repository, CacheConfig::default(), Metrics::new()));
+        order_lifecycle(init_app(&repository, &test_keys(), &test_webhooks(), &test_config(), &Metrics::new(), &OrderEvents::new(EventsConfig::default())).await).await;
+
+        // deleted orders are kept with their audit timestamps
+        let connection = pool.get().unwrap();
+        let (updated_at, deleted_at): (Option<String>, Option<String>) = connection.query_row(
+            "SELECT updated_at, deleted_at FROM orders WHERE order_uid = '1'",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        ).unwrap();
+        assert!(updated_at.is_some());
+        assert!(deleted_at.is_some());
+        let items: i64 = connection.query_row("SELECT COUNT(*) FROM items WHERE order_uid = '1'", [], |row| row.get(0)).unwrap();
+        assert_eq!(items, 1);
+    }
+
+    // Collects every way `value` differs from the OpenAPI `schema`: missing
+    // or undocumented fields and mismatched types.
+    fn schema_errors(spec: &Value, schema: &Value, value: &Value, path: &str, errors: &mut Vec<String>) {
+        if let Some(reference) = schema["$ref"].as_str() {
+            let name = reference.trim_start_matches("#/components/schemas/");
+            return schema_errors(spec, &spec["components"]["schemas"][name], value, path, errors);
+        }
+        if let Some(variants) = schema["oneOf"].as_array() {
+            let matches = variants.iter().any(|variant| {
+                let mut variant_errors = Vec::new();
+                schema_errors(spec, variant, value, path, &mut variant_errors);
+                variant_errors.is_empty()
+            });
+            if !matches {
+                errors.push(format!("{}: {} matches none of the variants", path, value));
+            }
+            return;
+        }
+
+        let actual = match value {
+            Value::Null => "null",
+            Value::Bool(_) => "boolean",
+            Value::Number(number) if number.is_f64() => "number",
+            Value::Number(_) => "integer",
+            Value::String(_) => "string",
+            Value::Array(_) => "array",
+            Value::Object(_) => "object",
+        };
+        let types: Vec<&str> = match &schema["type"] {
+            Value::String(kind) => vec![kind.as_str()],
+            Value::Array(kinds) => kinds.iter().filter_map(Value::as_str).collect(),
+            // no type means any value
+            _ => return,
+        };
+        let integer_as_number = actual == "integer" && types.contains(&"number");
+        if !types.contains(&actual) && !integer_as_number {
+            errors.push(format!("{}: expected {:?}, got {}", path, types, value));
+            return;
+        }
+        if let Some(values) = schema["enum"].as_array() {
+            if !values.contains(value) {
+                errors.push(format!("{}: {} is not one of {:?}", path, value, values));
+            }
+        }
+
+        match value {
+            Value::Object(fields) => {
+                let properties = schema["properties"].as_object().cloned().unwrap_or_default();
+                for required in schema["required"].as_array().into_iter().flatten().filter_map(Value::as_str) {
+                    if !fields.contains_key(required) {
+                        errors.push(format!("{}.{}: required but missing", path, required));
+                    }
+                }
+                for (name, field) in fields {
+                    match properties.get(name) {
+                        Some(property) => schema_errors(spec, property, field, &format!("{}.{}", path, name), errors),
+                        None => errors.push(format!("{}.{}: not in the schema", path, name)),
+                    }
+                }
+            }
+            Value::Array(values) => {
+                for (index, element) in values.iter().enumerate() {
+                    schema_errors(spec, &schema["items"], element, &format!("{}[{}]", path, index), errors);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // Finds the documented operation for a concrete request path.
+    fn operation<'a>(spec: &'a Value, method: &str, uri: &str) -> Option<(String, &'a Value)> {
+        let path = uri.split('?').next().unwrap_or_default();
+        let segments: Vec<&str> = path.split('/').collect();
+        spec["paths"].as_object()?.iter().find_map(|(template, operations)| {
+            let template_segments: Vec<&str> = template.split('/').collect();
+            let matches = template_segments.len() == segments.len()
+                && template_segments.iter().zip(&segments).all(|(expected, actual)| expected.starts_with('{') || expected == actual);
+            let operation = &operations[method.to_lowercase()];
+            (matches && operation.is_object()).then(|| (format!("{} {}", method, template), operation))
+        })
+    }
+
+    #[tokio::test]
+    async fn openapi_test() {
+        // closed events end the event stream right away
+        let events = OrderEvents::new(EventsConfig::default());
+        events.close();
+        let repository: Arc<dyn OrderRepository> = Arc::new(InMemoryOrderRepository::new());
+        // a webhook with a delivery to list and retry
+        let outbox = Arc::new(Outbox::default());
+        let webhooks: Arc<dyn WebhookRepository> = Arc::new(InMemoryWebhookRepository::new(Arc::clone(&outbox)));
+        webhooks.insert("http://localhost:9/orders", "whsec_test").await.unwrap_or_else(|err| panic!("{}", err));
+        outbox.push(OrderChange::Created, &sample_order("1")).unwrap_or_else(|err| panic!("{}", err));
+        webhooks.fan_out(10).await.unwrap_or_else(|err| panic!("{}", err));
+        let mut app = init_app(&repository, &test_keys(), &webhooks, &test_config(), &Metrics::new(), &events).await.into_service();
+        let (status, body) = send_json(&mut app, "GET", "/openapi.json", None).await;
+        assert_eq!(status, StatusCode::OK);
+        let spec: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(spec, serde_json::to_value(openapi::ApiDoc::openapi()).unwrap());
+
+        let (status, _) = send_json(&mut app, "GET", "/docs/", None).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let order = serde_json::to_string(&sample_order("1")).unwrap();
+        let mut conflicting = sample_order("1");
+        conflicting.items.pop();
+        let conflicting = serde_json::to_string(&conflicting).unwrap();
+        let invalid = order_json_with(&sample_order("2"), "/payment/currency", json!(""));
+        let patch = String::from(r#"{"items": [{"chrt_id": 9934930, "status": 300}]}"#);
+        let unknown_item = String::from(r#"{"items": [{"chrt_id": 1, "status": 300}]}"#);
+
+        let requests = [
+            ("POST", "/orders", Some(order.clone())),
+            ("POST", "/orders", Some(order.clone())),
+            ("POST", "/orders", Some(conflicting)),
+            ("POST", "/orders", Some(invalid.clone())),
+            ("POST", "/orders", Some(String::from("{"))),
+            ("GET", "/orders", None),
+            ("GET", "/orders?limit=1&sort=sm_id&order=desc", None),
+            ("GET", "/orders?limit=0", None),
+            ("GET", "/orders/1", None),
+            ("GET", "/orders/2", None),
+            ("PUT", "/orders/1", Some(order.clone())),
+            ("PUT", "/orders/3", Some(order)),
+            ("PUT", "/orders/2", Some(invalid)),
+            ("PATCH", "/orders/1", Some(patch.clone())),
+            ("PATCH", "/orders/1", Some(unknown_item)),
+            ("PATCH", "/orders/2", Some(patch)),
+            ("DELETE", "/orders/1", None),
+            ("DELETE", "/orders/1", None),
+            ("POST", "/orders/bulk", Some(format!("{}\n{{\n", serde_json::to_string(&sample_order("4")).unwrap()))),
+            ("GET", "/orders/export", None),
+            ("GET", "/orders/export?format=csv", None),
+            ("GET", "/orders/export?format=xml", None),
+            ("GET", "/orders/events", None),
+            ("GET", "/orders/events?customer_id=test", None),
+            ("GET", "/orders/ws", None),
+            ("GET", "/stats/revenue", None),
+            ("GET", "/stats/brands?limit=5", None),
+            ("GET", "/stats/brands?limit=0", None),
+            ("GET", "/stats/daily?created_from=2021-11-26T00:00:00Z", None),
+            ("POST", "/admin/keys", Some(String::from(r#"{"name": "support", "scope": "read"}"#))),
+            ("POST", "/admin/keys", Some(String::from(r#"{"name": " ", "scope": "write"}"#))),
+            ("POST", "/admin/keys", Some(String::from("{"))),
+            ("GET", "/admin/keys", None),
+            ("DELETE", "/admin/keys/1", None),
+            ("DELETE", "/admin/keys/2", None),
+            ("POST", "/admin/webhooks", Some(String::from(r#"{"url": "http://localhost:8081/orders"}"#))),
+            ("POST", "/admin/webhooks", Some(String::from(r#"{"url": "ftp://localhost/orders"}"#))),
+            ("POST", "/admin/webhooks", Some(String::from("{"))),
+            ("GET", "/admin/webhooks", None),
+            ("GET", "/admin/webhooks/1/deliveries?status=pending", None),
+            ("GET", "/admin/webhooks/1/deliveries?limit=0", None),
+            ("GET", "/admin/webhooks/9/deliveries", None),
+            ("POST", "/admin/webhooks/1/deliveries/1/retry", None),
+            ("POST", "/admin/webhooks/1/deliveries/9/retry", None),
+            ("DELETE", "/admin/webhooks/1", None),
+            ("DELETE", "/admin/webhooks/9", None),
+            ("GET", "/healthz", None),
+            ("GET", "/readyz", None),
+            ("GET", "/metrics", None),
+        ];
+
+        let mut errors = Vec::new();
+        let mut covered = std::collections::HashSet::new();
+        for (method, uri, body) in requests {
+            let request_body = body.as_ref().map(|body| serde_json::from_str::<Value>(body));
+            let Some((name, operation)) = operation(&spec, method, uri) else {
+                errors.push(format!("{} {}: not documented", method, uri));
+                continue;
+            };
+            covered.insert(name.clone());
+
+            // request bodies the service accepts must match the documented schema
+            if let Some(Ok(request_body)) = &request_body {
+                let schema = &operation["requestBody"]["content"]["application/json"]["schema"];
+                schema_errors(&spec, schema, request_body, &format!("{} request", name), &mut errors);
+            }
+
+            let (status, response) = send_json(&mut app, method, uri, body).await;
+            let documented = &operation["responses"][status.as_str()];
+            if !documented.is_object() {
+                errors.push(format!("{} {}: status {} is not documented", method, uri, status));
+                continue;
+            }
+            match documented["content"]["application/json"]["schema"] {
+                Value::Null => {}
+                ref schema => match serde_json::from_slice::<Value>(&response) {
+                    Ok(response) => schema_errors(&spec, schema, &response, &format!("{} {}", name, status.as_u16()), &mut errors),
+                    Err(err) => errors.push(format!("{} {}: the response is not JSON: {}", method, uri, err)),
+                },
+            }
+        }
+
+        for (template, operations) in spec["paths"].as_object().unwrap() {
+            for method in operations.as_object().unwrap().keys() {
+                let name = format!("{} {}", method.to_uppercase(), template);
+                if !covered.contains(&name) {
+                    errors.push(format!("{}: documented but not exercised", name));
+                }
+            }
+        }
+        assert!(errors.is_empty(), "the OpenAPI document drifted:\n{}", errors.join("\n"));
+    }
+
+    #[tokio::test]
+    async fn bulk_import_export_test() {
+        let mut config = test_config();
+        config.server.body_limit = 4096;
+        for storage in test_storages().await {
+            let mut app = storage_app(&storage, &config).await.into_service();
+
+            let count = bulk::IMPORT_BATCH_SIZE + 100;
+            let mut orders: Vec<Order> = (0..count).map(|index| sample_order(&format!("{:04}", index))).collect();
+            orders[7].delivery.address = String::from("Ploshad Mira, \"15\"");
+            orders[8].delivery.name = String::from("=HYPERLINK(A1)");
+            orders[8].items[0].name = String::from("@SUM(1+2)");
+            orders[8].delivery.address = String::from("-1+2");
+            let mut body = String::new();
+            for order in &orders {
+                body.push_str(&serde_json::to_string(order).unwrap());
+                body.push('\n');
+            }
+            let mut conflicting = orders[0].clone();
+            conflicting.items.pop();
+            let lines = [
+                String::new(),
+                String::from("{"),
+                order_json_with(&sample_order("invalid"), "/payment/currency", json!("usd")),
+                serde_json::to_string(&conflicting).unwrap(),
+                serde_json::to_string(&orders[1]).unwrap(),
+                "x".repeat(5000),
+            ];
+            body.push_str(&lines.join("\r\n"));
+
+            // the body arrives in chunks that split lines
+            let chunks: Vec<Result<Vec<u8>, std::io::Error>> = body.into_bytes().chunks(1000).map(|chunk| Ok(chunk.to_vec())).collect();
+            let request = Request::builder()
+                .method("POST")
+                .uri("/orders/bulk")
+                .header("content-type", "application/x-ndjson")
+                .body(Body::from_stream(futures_util::stream::iter(chunks)))
+                .unwrap();
+            let response = ServiceExt::<Request<Body>>::ready(&mut app).await.unwrap().call(request).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            let report: bulk::ImportReport = serde_json::from_slice(&body).unwrap();
+
+            assert_eq!((report.created, report.unchanged, report.failed), (count, 1, 4));
+            let errors: Vec<(usize, &str)> = report.errors.iter().map(|error| (error.line, error.code.as_str())).collect();
+            assert_eq!(errors, vec![
+                (count + 2, "invalid_json"),
+                (count + 3, "validation_failed"),
+                (count + 4, "order_already_exists"),
+                (count + 6, "line_too_long"),
+            ]);
+            assert_eq!(storage.orders().count().await.ok(), Some(count as u64));
+
+            // the report lists the first failed lines only, a stored order fails after the later ones
+            let body = format!("{}\n{}", serde_json::to_string(&orders[0]).unwrap(), "x\n".repeat(bulk::MAX_IMPORT_ERRORS + 50));
+            let (_, body) = send_json(&mut app, "POST", "/orders/bulk", Some(body)).await;
+            let report: bulk::ImportReport = serde_json::from_slice(&body).unwrap();
+            assert_eq!((report.unchanged, report.failed, report.errors.len()), (1, bulk::MAX_IMPORT_ERRORS + 50, bulk::MAX_IMPORT_ERRORS));
+            let lines: Vec<usize> = report.errors.iter().map(|error| error.line).collect();
+            assert_eq!(lines, (2..bulk::MAX_IMPORT_ERRORS + 2).collect::<Vec<usize>>());
+
+            // export
+            let (status, body) = send_json(&mut app, "GET", "/orders/export", None).await;
+            assert_eq!(status, StatusCode::OK);
+            let exported: Vec<Order> = String::from_utf8(body).unwrap().lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+            assert_eq!(exported, orders);
+
+            let request = Request::builder().uri("/orders/export?format=csv").body(Body::empty()).unwrap();
+            let response = ServiceExt::<Request<Body>>::ready(&mut app).await.unwrap().call(request).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(response.headers()["content-type"], "text/csv; charset=utf-8");
+            let csv = String::from_utf8(response.into_body().collect().await.unwrap().to_bytes().to_vec()).unwrap();
+            let rows: Vec<&str> = csv.split_terminator("\r\n").collect();
+            assert_eq!(rows.len(), 1 + 2 * count);
+            assert!(rows[0].starts_with("order_uid,track_number,entry,"));
+            assert_eq!(rows[0].split(',').count(), 39);
+            assert!(rows[1].starts_with("0000,WBILMTESTTRACK,WBIL,"));
+            assert!(rows[1].contains(",9934930,") && rows[2].contains(",9934931,"));
+            assert!(rows[15].contains(",\"Ploshad Mira, \"\"15\"\"\","));
+            // formulas stay text when the export is opened in a spreadsheet, numbers stay numbers
+            assert!(rows[17].contains(",'=HYPERLINK(A1),") && rows[17].contains(",'-1+2,") && rows[17].contains(",'@SUM(1+2),"));
+            assert!(rows[17].contains(",+9720000000,"));
+
+            let (status, body) = send_json(&mut app, "GET", "/orders/export?format=xml", None).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST);
+            assert_eq!(serde_json::from_slice::<Problem>(&body).unwrap().code, "invalid_query");
+        }
+    }
+
+    async fn stats_checks(app: Router) {
+        let mut app = app.into_service();
+        let order = |order_uid: &str, delivery_service: &str, currency: &str, date_created: &str, items: &[(&str, u32)]| {
+            let mut order = sample_order(order_uid);
+            order.delivery_service = String::from(delivery_service);
+            let currency: Currency = currency.parse().unwrap();
+            order.payment.currency = currency;
+            order.date_created = date_created.parse().unwrap();
+            let template = order.items[0].clone();
+            order.items = items.iter().enumerate().map(|(index, (brand, total_price))| Item {
+                chrt_id: index as i64,
+                brand: brand.to_string(),
+                price: currency.money(453),
+                total_price: currency.money(*total_price),
+                ..template.clone()
+            }).collect();
+            order.payment.delivery_cost = currency.money(1500);
+            order.payment.custom_fee = currency.money(0);
+            order.payment.goods_total = currency.money(items.iter().map(|(_, total_price)| total_price).sum::<u32>());
+            order.payment.amount = order.payment.goods_total.checked_add(order.payment.delivery_cost).unwrap();
+            order
+        };
+        let orders = [
+            order("a", "meest", "USD", "2021-11-26T06:22:19Z", &[("Vivienne Sabo", 317), ("Maybelline", 317)]),
+            // 2021-11-27 in UTC
+            order("b", "meest", "USD", "2021-11-26T23:30:00-03:00", &[("Maybelline", 500)]),
+            order("c", "dhl", "RUB", "2021-11-27T10:00:00Z", &[("Nivea", 4000), ("Maybelline", 100)]),
+            order("d", "meest", "USD", "2021-11-28T10:00:00Z", &[("Nivea", 9000)]),
+        ];
+        for order in &orders {
+            let (status, _) = send_json(&mut app, "POST", "/orders", Some(serde_json::to_string(order).unwrap())).await;
+            assert_eq!(status, StatusCode::CREATED);
+        }
+        // deleted orders do not count
+        let (status, _) = send_json(&mut app, "DELETE", "/orders/d", None).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+
+        let (status, body) = send_json(&mut app, "GET", "/stats/revenue", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(serde_json::from_slice::<Value>(&body).unwrap(), json!([
+            {"delivery_service": "dhl", "currency": "RUB", "orders": 1, "amount": 5600},
+            {"delivery_service": "meest", "currency": "USD", "orders": 2, "amount": 4134},
+        ]));
+
+        let (_, body) = send_json(&mut app, "GET", "/stats/revenue?created_from=2021-11-26T10:00:00Z", None).await;
+        assert_eq!(serde_json::from_slice::<Value>(&body).unwrap(), json!([
+            {"delivery_service": "dhl", "currency": "RUB", "orders": 1, "amount": 5600},
+            {"delivery_service": "meest", "currency": "USD", "orders": 1, "amount": 2000},
+        ]));
+
+        let (status, body) = send_json(&mut app, "GET", "/stats/brands", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(serde_json::from_slice::<Value>(&body).unwrap(), json!([
+            {"brand": "Nivea", "currency": "RUB", "items": 1, "total_price": 4000},
+            {"brand": "Maybelline", "currency": "RUB", "items": 1, "total_price": 100},
+            {"brand": "Maybelline", "currency": "USD", "items": 2, "total_price": 817},
+            {"brand": "Vivienne Sabo", "currency": "USD", "items": 1, "total_price": 317},
+        ]));
+
+        let (_, body) = send_json(&mut app, "GET", "/stats/brands?limit=1&created_to=2021-11-26T10:00:00Z", None).await;
+        assert_eq!(serde_json::from_slice::<Value>(&body).unwrap(), json!([
+            {"brand": "Maybelline", "currency": "USD", "items": 1, "total_price": 317},
+        ]));
+
+        let (status, body) = send_json(&mut app, "GET", "/stats/daily", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(serde_json::from_slice::<Value>(&body).unwrap(), json!([
+            {"day": "2021-11-26", "currency": "USD", "orders": 1, "amount": 2134},
+            {"day": "2021-11-27", "currency": "RUB", "orders": 1, "amount": 5600},
+            {"day": "2021-11-27", "currency": "USD", "orders": 1, "amount": 2000},
+        ]));
+
+        // amounts in different currencies are never added together
+        let order = order("e", "meest", "JPY", "2021-11-29T10:00:00Z", &[("Maybelline", 1000)]);
+        let (status, _) = send_json(&mut app, "POST", "/orders", Some(serde_json::to_string(&order).unwrap())).await;
+        assert_eq!(status, StatusCode::CREATED);
+        let (_, body) = send_json(&mut app, "GET", "/stats/revenue?created_from=2021-11-26T10:00:00Z", None).await;
+        assert_eq!(serde_json::from_slice::<Value>(&body).unwrap(), json!([
+            {"delivery_service": "dhl", "currency": "RUB", "orders": 1, "amount": 5600},
+            {"delivery_service": "meest", "currency": "JPY", "orders": 1, "amount": 2500},
+            {"delivery_service": "meest", "currency": "USD", "orders": 1, "amount": 2000},
+        ]));
+
+        for uri in ["/stats/brands?limit=0", "/stats/brands?limit=101", "/stats/daily?created_from=yesterday", "/stats/revenue?created_to=1"] {
+            let (status, body) = send_json(&mut app, "GET", uri, None).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{}", uri);
+            assert_eq!(serde_json::from_slice::<Problem>(&body).unwrap().code, "invalid_query");
+        }
+    }
+
+    #[tokio::test]
+    async fn stats_test() {
+        for app in backend_apps().await {
+            stats_checks(app).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn order_events_test() {
+        let events = OrderEvents::new(EventsConfig { buffer: 2 });
+        let mut all = events.subscribe(EventFilter::default());
+        let mut other = events.subscribe(EventFilter { customer_id: Some(String::from("other")), delivery_service: None });
+        assert_eq!(events.subscribers(), 2);
+
+        // the third event overflows the buffer of `all`, `other` filters them out
+        for order_uid in ["1", "2", "3"] {
+            events.publish(OrderEventKind::Created, &sample_order(order_uid));
+        }
+        assert_eq!(events.subscribers(), 1);
+        assert_eq!(all.recv().await.unwrap().order.order_uid, "1");
+        assert_eq!(all.recv().await.unwrap().order.order_uid, "2");
+        assert!(all.recv().await.is_none());
+
+        let mut order = sample_order("4");
+        order.customer_id = String::from("other");
+        events.publish(OrderEventKind::Updated, &order);
+        assert_eq!(*other.recv().await.unwrap(), OrderEvent { event: OrderEventKind::Updated, order });
+
+        // subscribers that went away are dropped without an event to publish
+        for _ in 0..10 {
+            drop(events.subscribe(EventFilter::default()));
+        }
+        let _last = events.subscribe(EventFilter::default());
+        assert_eq!(events.subscribers(), 2);
+
+        events.close();
+        assert_eq!(events.subscribers(), 0);
+        assert!(other.recv().await.is_none());
+        assert!(events.subscribe(EventFilter::default()).recv().await.is_none());
+    }
+
+    // Reads lines of an SSE response until the next event and returns its name and data.
+    async fn next_sse_event(reader: &mut BufReader<tokio::net::TcpStream>) -> Option<(String, String)> {
+        let mut name = None;
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line).await.ok()? == 0 {
+                return None;
+            }
+            if let Some(event) = line.trim_end().strip_prefix("event: ") {
+                name = Some(event.to_string());
+            } else if let Some(data) = line.trim_end().strip_prefix("data: ") {
+                return Some((name?, data.to_string()));
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn order_streams_test() {
+        use futures_util::StreamExt;
+        use tokio_tungstenite::tungstenite::Message;
+
+        let events = OrderEvents::new(EventsConfig::default());
+        let repository: Arc<dyn OrderRepository> = Arc::new(PublishingOrderRepository::new(Arc::new(InMemoryOrderRepository::new()), events.clone()));
+        let app = init_app(&repository, &test_keys(), &test_webhooks(), &test_config(), &Metrics::new(), &events).await;
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (shutdown, shutdown_requested) = tokio::sync::oneshot::channel::<()>();
+        let closing = events.clone();
+        let server = tokio::spawn(serve(listener, app, async move {
+            shutdown_requested.await.ok();
+            closing.close();
+        }, std::time::Duration::from_secs(10)));
+
+        let mut sse = BufReader::new(tokio::net::TcpStream::connect(addr).await.unwrap());
+        sse.get_mut().write_all(b"GET /orders/events?customer_id=sse HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").await.unwrap();
+        let mut status = String::new();
+        sse.read_line(&mut status).await.unwrap();
+        assert!(status.starts_with("HTTP/1.1 200 OK"));
+
+        let (mut socket, _) = tokio_tungstenite::connect_async(format!("ws://{}/orders/ws?delivery_service=ws", addr)).await.unwrap();
+        assert_eq!(events.subscribers(), 2);
+
+        let mut for_sse = sample_order("1");
+        for_sse.customer_id = String::from("sse");
+        let mut for_socket = sample_order("2");
+        for_socket.delivery_service = String::from("ws");
+        assert!(repository.insert(&sample_order("3")).await.is_ok());
+        assert!(repository.insert(&for_sse).await.is_ok());
+        assert!(repository.insert(&for_socket).await.is_ok());
+        for_socket.entry = String::from("changed");
+        assert!(repository.update(&for_socket).await.is_ok());
+
+        let (name, data) = next_sse_event(&mut sse).await.unwrap();
+        assert_eq!(name, "created");
+        assert_eq!(serde_json::from_str::<Order>(&data).unwrap(), for_sse);
+
+        let mut received = Vec::new();
+        while received.len() < 2 {
+            match socket.next().await.unwrap().unwrap() {
+                Message::Text(text) => received.push(serde_json::from_str::<OrderEvent>(&text).unwrap()),
+                message => panic!("unexpected message {:?}", message),
+            }
+        }
+        assert_eq!(received[0].event, OrderEventKind::Created);
+        assert_eq!(received[0].order.entry, "WBIL");
+        assert_eq!(received[1], OrderEvent { event: OrderEventKind::Updated, order: for_socket });
+
+        // shutdown ends both streams
+        shutdown.send(()).unwrap();
+        assert!(next_sse_event(&mut sse).await.is_none());
+        assert!(matches!(socket.next().await, Some(Ok(Message::Close(_))) | None));
+        assert!(server.await.unwrap().is_ok());
+    }
+
+    #[tokio::test]
+    async fn auth_test() {
+        for storage in test_storages().await {
+            let keys = storage.keys();
+            let admin = auth::issue_key(keys.as_ref(), "admin", Scope::Admin).await.unwrap_or_else(|err| panic!("{}", err)).key;
+            let mut app = storage_app(&storage, &Config::default()).await.into_service();
+            let order = serde_json::to_string(&sample_order("1")).unwrap();
+
+            // a missing or unknown key is rejected, public routes need none
+            for key in [None, Some("l0_unknown")] {
+                let (status, headers, body) = send_with_key(&mut app, "GET", "/orders/1", key, None).await;
+                assert_eq!(status, StatusCode::UNAUTHORIZED);
+                assert_eq!(headers["www-authenticate"], "Bearer");
+                assert_eq!(serde_json::from_slice::<Problem>(&body).unwrap().code, "unauthorized");
+            }
+            for uri in ["/healthz", "/openapi.json"] {
+                assert_eq!(send_with_key(&mut app, "GET", uri, None, None).await.0, StatusCode::OK);
+            }
+
+            let mut issued = Vec::new();
+            for (name, scope) in [("support", "read"), ("shop", "write")] {
+                let body = format!(r#"{{"name": "{}", "scope": "{}"}}"#, name, scope);
+                let (status, _, body) = send_with_key(&mut app, "POST", "/admin/keys", Some(&admin), Some(body)).await;
+                assert_eq!(status, StatusCode::CREATED);
+                issued.push(serde_json::from_slice::<IssuedApiKey>(&body).unwrap());
+            }
+            // errors name the resource of the body
+            for (body, message) in [("{", "The request body is not a valid API key"), (r#"{"name": " ", "scope": "read"}"#, "The API key violates validation rules")] {
+                let (_, _, body) = send_with_key(&mut app, "POST", "/admin/keys", Some(&admin), Some(String::from(body))).await;
+                assert_eq!(serde_json::from_slice::<Problem>(&body).unwrap().message, message);
+            }
+            let [reader, writer] = <[IssuedApiKey; 2]>::try_from(issued).unwrap();
+            assert!(reader.key.starts_with("l0_"));
+            assert_eq!(reader.prefix, reader.key[..11]);
+            assert_eq!(reader.scope, Scope::Read);
+
+            // keys are looked up by their hash, sqlite shows only hashes are stored
+            for key in [&admin, &reader.key, &writer.key] {
+                assert!(matches!(keys.find(&hash_key(key)).await, Ok(Some(_))));
+            }
+            if let Storage::Sqlite(pool) = &storage {
+                let hashes: Vec<String> = pool.get().unwrap()
+                    .prepare("SELECT key_hash FROM api_keys ORDER BY id").unwrap()
+                    .query_map([], |row| row.get(0)).unwrap()
+                    .collect::<Result<_, _>>().unwrap();
+                assert_eq!(hashes, [hash_key(&admin), hash_key(&reader.key), hash_key(&writer.key)]);
+            }
+
+            // each scope includes the ones below it
+            let (status, _, body) = send_with_key(&mut app, "POST", "/orders", Some(&reader.key), Some(order.clone())).await;
+            assert_eq!(status, StatusCode::FORBIDDEN);
+            assert_eq!(serde_json::from_slice::<Problem>(&body).unwrap().code, "forbidden");
+            assert_eq!(send_with_key(&mut app, "POST", "/orders", Some(&writer.key), Some(order)).await.0, StatusCode::CREATED);
+            for key in [&reader.key, &writer.key] {
+                assert_eq!(send_with_key(&mut app, "GET", "/admin/keys", Some(key), None).await.0, StatusCode::FORBIDDEN);
+            }
+
+            // read-only keys get the order without the id and contact details of the customer
+            let (status, _, body) = send_with_key(&mut app, "GET", "/orders/1", Some(&reader.key), None).await;
+            assert_eq!(status, StatusCode::OK);
+            let redacted: Order = serde_json::from_slice(&body).unwrap();
+            let mut expected = sample_order("1");
+            expected.customer_id = String::from(REDACTED);
+            expected.delivery = Delivery {
+                name: String::from(REDACTED),
+                phone: String::from(REDACTED),
+                zip: String::from(REDACTED),
+                city: String::from(REDACTED),
+                address: String::from(REDACTED),
+                region: String::from(REDACTED),
+                email: String::from(REDACTED),
+            };
+            assert_eq!(redacted, expected);
+            let (_, _, body) = send_with_key(&mut app, "GET", "/orders", Some(&reader.key), None).await;
+            assert_eq!(serde_json::from_slice::<OrderPage>(&body).unwrap().orders, [expected]);
+            let (_, _, body) = send_with_key(&mut app, "GET", "/orders/export?format=csv", Some(&reader.key), None).await;
+            let csv = String::from_utf8(body).unwrap();
+            assert!(!csv.contains("Test Testov") && !csv.contains("Kiryat Mozkin") && !csv.contains("Kraiot"));
+            // nor find the orders of a customer by filtering
+            for uri in ["/orders?customer_id=test", "/orders?customer_id=unknown", "/orders/events?customer_id=test", "/orders/ws?customer_id=test"] {
+                let (status, _, body) = send_with_key(&mut app, "GET", uri, Some(&reader.key), None).await;
+                assert_eq!(status, StatusCode::FORBIDDEN, "{}", uri);
+                assert_eq!(serde_json::from_slice::<Problem>(&body).unwrap().code, "forbidden");
+            }
+            let (_, _, body) = send_with_key(&mut app, "GET", "/orders?customer_id=test", Some(&writer.key), None).await;
+            assert_eq!(serde_json::from_slice::<OrderPage>(&body).unwrap().orders, [sample_order("1")]);
+            let rows: Vec<Vec<&str>> = csv.lines().map(|line| line.split(',').collect()).collect();
+            let column = rows[0].iter().position(|name| *name == "customer_id").unwrap();
+            assert_eq!(rows[1][column], REDACTED);
+
+            let (_, _, body) = send_with_key(&mut app, "GET", "/orders/1", Some(&writer.key), None).await;
+            assert_eq!(serde_json::from_slice::<Order>(&body).unwrap(), sample_order("1"));
+
+            // revoked keys stop working but stay listed
+            let uri = format!("/admin/keys/{}", reader.id);
+            assert_eq!(send_with_key(&mut app, "DELETE", &uri, Some(&admin), None).await.0, StatusCode::NO_CONTENT);
+            assert_eq!(send_with_key(&mut app, "GET", "/orders/1", Some(&reader.key), None).await.0, StatusCode::UNAUTHORIZED);
+            let (status, _, body) = send_with_key(&mut app, "DELETE", "/admin/keys/99", Some(&admin), None).await;
+            assert_eq!(status, StatusCode::NOT_FOUND);
+            assert_eq!(serde_json::from_slice::<Problem>(&body).unwrap().code, "api_key_not_found");
+
+            let (status, _, body) = send_with_key(&mut app, "GET", "/admin/keys", Some(&admin), None).await;
+            assert_eq!(status, StatusCode::OK);
+            assert!(!String::from_utf8_lossy(&body).contains(&writer.key));
+            let listed: Vec<ApiKey> = serde_json::from_slice(&body).unwrap();
+            assert_eq!(listed.iter().map(|api_key| (api_key.name.as_str(), api_key.revoked_at.is_some())).collect::<Vec<_>>(),
+                [("admin", false), ("support", true), ("shop", false)]);
+        }
+    }
+
+    #[test]
+    fn rate_limiter_test() {
+        let limiter = RateLimiter::new(RateLimitConfig { burst: 2, per_second: 4.0 });
+        let start = std::time::Instant::now();
+        let at = |millis: u64| start + std::time::Duration::from_millis(millis);
+        let (client, other) = (Client::Key(1), Client::Address(None));
+
+        assert!(limiter.acquire(client, at(0)).is_ok());
+        assert!(limiter.acquire(client, at(0)).is_ok());
+        assert_eq!(limiter.acquire(client, at(0)), Err(std::time::Duration::from_millis(250)));
+        // buckets are per client
+        assert!(limiter.acquire(other, at(0)).is_ok());
+
+        // a token every 250ms, never more than the burst
+        let wait = limiter.acquire(client, at(100)).unwrap_err();
+        assert!(wait.abs_diff(std::time::Duration::from_millis(150)) < std::time::Duration::from_micros(1), "{:?}", wait);
+        assert!(limiter.acquire(client, at(250)).is_ok());
+        assert!(limiter.acquire(client, at(250)).is_err());
+        assert!(limiter.acquire(client, at(10_000)).is_ok());
+        assert!(limiter.acquire(client, at(10_000)).is_ok());
+        assert!(limiter.acquire(client, at(10_000)).is_err());
+
+        // a flood of new clients evicts the ones seen longest ago, not the busy ones
+        let limiter = RateLimiter::new(RateLimitConfig { burst: 1, per_second: 0.001 });
+        for id in 0..=MAX_CLIENTS as i64 {
+            assert!(limiter.acquire(Client::Key(id), at(id as u64)).is_ok());
+        }
+        let latest = Client::Key(MAX_CLIENTS as i64);
+        assert!(limiter.acquire(latest, at(MAX_CLIENTS as u64 + 1)).is_err());
+        assert!(limiter.acquire(Client::Key(0), at(MAX_CLIENTS as u64 + 1)).is_ok());
+
+        // a refill too slow for a Duration waits as long as one can
+        let limiter = RateLimiter::new(RateLimitConfig { burst: 1, per_second: 1e-300 });
+        assert!(limiter.acquire(client, at(0)).is_ok());
+        assert_eq!(limiter.acquire(client, at(0)), Err(std::time::Duration::MAX));
+    }
+
+    #[tokio::test]
+    async fn request_limits_test() {
+        let repository: Arc<dyn OrderRepository> = Arc::new(InMemoryOrderRepository::new());
+        let keys = test_keys();
+        let mut issued = Vec::new();
+        for name in ["first", "second"] {
+            issued.push(auth::issue_key(keys.as_ref(), name, Scope::Write).await.unwrap_or_else(|err| panic!("{}", err)).key);
+        }
+        let mut config = Config::default();
+        config.rate_limit.burst = 2;
+        config.rate_limit.per_second = 0.1;
+        config.server.max_items = 2;
+        let mut app = init_app(&repository, &keys, &test_webhooks(), &config, &Metrics::new(), &OrderEvents::new(EventsConfig::default())).await.into_service();
+
+        // orders with too many items are rejected before they reach the handler
+        let mut order = sample_order("1");
+        order.items.push(order.items[0].clone());
+        let (status, _, body) = send_with_key(&mut app, "POST", "/orders", Some(&issued[0]), Some(serde_json::to_string(&order).unwrap())).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        let problem: Problem = serde_json::from_slice(&body).unwrap();
+        assert_eq!(problem.detail.unwrap()[0]["rule"], "max_items");
+        let (_, _, body) = send_with_key(&mut app, "POST", "/orders/bulk", Some(&issued[0]), Some(serde_json::to_string(&order).unwrap())).await;
+        assert_eq!(serde_json::from_slice::<Value>(&body).unwrap()["errors"][0]["detail"][0]["rule"], "max_items");
+        assert!(repository.list().await.is_ok_and(|orders| orders.is_empty()));
+
+        // the two requests used up the burst of the first key
+        let (status, headers, body) = send_with_key(&mut app, "GET", "/orders", Some(&issued[0]), None).await;
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(headers["retry-after"], "10");
+        assert_eq!(serde_json::from_slice::<Problem>(&body).unwrap().code, "rate_limited");
+        assert_eq!(send_with_key(&mut app, "GET", "/orders", Some(&issued[1]), None).await.0, StatusCode::OK);
+        // public routes are not limited
+        assert_eq!(send_with_key(&mut app, "GET", "/healthz", Some(&issued[0]), None).await.0, StatusCode::OK);
+
+        // failed authentication is limited per client address, before the key is looked up
+        for key in [Some("l0_unknown"), None] {
+            assert_eq!(send_with_key(&mut app, "GET", "/orders", key, None).await.0, StatusCode::UNAUTHORIZED);
+        }
+        let (status, headers, _) = send_with_key(&mut app, "GET", "/orders", Some("l0_unknown"), None).await;
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(headers["retry-after"], "10");
+        assert_eq!(send_with_key(&mut app, "GET", "/healthz", Some("l0_unknown"), None).await.0, StatusCode::OK);
+    }
+
+    // A receiver answering every delivery with `status`, over TLS with the
+    // certificate of localhost in testdata if `tls` is set. The deliveries
+    // come out of the channel as their headers and body.
+    async fn webhook_receiver(status: Arc<AtomicUsize>, tls: bool) -> (String, tokio::sync::mpsc::UnboundedReceiver<(HashMap<String, String>, String)>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (url, acceptor) = match tls {
+            true => (format!("https://localhost:{}/orders", port), Some(tls_acceptor())),
+            false => (format!("http://127.0.0.1:{}/orders", port), None),
+        };
+        let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let (sender, status, acceptor) = (sender.clone(), Arc::clone(&status), acceptor.clone());
+                tokio::spawn(async move {
+                    match acceptor {
+                        Some(acceptor) => {
+                            if let Ok(stream) = acceptor.accept(stream).await {
+                                receive_delivery(stream, sender, status).await;
+                            }
+                        }
+                        None => receive_delivery(stream, sender, status).await,
+                    }
+                });
+            }
+        });
+        (url, receiver)
+    }
+
+    fn tls_acceptor() -> tokio_rustls::TlsAcceptor {
+        use tokio_rustls::rustls::{self, pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer}};
+        let certificates = CertificateDer::pem_slice_iter(include_bytes!("../testdata/webhooks-localhost.pem")).collect::<Result<Vec<_>, _>>().unwrap();
+        let key = PrivateKeyDer::from_pem_slice(include_bytes!("../testdata/webhooks-localhost.key")).unwrap();
+        let config = rustls::ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_no_client_auth()
+            .with_single_cert(certificates, key)
+            .unwrap();
+        tokio_rustls::TlsAcceptor::from(Arc::new(config))
+    }
+
+    async fn receive_delivery<S>(stream: S, sender: tokio::sync::mpsc::UnboundedSender<(HashMap<String, String>, String)>, status: Arc<AtomicUsize>)
+    where
+        S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin,
+    {
+        let mut stream = BufReader::new(stream);
+        let mut headers = HashMap::new();
+        loop {
+            let mut line = String::new();
+            stream.read_line(&mut line).await.unwrap();
+            let Some((name, value)) = line.trim_end().split_once(':') else {
+                if line.trim_end().is_empty() {
+                    break;
+                }
+                continue;
+            };
+            headers.insert(name.to_lowercase(), value.trim().to_string());
+        }
+        let mut body = vec![0; headers["content-length"].parse().unwrap()];
+        stream.read_exact(&mut body).await.unwrap();
+        sender.send((headers, String::from_utf8(body).unwrap())).ok();
+        let response = format!("HTTP/1.1 {} Webhook\r\ncontent-length: 0\r\nconnection: close\r\n\r\n", status.load(Ordering::SeqCst));
+        stream.write_all(response.as_bytes()).await.ok();
+        stream.shutdown().await.ok();
+    }
+
+    #[tokio::test]
+    async fn webhooks_test() {
+        let config = WebhooksConfig { max_attempts: 2, backoff_base_ms: 1, backoff_max_ms: 1, ..WebhooksConfig::default() };
+        let tls = config.tls_connector().unwrap();
+        for storage in test_storages().await {
+            let status = Arc::new(AtomicUsize::new(200));
+            let (url, mut received) = webhook_receiver(Arc::clone(&status), false).await;
+            let webhooks = storage.webhooks();
+            let metrics = Metrics::new();
+            let mut app = storage_app(&storage, &test_config()).await.into_service();
+
+            // only http:// and https:// receivers can be registered
+            for url in [" ", "ftp://localhost/orders", "localhost"] {
+                let (status, body) = send_json(&mut app, "POST", "/admin/webhooks", Some(json!({"url": url}).to_string())).await;
+                assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY, "{}", url);
+                assert_eq!(serde_json::from_slice::<Problem>(&body).unwrap().message, "The webhook violates validation rules");
+            }
+            let (status_code, body) = send_json(&mut app, "POST", "/admin/webhooks", Some(json!({"url": url}).to_string())).await;
+            assert_eq!(status_code, StatusCode::CREATED);
+            let webhook: CreatedWebhook = serde_json::from_slice(&body).unwrap();
+            assert!(webhook.secret.starts_with("whsec_"));
+            // the secret is shown once
+            let (_, body) = send_json(&mut app, "GET", "/admin/webhooks", None).await;
+            assert!(!String::from_utf8_lossy(&body).contains(&webhook.secret));
+            let listed: Vec<Webhook> = serde_json::from_slice(&body).unwrap();
+            assert_eq!(listed.len(), 1);
+            assert_eq!((listed[0].id, listed[0].url.as_str(), listed[0].deleted_at.as_deref()), (webhook.id, url.as_str(), None));
+
+            // every stored change of an order is an event, rejected ones are not
+            let mut order = sample_order("1");
+            assert_eq!(send_json(&mut app, "POST", "/orders", Some(serde_json::to_string(&order).unwrap())).await.0, StatusCode::CREATED);
+            order.delivery.city = String::from("Moscow");
+            assert!(send_json(&mut app, "PUT", "/orders/1", Some(serde_json::to_string(&order).unwrap())).await.0.is_success());
+            let mut invalid = sample_order("2");
+            invalid.payment.amount = invalid.payment.currency.money(1);
+            assert_eq!(send_json(&mut app, "POST", "/orders", Some(serde_json::to_string(&invalid).unwrap())).await.0, StatusCode::UNPROCESSABLE_ENTITY);
+            assert!(send_json(&mut app, "DELETE", "/orders/1", None).await.0.is_success());
+
+            assert_eq!(webhooks::dispatch(&config, &tls, webhooks.as_ref(), &metrics).await.unwrap_or_else(|err| panic!("{}", err)), 3);
+            let mut events = Vec::new();
+            for _ in 0..3 {
+                let (headers, body) = received.recv().await.unwrap();
+                let timestamp: i64 = headers["x-l0-timestamp"].parse().unwrap();
+                assert_eq!(headers["x-l0-signature"], webhooks::sign(&webhook.secret, timestamp, &body));
+                assert_eq!(headers["content-type"], "application/json");
+                assert_eq!(Some(headers["host"].as_str()), url.strip_prefix("http://").and_then(|url| url.strip_suffix("/orders")));
+                let event: WebhookEvent = serde_json::from_str(&body).unwrap();
+                assert_eq!(headers["x-l0-event"], event.event.as_str());
+                events.push(event);
+            }
+            events.sort_by_key(|event| event.id);
+            let changes: Vec<OrderChange> = events.iter().map(|event| event.event).collect();
+            assert_eq!(changes, [OrderChange::Created, OrderChange::Updated, OrderChange::Deleted]);
+            assert_eq!(events[0].order, sample_order("1"));
+            assert_eq!(events[1].order, order);
+            assert_eq!(events[2].order, order);
+            assert!(received.try_recv().is_err());
+
+            let uri = format!("/admin/webhooks/{}/deliveries", webhook.id);
+            let (status_code, body) = send_json(&mut app, "GET", &uri, None).await;
+            assert_eq!(status_code, StatusCode::OK);
+            let deliveries: Vec<WebhookDelivery> = serde_json::from_slice(&body).unwrap();
+            assert_eq!(deliveries.len(), 3);
+            assert_eq!(deliveries[0].event, OrderChange::Deleted);
+            assert!(deliveries.iter().all(|delivery| delivery.status == DeliveryStatus::Delivered && delivery.attempts == 1 && delivery.next_attempt_at.is_none()), "{:?}", deliveries);
+
+            // failed attempts are retried with a backoff, after the last one the delivery is dead
+            status.store(500, Ordering::SeqCst);
+            assert_eq!(send_json(&mut app, "POST", "/orders", Some(serde_json::to_string(&sample_order("2")).unwrap())).await.0, StatusCode::CREATED);
+            assert_eq!(webhooks::dispatch(&config, &tls, webhooks.as_ref(), &metrics).await.unwrap_or_else(|err| panic!("{}", err)), 1);
+            received.recv().await.unwrap();
+            let (_, body) = send_json(&mut app, "GET", &format!("{}?status=pending", uri), None).await;
+            let pending: Vec<WebhookDelivery> = serde_json::from_slice(&body).unwrap();
+            assert_eq!(pending.len(), 1);
+            assert_eq!((pending[0].order_uid.as_str(), pending[0].attempts), ("2", 1));
+            assert!(pending[0].last_error.as_deref().is_some_and(|error| error.contains("500")));
+            assert!(pending[0].next_attempt_at.is_some());
+
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+            assert_eq!(webhooks::dispatch(&config, &tls, webhooks.as_ref(), &metrics).await.unwrap_or_else(|err| panic!("{}", err)), 1);
+            received.recv().await.unwrap();
+            let (_, body) = send_json(&mut app, "GET", &format!("{}?status=dead", uri), None).await;
+            let dead: Vec<WebhookDelivery> = serde_json::from_slice(&body).unwrap();
+            assert_eq!(dead.len(), 1);
+            assert_eq!((dead[0].id, dead[0].attempts, dead[0].next_attempt_at.as_deref()), (pending[0].id, 2, None));
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+            assert_eq!(webhooks::dispatch(&config, &tls, webhooks.as_ref(), &metrics).await.unwrap_or_else(|err| panic!("{}", err)), 0);
+
+            // until it is retried
+            status.store(200, Ordering::SeqCst);
+            let (status_code, body) = send_json(&mut app, "POST", &format!("{}/{}/retry", uri, dead[0].id), None).await;
+            assert_eq!(status_code, StatusCode::OK);
+            let retried: WebhookDelivery = serde_json::from_slice(&body).unwrap();
+            assert_eq!((retried.status, retried.attempts), (DeliveryStatus::Pending, 0));
+            assert_eq!(webhooks::dispatch(&config, &tls, webhooks.as_ref(), &metrics).await.unwrap_or_else(|err| panic!("{}", err)), 1);
+            let (_, body) = received.recv().await.unwrap();
+            assert_eq!(serde_json::from_str::<WebhookEvent>(&body).unwrap().id, dead[0].event_id);
+            let (_, body) = send_json(&mut app, "GET", &format!("{}?status=delivered&limit=10", uri), None).await;
+            assert_eq!(serde_json::from_slice::<Vec<WebhookDelivery>>(&body).unwrap().len(), 4);
+
+            let rendered = metrics.render();
+            for line in [
+                r#"l0_webhook_attempts_total{result="delivered"} 4"#,
+                r#"l0_webhook_attempts_total{result="failed"} 1"#,
+                r#"l0_webhook_attempts_total{result="dead"} 1"#,
+            ] {
+                assert!(rendered.contains(line), "{}", line);
+            }
+
+            let not_found = [
+                ("POST", format!("{}/999/retry", uri), "webhook_delivery_not_found"),
+                ("POST", String::from("/admin/webhooks/999/deliveries/1/retry"), "webhook_delivery_not_found"),
+                ("GET", String::from("/admin/webhooks/999/deliveries"), "webhook_not_found"),
+                ("DELETE", String::from("/admin/webhooks/abc"), "webhook_not_found"),
+            ];
+            for (method, uri, code) in not_found {
+                let (status_code, body) = send_json(&mut app, method, &uri, None).await;
+                assert_eq!(status_code, StatusCode::NOT_FOUND, "{} {}", method, uri);
+                assert_eq!(serde_json::from_slice::<Problem>(&body).unwrap().code, code);
+            }
+
+            // a deleted webhook gets no new events and its pending deliveries are dead
+            status.store(500, Ordering::SeqCst);
+            assert_eq!(send_json(&mut app, "POST", "/orders", Some(serde_json::to_string(&sample_order("3")).unwrap())).await.0, StatusCode::CREATED);
+            assert_eq!(webhooks::dispatch(&config, &tls, webhooks.as_ref(), &metrics).await.unwrap_or_else(|err| panic!("{}", err)), 1);
+            received.recv().await.unwrap();
+            for _ in 0..2 {
+                assert_eq!(send_json(&mut app, "DELETE", &format!("/admin/webhooks/{}", webhook.id), None).await.0, StatusCode::NO_CONTENT);
+            }
+            let (_, body) = send_json(&mut app, "GET", &uri, None).await;
+            let deliveries: Vec<WebhookDelivery> = serde_json::from_slice(&body).unwrap();
+            assert_eq!((deliveries[0].order_uid.as_str(), deliveries[0].status), ("3", DeliveryStatus::Dead));
+            assert_eq!(deliveries[0].last_error.as_deref(), Some("the webhook has been deleted"));
+            let (status_code, _) = send_json(&mut app, "POST", &format!("{}/{}/retry", uri, deliveries[0].id), None).await;
+            assert_eq!(status_code, StatusCode::NOT_FOUND);
+
+            assert_eq!(send_json(&mut app, "POST", "/orders", Some(serde_json::to_string(&sample_order("4")).unwrap())).await.0, StatusCode::CREATED);
+            assert_eq!(webhooks::dispatch(&config, &tls, webhooks.as_ref(), &metrics).await.unwrap_or_else(|err| panic!("{}", err)), 0);
+            assert!(received.try_recv().is_err());
+            let (_, body) = send_json(&mut app, "GET", "/admin/webhooks", None).await;
+            assert!(serde_json::from_slice::<Vec<Webhook>>(&body).unwrap()[0].deleted_at.is_some());
+        }
+    }
+
+    #[tokio::test]
+    async fn webhook_retention_test() {
+        let config = WebhooksConfig { max_attempts: 2, backoff_base_ms: 60_000, backoff_max_ms: 60_000, ..WebhooksConfig::default() };
+        let tls = config.tls_connector().unwrap();
+        let now = Utc::now();
+        assert_eq!(config.retained_since(now), Some(now - TimeDelta::hours(168)));
+        assert_eq!(WebhooksConfig { retention_hours: 0, ..config.clone() }.retained_since(now), None);
+        assert_eq!(WebhooksConfig { retention_hours: u64::MAX, ..config.clone() }.retained_since(now), None);
+
+        for storage in test_storages().await {
+            let status = Arc::new(AtomicUsize::new(200));
+            let (url, mut received) = webhook_receiver(Arc::clone(&status), false).await;
+            let (webhooks, metrics) = (storage.webhooks(), Metrics::new());
+            let mut app = storage_app(&storage, &test_config()).await.into_service();
+            assert_eq!(send_json(&mut app, "POST", "/admin/webhooks", Some(json!({"url": url}).to_string())).await.0, StatusCode::CREATED);
+            for uid in ["1", "2"] {
+                assert_eq!(send_json(&mut app, "POST", "/orders", Some(serde_json::to_string(&sample_order(uid)).unwrap())).await.0, StatusCode::CREATED);
+            }
+            assert_eq!(webhooks::dispatch(&config, &tls, webhooks.as_ref(), &metrics).await.unwrap_or_else(|err| panic!("{}", err)), 2);
+            status.store(500, Ordering::SeqCst);
+            assert_eq!(send_json(&mut app, "POST", "/orders", Some(serde_json::to_string(&sample_order("3")).unwrap())).await.0, StatusCode::CREATED);
+            assert_eq!(webhooks::dispatch(&config, &tls, webhooks.as_ref(), &metrics).await.unwrap_or_else(|err| panic!("{}", err)), 1);
+            for _ in 0..3 {
+                received.recv().await.unwrap();
+            }
+
+            // recent deliveries are kept
+            assert_eq!(webhooks.purge(Utc::now() - TimeDelta::hours(1)).await.unwrap_or_else(|err| panic!("{}", err)), 0);
+            tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+            // pending ones are kept however old they are
+            assert_eq!(webhooks.purge(Utc::now()).await.unwrap_or_else(|err| panic!("{}", err)), 2);
+            let (_, body) = send_json(&mut app, "GET", "/admin/webhooks/1/deliveries", None).await;
+            let deliveries: Vec<WebhookDelivery> = serde_json::from_slice(&body).unwrap();
+            assert_eq!(deliveries.len(), 1);
+            assert_eq!((deliveries[0].order_uid.as_str(), deliveries[0].status), ("3", DeliveryStatus::Pending));
+            // and so are the events of pending deliveries
+            if let Storage::Sqlite(pool) = &storage {
+                let events: Vec<String> = pool.get().unwrap().prepare("SELECT order_uid FROM outbox").unwrap()
+                    .query_map([], |row| row.get(0)).unwrap().collect::<Result<_, _>>().unwrap();
+                assert_eq!(events, ["3"]);
+            }
+
+            // ids of deleted deliveries are not given out again
+            status.store(200, Ordering::SeqCst);
+            assert_eq!(send_json(&mut app, "POST", "/orders", Some(serde_json::to_string(&sample_order("4")).unwrap())).await.0, StatusCode::CREATED);
+            assert_eq!(webhooks::dispatch(&config, &tls, webhooks.as_ref(), &metrics).await.unwrap_or_else(|err| panic!("{}", err)), 1);
+            received.recv().await.unwrap();
+            let (_, body) = send_json(&mut app, "GET", "/admin/webhooks/1/deliveries", None).await;
+            let deliveries: Vec<WebhookDelivery> = serde_json::from_slice(&body).unwrap();
+            assert_eq!(deliveries.len(), 2);
+            assert!(deliveries[0].id > 3, "{:?}", deliveries);
+            assert_eq!(deliveries[0].status, DeliveryStatus::Delivered);
+        }
+    }
+
+    #[tokio::test]
+    async fn webhooks_https_test() {
+        let memory = DatabaseConfig { backend: Backend::Memory, ..DatabaseConfig::default() };
+        let storage = Storage::open(&memory).unwrap();
+        storage.migrate(None).await.unwrap();
+        let (url, mut received) = webhook_receiver(Arc::new(AtomicUsize::new(200)), true).await;
+        // the credentials of the URL stay out of the Host header
+        let host = url.trim_start_matches("https://").trim_end_matches("/orders").to_string();
+        let url = url.replacen("https://", "https://user:secret@", 1);
+        let (webhooks, metrics) = (storage.webhooks(), Metrics::new());
+        let mut app = storage_app(&storage, &test_config()).await.into_service();
+        assert_eq!(send_json(&mut app, "POST", "/admin/webhooks", Some(json!({"url": url}).to_string())).await.0, StatusCode::CREATED);
+        assert_eq!(send_json(&mut app, "POST", "/orders", Some(serde_json::to_string(&sample_order("1")).unwrap())).await.0, StatusCode::CREATED);
+
+        // the test CA is not one of the public roots
+        let config = WebhooksConfig { max_attempts: 2, backoff_base_ms: 1, backoff_max_ms: 1, ..WebhooksConfig::default() };
+        assert_eq!(webhooks::dispatch(&config, &config.tls_connector().unwrap(), webhooks.as_ref(), &metrics).await.unwrap_or_else(|err| panic!("{}", err)), 1);
+        let (_, body) = send_json(&mut app, "GET", "/admin/webhooks/1/deliveries", None).await;
+        let deliveries: Vec<WebhookDelivery> = serde_json::from_slice(&body).unwrap();
+        assert_eq!(deliveries[0].status, DeliveryStatus::Pending);
+        assert!(deliveries[0].last_error.as_deref().is_some_and(|error| error.starts_with("TLS handshake failed")), "{:?}", deliveries[0]);
+        assert!(received.try_recv().is_err());
+
+        let ca_file = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/webhooks-ca.pem");
+        let config = WebhooksConfig { ca_file: Some(String::from(ca_file)), ..config };
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        assert_eq!(webhooks::dispatch(&config, &config.tls_connector().unwrap(), webhooks.as_ref(), &metrics).await.unwrap_or_else(|err| panic!("{}", err)), 1);
+        let (headers, body) = received.recv().await.unwrap();
+        assert_eq!(headers["host"], host);
+        assert_eq!(serde_json::from_str::<WebhookEvent>(&body).unwrap().order, sample_order("1"));
+        let (_, body) = send_json(&mut app, "GET", "/admin/webhooks/1/deliveries", None).await;
+        assert_eq!(serde_json::from_slice::<Vec<WebhookDelivery>>(&body).unwrap()[0].status, DeliveryStatus::Delivered);
+
+        let missing = WebhooksConfig { ca_file: Some(String::from("/nonexistent/ca.pem")), ..WebhooksConfig::default() };
+        assert!(missing.tls_connector().is_err());
+    }
+
+    // Strategies for orders that pass validation.
+    fn arb_text() -> impl Strategy<Value = String> {
+        "(?s).{0,24}"
+    }
+
+    fn arb_required() -> impl Strategy<Value = String> {
+        arb_text().prop_filter("required fields must not be blank", |text| !text.trim().is_empty())
+    }
+
+    fn arb_timestamp() -> impl Strategy<Value = Timestamp> {
+        (0i64..4_102_444_800, 0u32..1_000_000_000, -1439i32..1440, 0usize..4, any::<bool>()).prop_map(|(seconds, nanos, minutes, precision, use_z)| {
+            let offset = chrono::FixedOffset::east_opt(minutes * 60).unwrap();
+            let precision = [chrono::SecondsFormat::Secs, chrono::SecondsFormat::Millis, chrono::SecondsFormat::Nanos, chrono::SecondsFormat::AutoSi][precision];
+            chrono::DateTime::from_timestamp(seconds, nanos).unwrap().with_timezone(&offset).to_rfc3339_opts(precision, use_z).parse().unwrap()
+        })
+    }
+
+    fn arb_delivery() -> impl Strategy<Value = Delivery> {
+        let email = prop_oneof![Just(String::new()), (arb_text(), arb_text()).prop_map(|(user, domain)| format!("{}@{}", user, domain))];
+        (arb_required(), arb_required(), arb_text(), arb_required(), arb_required(), arb_text(), email)
+            .prop_map(|(name, phone, zip, city, address, region, email)| Delivery { name, phone, zip, city, address, region, email })
+    }
+
+    fn arb_payment() -> impl Strategy<Value = Payment> {
+        let money = 0..=u32::MAX / 2;
+        let currency = "[A-Z]{3}".prop_map(|code| code.parse::<Currency>().unwrap());
+        let payment_dt = (chrono::DateTime::<chrono::Utc>::MIN_UTC.timestamp()..=chrono::DateTime::<chrono::Utc>::MAX_UTC.timestamp())
+            .prop_map(|seconds| UnixTime::from_seconds(seconds).unwrap());
+        (arb_required(), arb_text(), currency, arb_required(), payment_dt, arb_text(), money.clone(), money, any::<u32>())
+            .prop_map(|(transaction, request_id, currency, provider, payment_dt, bank, delivery_cost, goods_total, custom_fee)| Payment {
+                transaction,
+                request_id,
+                currency,
+                provider,
+                amount: currency.money(delivery_cost + goods_total),
+                payment_dt,
+                bank,
+                delivery_cost: currency.money(delivery_cost),
+                goods_total: currency.money(goods_total),
+                custom_fee: currency.money(custom_fee),
+            })
+    }
+
+    // Amounts in the currency of the payment of the order.
+    fn arb_item(currency: Currency) -> impl Strategy<Value = Item> {
+        (any::<i64>(), any::<u32>(), arb_required(), arb_required(), 0u32..=100, arb_text(), any::<u32>(), any::<i64>(), arb_text(), any::<i32>())
+            .prop_map(move |(chrt_id, price, rid, name, sale, size, total_price, nm_id, brand, status)| Item {
+                chrt_id,
+                // replaced with the track_number of the order
+                track_number: String::new(),
+                price: currency.money(price),
+                rid,
+                name,
+                sale,
+                size,
+                total_price: currency.money(total_price),
+                nm_id,
+                brand,
+                status,
+            })
+    }
+
+    fn arb_order() -> impl Strategy<Value = Order> {
+        let locale = proptest::sample::select(Locale::ALL.to_vec());
+        let header = (arb_required(), arb_required(), arb_required(), locale, arb_text(), arb_required(), arb_required());
+        let rest = (arb_text(), any::<i32>(), arb_timestamp(), arb_text());
+        let details = (arb_delivery(), arb_payment().prop_flat_map(|payment| {
+            let currency = payment.currency;
+            (Just(payment), proptest::collection::vec(arb_item(currency), 1..4))
+        }));
+        (header, rest, details).prop_map(|(header, rest, details)| {
+            let (order_uid, track_number, entry, locale, internal_signature, customer_id, delivery_service) = header;
+            let (shardkey, sm_id, date_created, oof_shard) = rest;
+            let (delivery, (payment, mut items)) = details;
+            for item in items.iter_mut() {
+                item.track_number = track_number.clone();
+            }
+            Order {
+                order_uid,
+                track_number,
+                entry,
+                delivery,
+                payment,
+                items,
+                locale,
+                internal_signature,
+                customer_id,
+                delivery_service,
+                shardkey,
+                sm_id,
+                date_created,
+                oof_shard,
+            }
+        })
+    }
+
+    fn percent_encode(text: &str) -> String {
+        text.bytes().map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => (byte as char).to_string(),
+            _ => format!("%{:02X}", byte),
+        }).collect()
+    }
+
+    // PROPTEST_CASES overrides `cases` for longer fuzzing runs.
+    fn proptest_config(cases: u32) -> proptest::test_runner::Config {
+        let cases = std::env::var("PROPTEST_CASES").ok().and_then(|cases| cases.parse().ok()).unwrap_or(cases);
+        proptest::test_runner::Config { cases, failure_persistence: None, ..proptest::test_runner::Config::default() }
+    }
+
+    fn roundtrip(app: Router, orders: impl Strategy<Value = Order>) {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let mut runner = proptest::test_runner::TestRunner::new(proptest_config(64));
+        let next_order = AtomicUsize::new(0);
+
+        runner.run(&orders, |mut order| {
+            // shrinking reruns cases, every run stores a new order
+            order.order_uid = format!("{}#{}", order.order_uid, next_order.fetch_add(1, Ordering::SeqCst));
+            let mut app = app.clone().into_service();
+            runtime.block_on(async {
+                let body = serde_json::to_string(&order).unwrap();
+                let (status, response) = send_json(&mut app, "POST", "/orders", Some(body.clone())).await;
+                prop_assert_eq!(status, StatusCode::CREATED, "{}", String::from_utf8_lossy(&response));
+
+                let (status, response) = send_json(&mut app, "GET", &format!("/orders/{}", percent_encode(&order.order_uid)), None).await;
+                prop_assert_eq!(status, StatusCode::OK);
+                prop_assert_eq!(serde_json::from_slice::<Order>(&response).unwrap(), order.clone());
+
+                let (status, _) = send_json(&mut app, "POST", "/orders", Some(body)).await;
+                prop_assert_eq!(status, StatusCode::OK);
+                Ok(())
+            })
+        }).unwrap();
+    }
+
+    #[test]
+    fn post_get_roundtrip_test() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        roundtrip(runtime.block_on(app()), arb_order());
+    }
+
+    #[test]
+    fn sqlite_post_get_roundtrip_test() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        roundtrip(runtime.block_on(sqlite_app()), arb_order());
+    }
+
+    #[cfg(feature = "postgres")]
+    #[test]
+    fn postgres_post_get_roundtrip_test() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let app = runtime.block_on(async {
+            let storage = postgres_storage().await;
+            storage.migrate(None).await.unwrap();
+            storage_app(&storage, &test_config()).await
+        });
+        roundtrip(app, arb_order().prop_map(without_nul));
+    }
+
+    // PostgreSQL text and JSONB cannot hold NUL characters, the other backends can.
+    #[cfg(feature = "postgres")]
+    fn without_nul(order: Order) -> Order {
+        fn replace(value: &mut Value) {
+            match value {
+                Value::String(text) => *text = text.replace('\0', "?"),
+                Value::Array(values) => values.iter_mut().for_each(replace),
+                Value::Object(fields) => fields.values_mut().for_each(replace),
+                _ => {}
+            }
+        }
+        let mut value = serde_json::to_value(order).unwrap();
+        replace(&mut value);
+        serde_json::from_value(value).unwrap()
+    }
+
+    // Arbitrary bytes and corrupted orders sent to the JSON handlers must be
+    // rejected, not crash the server or fail as a storage error.
+    #[test]
+    fn order_json_fuzz_test() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let app = runtime.block_on(sqlite_app());
+        let mut runner = proptest::test_runner::TestRunner::new(proptest_config(256));
+
+        let corrupted = (arb_order(), proptest::collection::vec((any::<proptest::sample::Index>(), any::<u8>()), 1..8))
+            .prop_map(|(order, changes)| {
+                let mut bytes = serde_json::to_vec(&order).unwrap();
+                for (index, byte) in changes {
+                    let index = index.index(bytes.len());
+                    bytes[index] = byte;
+                }
+                bytes
+            });
+        let bytes = prop_oneof![proptest::collection::vec(any::<u8>(), 0..512), corrupted];
+
+        runner.run(&(bytes, any::<bool>()), |(bytes, put)| {
+            let mut app = app.clone().into_service();
+            runtime.block_on(async {
+                let (method, uri) = if put { ("PUT", "/orders/fuzz") } else { ("POST", "/orders") };
+                let request = Request::builder()
+                    .method(method)
+                    .uri(uri)
+                    .header("content-type", "application/json")
+                    .body(Body::from(bytes))
+                    .unwrap();
+                let response = ServiceExt::<Request<Body>>::ready(&mut app).await.unwrap().call(request).await.unwrap();
+                prop_assert!(!response.status().is_server_error(), "{}", response.status());
+                Ok(())
+            })
+        }).unwrap();
+    }
+
+    #[derive(Clone, Debug)]
+    enum FuzzValue {
+        Sql(rusqlite::types::Value),
+        // bytes stored as TEXT, they need not be UTF-8
+        Text(Vec<u8>),
+    }
+
+    fn arb_sql_value(nullable: bool) -> impl Strategy<Value = FuzzValue> {
+        use rusqlite::types::Value as SqlValue;
+        prop_oneof![
+            4 => any::<i64>().prop_map(|value| FuzzValue::Sql(SqlValue::Integer(value))),
+            2 => any::<f64>().prop_map(|value| FuzzValue::Sql(SqlValue::Real(value))),
+            4 => arb_text().prop_map(|value| FuzzValue::Sql(SqlValue::Text(value))),
+            2 => proptest::collection::vec(any::<u8>(), 0..16).prop_map(|value| FuzzValue::Sql(SqlValue::Blob(value))),
+            2 => proptest::collection::vec(any::<u8>(), 0..16).prop_map(FuzzValue::Text),
+            1 => Just(FuzzValue::Sql(if nullable { SqlValue::Null } else { SqlValue::Integer(0) })),
+        ]
+    }
+
+    // Inserts a row of arbitrary values, `order_uid` is always text.
+    fn insert_fuzz_row(connection: &rusqlite::Connection, table: &str, columns: &[&str], order_uid: &str, values: &[FuzzValue]) -> rusqlite::Result<usize> {
+        let mut placeholders = vec![String::from("?1")];
+        let mut parameters = vec![rusqlite::types::Value::Text(order_uid.to_string())];
+        for value in values {
+            parameters.push(match value {
+                FuzzValue::Sql(value) => value.clone(),
+                FuzzValue::Text(bytes) => rusqlite::types::Value::Blob(bytes.clone()),
+            });
+            placeholders.push(match value {
+                FuzzValue::Sql(_) => format!("?{}", parameters.len()),
+                FuzzValue::Text(_) => format!("CAST(?{} AS TEXT)", parameters.len()),
+            });
+        }
+        connection.execute(
+            &format!("INSERT INTO {} (order_uid, {}) VALUES ({})", table, columns.join(", "), placeholders.join(", ")),
+            rusqlite::params_from_iter(parameters),
+        )
+    }
+
+    // Whatever the tables contain, loading orders fails with an error at worst.
+    #[test]
+    fn order_row_fuzz_test() {
+        let pool = temp_pool(1);
+        let mut connection = pool.get().unwrap();
+        migrations::migrate(&mut connection, None).ok().unwrap();
+        let mut runner = proptest::test_runner::TestRunner::new(proptest_config(256));
+        let next_order = AtomicUsize::new(0);
+
+        const ORDER_COLUMNS: [&str; 10] = ["track_number", "entry", "locale", "internal_signature", "customer_id", "delivery_service", "shardkey", "sm_id", "date_created", "oof_shard"];
+        const DELIVERY_COLUMNS: [&str; 7] = ["name", "phone", "zip", "city", "address", "region", "email"];
+        const PAYMENT_COLUMNS: [&str; 10] = ["[transaction]", "request_id", "currency", "provider", "amount", "payment_dt", "bank", "delivery_cost", "goods_total", "custom_fee"];
+        const ITEM_COLUMNS: [&str; 12] = ["position", "chrt_id", "track_number", "price", "rid", "name", "sale", "size", "total_price", "nm_id", "brand", "status"];
+
+        let rows = (
+            proptest::collection::vec(arb_sql_value(true), ORDER_COLUMNS.len()),
+            proptest::collection::vec(arb_sql_value(false), DELIVERY_COLUMNS.len()),
+            proptest::collection::vec(arb_sql_value(false), PAYMENT_COLUMNS.len()),
+            proptest::collection::vec(proptest::collection::vec(arb_sql_value(false), ITEM_COLUMNS.len()), 0..3),
+        );
+        runner.run(&rows, |(order, delivery, payment, items)| {
+            let order_uid = format!("fuzz-{}", next_order.fetch_add(1, Ordering::SeqCst));
+            // NOT NULL columns of orders reject NULL, such rows cannot exist
+            if insert_fuzz_row(&connection, "orders", &ORDER_COLUMNS, &order_uid, &order).is_err() {
+                return Ok(());
+            }
+            insert_fuzz_row(&connection, "deliveries", &DELIVERY_COLUMNS, &order_uid, &delivery).unwrap();
+            insert_fuzz_row(&connection, "payments", &PAYMENT_COLUMNS, &order_uid, &payment).unwrap();
+            for item in &items {
+                insert_fuzz_row(&connection, "items", &ITEM_COLUMNS, &order_uid, item).unwrap();
+            }
+
+            let loaded = repository::load_orders(&connection, "WHERE orders.order_uid = ?1", rusqlite::params![order_uid]);
+            if let Ok(orders) = loaded {
+                prop_assert_eq!(orders.len(), 1);
+                prop_assert_eq!(&orders[0].order_uid, &order_uid);
+                prop_assert_eq!(orders[0].items.len(), items.len());
+            }
+            Ok(())
+        }).unwrap();
+
+        // the whole table at once, an error is fine
+        repository::load_orders(&connection, "", []).ok();
     }
 }
\ No newline at end of file
@@ -0,0 +1,69 @@
+use std::sync::Arc;
+use askama::Template;
+use axum::{extract::Query, http::StatusCode, response::{Html, IntoResponse, Response}, Extension};
+use serde::Deserialize;
+
+use crate::Order;
+use crate::domain::UnixTime;
+use crate::auth::Access;
+use crate::repository::OrderRepository;
+
+// Query string of GET /ui.
+#[derive(Deserialize, Default, Debug)]
+pub struct OrderLookup {
+    #[serde(default)]
+    pub order_uid: String,
+}
+
+// Compiled from templates/order.html, the page does not need any files at runtime.
+#[derive(Template)]
+#[template(path = "order.html")]
+struct OrderTemplate<'a> {
+    order_uid: &'a str,
+    order: Option<&'a Order>,
+    message: Option<String>,
+}
+
+fn payment_time(payment_dt: &UnixTime) -> String {
+    payment_dt.utc().format("%Y-%m-%d %H:%M:%S UTC").to_string()
+}
+
+fn render(status: StatusCode, template: OrderTemplate<'_>) -> Response {
+    match template.render() {
+        Ok(html) => (status, Html(html)).into_response(),
+        Err(err) => {
+            tracing::error!(%err, "Failed to render the order page");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+// Search form for support staff, shows the order `get_order` would return.
+pub async fn order_page(Extension(repository): Extension<Arc<dyn OrderRepository>>, Extension(access): Extension<Access>, lookup: Option<Query<OrderLookup>>) -> Response {
+    let Query(lookup) = lookup.unwrap_or_default();
+    let order_uid = lookup.order_uid.trim();
+    crate::logging::record_order_uid(order_uid);
+    if order_uid.is_empty() {
+        return render(StatusCode::OK, OrderTemplate { order_uid, order: None, message: None });
+    }
+
+    match repository.get(order_uid).await {
+        Ok(Some(mut order)) => {
+            access.redact(&mut order);
+            render(StatusCode::OK, OrderTemplate { order_uid, order: Some(&order), message: None })
+        }
+        Ok(None) => render(StatusCode::NOT_FOUND, OrderTemplate {
+            order_uid,
+            order: None,
+            message: Some(format!("Заказ {} не найден. Проверьте order_uid и попробуйте ещё раз.", order_uid)),
+        }),
+        Err(err) => {
+            tracing::error!(%err, "Storage failure");
+            render(StatusCode::INTERNAL_SERVER_ERROR, OrderTemplate {
+                order_uid,
+                order: None,
+                message: Some(String::from("Не удалось загрузить заказ, попробуйте позже.")),
+            })
+        }
+    }
+}
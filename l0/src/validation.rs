@@ -0,0 +1,269 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use utoipa::ToSchema;
+
+use crate::{Delivery, Item, Order, Payment};
+use crate::domain::{Currency, Locale, Timestamp, UnixTime};
+
+#[derive(Serialize, ToSchema, PartialEq, Clone, Debug)]
+pub struct FieldError {
+    // path of the field in the order JSON, e.g. `items[0].track_number`
+    pub field: String,
+    pub rule: &'static str,
+    pub message: String,
+}
+
+const MAX_LENGTH: usize = 255;
+
+pub trait Validate {
+    // Pushes every rule `self` violates, field paths are prefixed with `path`.
+    fn validate(&self, path: &str, errors: &mut Vec<FieldError>);
+}
+
+// Why a JSON body is not a valid order.
+pub enum InvalidOrder {
+    // not the JSON of an order, e.g. a missing field: `path: message`
+    Json(String),
+    Rules(Vec<FieldError>),
+}
+
+// An order as it is sent: the typed fields are kept as the JSON they are
+// sent as, so a value their type cannot hold, e.g. a date_created string that
+// is not a timestamp, is checked in its place among the other rules. Orders
+// are deserialized through it, amounts become Money of payment.currency.
+#[derive(Deserialize)]
+pub struct SentOrder {
+    order_uid: String,
+    track_number: String,
+    entry: String,
+    delivery: Delivery,
+    payment: SentPayment,
+    items: Vec<SentItem>,
+    locale: String,
+    internal_signature: String,
+    customer_id: String,
+    delivery_service: String,
+    shardkey: String,
+    sm_id: i32,
+    date_created: String,
+    oof_shard: String,
+}
+
+#[derive(Deserialize)]
+struct SentPayment {
+    transaction: String,
+    request_id: String,
+    currency: String,
+    provider: String,
+    amount: u32,
+    payment_dt: i64,
+    bank: String,
+    delivery_cost: u32,
+    goods_total: u32,
+    custom_fee: u32,
+}
+
+#[derive(Deserialize)]
+struct SentItem {
+    chrt_id: i64,
+    track_number: String,
+    price: u32,
+    rid: String,
+    name: String,
+    sale: u32,
+    size: String,
+    total_price: u32,
+    nm_id: i64,
+    brand: String,
+    status: i32,
+}
+
+// Called once `validate` has passed, so the typed fields parse, or for an
+// order read back, which fails on a value its type cannot hold.
+impl TryFrom<SentOrder> for Order {
+    type Error = String;
+
+    fn try_from(sent: SentOrder) -> Result<Self, Self::Error> {
+        let payment = sent.payment;
+        let currency: Currency = payment.currency.parse()?;
+        Ok(Order {
+            order_uid: sent.order_uid,
+            track_number: sent.track_number,
+            entry: sent.entry,
+            delivery: sent.delivery,
+            payment: Payment {
+                transaction: payment.transaction,
+                request_id: payment.request_id,
+                currency,
+                provider: payment.provider,
+                amount: currency.money(payment.amount),
+                payment_dt: UnixTime::from_seconds(payment.payment_dt)
+                    .ok_or_else(|| format!("{} is out of the range of unix timestamps", payment.payment_dt))?,
+                bank: payment.bank,
+                delivery_cost: currency.money(payment.delivery_cost),
+                goods_total: currency.money(payment.goods_total),
+                custom_fee: currency.money(payment.custom_fee),
+            },
+            items: sent.items.into_iter().map(|item| Item {
+                chrt_id: item.chrt_id,
+                track_number: item.track_number,
+                price: currency.money(item.price),
+                rid: item.rid,
+                name: item.name,
+                sale: item.sale,
+                size: item.size,
+                total_price: currency.money(item.total_price),
+                nm_id: item.nm_id,
+                brand: item.brand,
+                status: item.status,
+            }).collect(),
+            locale: sent.locale.parse()?,
+            internal_signature: sent.internal_signature,
+            customer_id: sent.customer_id,
+            delivery_service: sent.delivery_service,
+            shardkey: sent.shardkey,
+            sm_id: sent.sm_id,
+            date_created: sent.date_created.parse()?,
+            oof_shard: sent.oof_shard,
+        })
+    }
+}
+
+// Parses an order and checks every rule, so that a value a typed field
+// cannot hold is reported together with the other violations.
+pub fn parse_order(bytes: &[u8]) -> Result<Order, InvalidOrder> {
+    let value = serde_json::from_slice(bytes).map_err(|err| InvalidOrder::Json(err.to_string()))?;
+    order_from_value(value)
+}
+
+pub fn order_from_value(value: Value) -> Result<Order, InvalidOrder> {
+    let sent: SentOrder = serde_path_to_error::deserialize(value).map_err(|err| InvalidOrder::Json(err.to_string()))?;
+    let mut errors = Vec::new();
+    sent.validate("", &mut errors);
+    if !errors.is_empty() {
+        return Err(InvalidOrder::Rules(errors));
+    }
+    Order::try_from(sent).map_err(InvalidOrder::Json)
+}
+
+fn field(path: &str, name: &str) -> String {
+    if path.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}.{}", path, name)
+    }
+}
+
+// A typed field sent with a value its type cannot hold.
+fn typed(path: &str, name: &str, holds: bool, rule: &'static str, message: &str, errors: &mut Vec<FieldError>) {
+    if !holds {
+        errors.push(FieldError {
+            field: field(path, name),
+            rule,
+            message: String::from(message),
+        });
+    }
+}
+
+pub fn required(path: &str, name: &str, value: &str, errors: &mut Vec<FieldError>) {
+    if value.trim().is_empty() {
+        errors.push(FieldError {
+            field: field(path, name),
+            rule: "required",
+            message: String::from("must not be empty"),
+        });
+    } else if value.len() > MAX_LENGTH {
+        errors.push(FieldError {
+            field: field(path, name),
+            rule: "max_length",
+            message: format!("must be at most {} bytes long", MAX_LENGTH),
+        });
+    }
+}
+
+impl Validate for SentOrder {
+    fn validate(&self, path: &str, errors: &mut Vec<FieldError>) {
+        required(path, "order_uid", &self.order_uid, errors);
+        required(path, "track_number", &self.track_number, errors);
+        required(path, "entry", &self.entry, errors);
+        required(path, "customer_id", &self.customer_id, errors);
+        required(path, "delivery_service", &self.delivery_service, errors);
+        typed(path, "date_created", self.date_created.parse::<Timestamp>().is_ok(), "timestamp", "must be an RFC 3339 timestamp, e.g. 2021-11-26T06:22:19Z", errors);
+        typed(path, "locale", self.locale.parse::<Locale>().is_ok(), "locale", "must be one of ru, en, be, kk, ky, hy, uz", errors);
+
+        self.delivery.validate(&field(path, "delivery"), errors);
+        self.payment.validate(&field(path, "payment"), errors);
+
+        if self.items.is_empty() {
+            errors.push(FieldError {
+                field: field(path, "items"),
+                rule: "required",
+                message: String::from("must contain at least one item"),
+            });
+        }
+        for (index, item) in self.items.iter().enumerate() {
+            let item_path = format!("{}[{}]", field(path, "items"), index);
+            item.validate(&item_path, errors);
+
+            if item.track_number != self.track_number {
+                errors.push(FieldError {
+                    field: field(&item_path, "track_number"),
+                    rule: "track_number_mismatch",
+                    message: format!("must match the order track_number {}", self.track_number),
+                });
+            }
+        }
+    }
+}
+
+impl Validate for Delivery {
+    fn validate(&self, path: &str, errors: &mut Vec<FieldError>) {
+        required(path, "name", &self.name, errors);
+        required(path, "phone", &self.phone, errors);
+        required(path, "city", &self.city, errors);
+        required(path, "address", &self.address, errors);
+
+        if !self.email.is_empty() && !self.email.contains('@') {
+            errors.push(FieldError {
+                field: field(path, "email"),
+                rule: "email",
+                message: String::from("must be an email address"),
+            });
+        }
+    }
+}
+
+impl Validate for SentPayment {
+    fn validate(&self, path: &str, errors: &mut Vec<FieldError>) {
+        required(path, "transaction", &self.transaction, errors);
+        required(path, "provider", &self.provider, errors);
+        typed(path, "currency", self.currency.parse::<Currency>().is_ok(), "currency", "must be a three letter ISO 4217 code, e.g. USD", errors);
+        typed(path, "payment_dt", UnixTime::from_seconds(self.payment_dt).is_some(), "unix_time", "must be a unix time in seconds", errors);
+
+        if self.goods_total.checked_add(self.delivery_cost) != Some(self.amount) {
+            errors.push(FieldError {
+                field: field(path, "amount"),
+                rule: "payment_total",
+                message: format!(
+                    "must equal goods_total + delivery_cost = {}",
+                    u64::from(self.goods_total) + u64::from(self.delivery_cost)
+                ),
+            });
+        }
+    }
+}
+
+impl Validate for SentItem {
+    fn validate(&self, path: &str, errors: &mut Vec<FieldError>) {
+        required(path, "rid", &self.rid, errors);
+        required(path, "name", &self.name, errors);
+
+        if self.sale > 100 {
+            errors.push(FieldError {
+                field: field(path, "sale"),
+                rule: "percentage",
+                message: String::from("must be between 0 and 100"),
+            });
+        }
+    }
+}
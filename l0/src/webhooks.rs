@@ -0,0 +1,1056 @@
+use std::{fmt, str::FromStr, sync::{Arc, Mutex, MutexGuard}, time::{Duration, Instant}};
+use async_trait::async_trait;
+use axum::{body::Bytes, extract::{rejection::{JsonRejection, QueryRejection}, Path, Query}, http::{header, Request, StatusCode, Uri}, Extension, Json};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, SecondsFormat, TimeDelta, Utc};
+use futures_util::future;
+use hmac::{Hmac, KeyInit, Mac};
+use http_body_util::Full;
+use hyper_util::rt::TokioIo;
+use rand::{rngs::OsRng, RngCore};
+use rusqlite::{params, types::{FromSql, FromSqlError, FromSqlResult, ToSql, ToSqlOutput, ValueRef}, Connection, OptionalExtension, Params, Row, TransactionBehavior};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::Sha256;
+use tokio::{io::{AsyncRead, AsyncWrite}, net::TcpStream};
+use tokio_rustls::{rustls::{self, pki_types::{pem::PemObject, CertificateDer, ServerName}, ClientConfig, RootCertStore}, TlsConnector};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::Order;
+use crate::db::{self, Pool};
+use crate::error::{AppError, Problem, Resource};
+use crate::metrics::Metrics;
+use crate::query::{DEFAULT_LIMIT, MAX_LIMIT};
+use crate::repository::RepositoryError;
+use crate::validation::{self, FieldError};
+
+// Secrets start with it, like API keys start with l0_.
+const SECRET_PREFIX: &str = "whsec_";
+// events fanned out and deliveries attempted per round of the dispatcher
+const BATCH_SIZE: usize = 100;
+// how often the dispatcher deletes deliveries older than the retention
+const PURGE_INTERVAL: Duration = Duration::from_secs(3600);
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct WebhooksConfig {
+    // milliseconds the dispatcher waits between rounds
+    pub poll_interval_ms: u64,
+    // attempts of a delivery before it is dead
+    pub max_attempts: u32,
+    // milliseconds before the first retry, doubled after every failed attempt
+    pub backoff_base_ms: u64,
+    // the longest wait between two attempts
+    pub backoff_max_ms: u64,
+    // milliseconds a receiver has to answer
+    pub timeout_ms: u64,
+    // PEM certificates trusted by https receivers besides the public roots
+    pub ca_file: Option<String>,
+    // hours delivered and dead deliveries are kept, 0 keeps them forever
+    pub retention_hours: u64,
+}
+
+impl Default for WebhooksConfig {
+    fn default() -> Self {
+        WebhooksConfig { poll_interval_ms: 1000, max_attempts: 8, backoff_base_ms: 5000, backoff_max_ms: 3_600_000, timeout_ms: 10_000, ca_file: None, retention_hours: 168 }
+    }
+}
+
+impl WebhooksConfig {
+    // Wait after the `attempts`-th failed attempt of a delivery.
+    pub fn backoff(&self, attempts: u32) -> Duration {
+        let factor = 2u64.saturating_pow(attempts.saturating_sub(1));
+        Duration::from_millis(self.backoff_base_ms.saturating_mul(factor).min(self.backoff_max_ms))
+    }
+
+    // Deliveries finished before it are deleted, `None` if they are kept.
+    pub fn retained_since(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        if self.retention_hours == 0 {
+            return None;
+        }
+        now.checked_sub_signed(delta(Duration::from_secs(self.retention_hours.saturating_mul(3600))))
+    }
+
+    // The TLS client of https receivers, trusting the public roots and the
+    // certificates of `ca_file`.
+    pub fn tls_connector(&self) -> Result<TlsConnector, String> {
+        let mut roots = RootCertStore { roots: webpki_roots::TLS_SERVER_ROOTS.to_vec() };
+        if let Some(ca_file) = &self.ca_file {
+            let certificates = CertificateDer::pem_file_iter(ca_file)
+                .and_then(|certificates| certificates.collect::<Result<Vec<_>, _>>())
+                .map_err(|err| format!("failed to read webhooks.ca_file {:?}: {}", ca_file, err))?;
+            if certificates.is_empty() {
+                return Err(format!("webhooks.ca_file {:?} has no certificates", ca_file));
+            }
+            for certificate in certificates {
+                roots.add(certificate).map_err(|err| format!("invalid certificate in webhooks.ca_file {:?}: {}", ca_file, err))?;
+            }
+        }
+        let config = ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .map_err(|err| err.to_string())?
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        Ok(TlsConnector::from(Arc::new(config)))
+    }
+}
+
+// What happened to an order, the event of an outbox row.
+#[derive(Serialize, Deserialize, ToSchema, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum OrderChange {
+    Created,
+    Updated,
+    // the order is the one that has been deleted
+    Deleted,
+}
+
+impl OrderChange {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            OrderChange::Created => "created",
+            OrderChange::Updated => "updated",
+            OrderChange::Deleted => "deleted",
+        }
+    }
+}
+
+impl FromStr for OrderChange {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "created" => Ok(OrderChange::Created),
+            "updated" => Ok(OrderChange::Updated),
+            "deleted" => Ok(OrderChange::Deleted),
+            _ => Err(format!("Unknown order change: {}", value)),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, ToSchema, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum DeliveryStatus {
+    // waiting for its next attempt
+    Pending,
+    // the receiver answered 2xx
+    Delivered,
+    // every attempt failed or the webhook has been deleted, see retry
+    Dead,
+}
+
+impl DeliveryStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DeliveryStatus::Pending => "pending",
+            DeliveryStatus::Delivered => "delivered",
+            DeliveryStatus::Dead => "dead",
+        }
+    }
+}
+
+impl FromStr for DeliveryStatus {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "pending" => Ok(DeliveryStatus::Pending),
+            "delivered" => Ok(DeliveryStatus::Delivered),
+            "dead" => Ok(DeliveryStatus::Dead),
+            _ => Err(format!("Unknown delivery status: {}", value)),
+        }
+    }
+}
+
+impl fmt::Display for DeliveryStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Serialize, Deserialize, ToSchema, Clone, PartialEq, Debug)]
+pub struct Webhook {
+    pub id: i64,
+    pub url: String,
+    pub created_at: String,
+    pub deleted_at: Option<String>,
+}
+
+// Body of POST /admin/webhooks.
+#[derive(Deserialize, ToSchema, Debug)]
+pub struct NewWebhook {
+    // http:// or https://host[:port]/path receiving a POST per event
+    pub url: String,
+}
+
+// Response of POST /admin/webhooks, the only place the secret is shown.
+#[derive(Serialize, Deserialize, ToSchema, Debug)]
+pub struct CreatedWebhook {
+    pub id: i64,
+    pub url: String,
+    pub created_at: String,
+    // signs the deliveries, see X-L0-Signature
+    pub secret: String,
+}
+
+#[derive(Serialize, Deserialize, ToSchema, Clone, PartialEq, Debug)]
+pub struct WebhookDelivery {
+    pub id: i64,
+    pub webhook_id: i64,
+    pub event_id: i64,
+    pub event: OrderChange,
+    pub order_uid: String,
+    pub status: DeliveryStatus,
+    // failed and successful attempts so far
+    pub attempts: u32,
+    // set while the delivery is pending
+    pub next_attempt_at: Option<String>,
+    // why the last failed attempt failed
+    pub last_error: Option<String>,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+// Body of every delivery. Retries of an event carry the same id, so
+// receivers can tell them apart from new events.
+#[derive(Serialize, Deserialize, ToSchema, Clone, PartialEq, Debug)]
+pub struct WebhookEvent {
+    pub id: i64,
+    pub event: OrderChange,
+    // when the change was stored
+    pub occurred_at: String,
+    pub order: Order,
+}
+
+// A delivery the dispatcher is about to attempt.
+pub struct DueDelivery {
+    pub id: i64,
+    pub url: String,
+    pub secret: String,
+    // attempts made before this one
+    pub attempts: u32,
+    pub event_id: i64,
+    pub event: OrderChange,
+    pub occurred_at: String,
+    // the order as stored in the outbox
+    pub order: Value,
+}
+
+impl DueDelivery {
+    // A WebhookEvent as JSON.
+    fn body(&self) -> String {
+        json!({
+            "id": self.event_id,
+            "event": self.event,
+            "occurred_at": self.occurred_at,
+            "order": self.order,
+        }).to_string()
+    }
+}
+
+// Outcome of an attempt of a delivery.
+pub enum Attempt {
+    Delivered,
+    // retried at `retry_at`, the delivery is dead without it
+    Failed { error: String, retry_at: Option<DateTime<Utc>> },
+}
+
+impl Attempt {
+    // status, next_attempt_at and last_error of the delivery after the attempt
+    pub fn columns(&self) -> (DeliveryStatus, Option<DateTime<Utc>>, Option<&str>) {
+        match self {
+            Attempt::Delivered => (DeliveryStatus::Delivered, None, None),
+            Attempt::Failed { error, retry_at: Some(retry_at) } => (DeliveryStatus::Pending, Some(*retry_at), Some(error)),
+            Attempt::Failed { error, retry_at: None } => (DeliveryStatus::Dead, None, Some(error)),
+        }
+    }
+}
+
+// Webhooks, their deliveries and the outbox the order storage writes events to.
+#[async_trait]
+pub trait WebhookRepository: Send + Sync {
+    async fn insert(&self, url: &str, secret: &str) -> Result<Webhook, RepositoryError>;
+    async fn list(&self) -> Result<Vec<Webhook>, RepositoryError>;
+    // Returns false if there is no webhook with the id, deleting twice is not
+    // an error. Pending deliveries of the webhook become dead.
+    async fn delete(&self, id: i64) -> Result<bool, RepositoryError>;
+    // The latest `limit` deliveries of the webhook, newest first. `None` if
+    // there is no webhook with the id.
+    async fn deliveries(&self, webhook_id: i64, status: Option<DeliveryStatus>, limit: usize) -> Result<Option<Vec<WebhookDelivery>>, RepositoryError>;
+    // Makes a delivery pending again with no attempts, due right away. `None`
+    // if the webhook has no such delivery or has been deleted.
+    async fn retry(&self, webhook_id: i64, delivery_id: i64) -> Result<Option<WebhookDelivery>, RepositoryError>;
+
+    // Turns up to `limit` new events of the outbox into a pending delivery
+    // per webhook, returns the number of events.
+    async fn fan_out(&self, limit: usize) -> Result<usize, RepositoryError>;
+    // Up to `limit` pending deliveries due at `now`. They are not due again
+    // before `lease_until`, so no other dispatcher attempts them meanwhile.
+    async fn claim(&self, now: DateTime<Utc>, lease_until: DateTime<Utc>, limit: usize) -> Result<Vec<DueDelivery>, RepositoryError>;
+    // Records an attempt of a pending delivery.
+    async fn record(&self, delivery_id: i64, attempt: &Attempt) -> Result<(), RepositoryError>;
+    // Deletes the delivered and dead deliveries last changed before `before`
+    // and the fanned out events left without deliveries. Returns the number
+    // of deleted deliveries.
+    async fn purge(&self, before: DateTime<Utc>) -> Result<usize, RepositoryError>;
+}
+
+// Times of the webhook tables, like strftime('%Y-%m-%dT%H:%M:%fZ') of SQLite.
+pub fn format_time(time: DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+// A secret of 256 random bits.
+fn generate_secret() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    format!("{}{}", SECRET_PREFIX, URL_SAFE_NO_PAD.encode(bytes))
+}
+
+// `sha256=` and the hex HMAC-SHA256 of `{timestamp}.{body}` keyed with the
+// secret, the X-L0-Signature header.
+pub fn sign(secret: &str, timestamp: i64, body: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(format!("{}.", timestamp).as_bytes());
+    mac.update(body.as_bytes());
+    let signature: String = mac.finalize().into_bytes().iter().map(|byte| format!("{:02x}", byte)).collect();
+    format!("sha256={}", signature)
+}
+
+fn check_url(url: &str) -> Result<Uri, String> {
+    let uri: Uri = url.parse().map_err(|_| String::from("must be a URL, e.g. https://shop.local/orders"))?;
+    if !matches!(uri.scheme_str(), Some("http" | "https")) {
+        return Err(String::from("must be an http:// or https:// URL"));
+    }
+    if uri.host().is_none_or(str::is_empty) {
+        return Err(String::from("must name a host"));
+    }
+    Ok(uri)
+}
+
+// dispatcher
+// Delivers the events of the outbox until the task is aborted.
+pub async fn run(config: WebhooksConfig, tls: TlsConnector, webhooks: Arc<dyn WebhookRepository>, metrics: Metrics) {
+    let mut purged_at: Option<Instant> = None;
+    loop {
+        if let Some(before) = config.retained_since(Utc::now()).filter(|_| purged_at.is_none_or(|at| at.elapsed() >= PURGE_INTERVAL)) {
+            purged_at = Some(Instant::now());
+            match webhooks.purge(before).await {
+                Ok(0) => {}
+                Ok(deleted) => tracing::info!(deleted, "Old webhook deliveries have been deleted"),
+                Err(err) => tracing::error!(%err, "Failed to delete old webhook deliveries"),
+            }
+        }
+        match dispatch(&config, &tls, webhooks.as_ref(), &metrics).await {
+            // a full batch, more deliveries may be due
+            Ok(attempts) if attempts == BATCH_SIZE => continue,
+            Ok(_) => {}
+            Err(err) => tracing::error!(%err, "Failed to dispatch webhooks"),
+        }
+        tokio::time::sleep(Duration::from_millis(config.poll_interval_ms)).await;
+    }
+}
+
+// One round of the dispatcher: fans out the new events and attempts the due
+// deliveries at once. Returns the number of attempts.
+pub async fn dispatch(config: &WebhooksConfig, tls: &TlsConnector, webhooks: &dyn WebhookRepository, metrics: &Metrics) -> Result<usize, RepositoryError> {
+    while webhooks.fan_out(BATCH_SIZE).await? == BATCH_SIZE {}
+
+    let timeout = Duration::from_millis(config.timeout_ms);
+    let now = Utc::now();
+    // a dispatcher stopped mid-attempt leaves the delivery to a later round
+    let due = webhooks.claim(now, now + delta(timeout * 2), BATCH_SIZE).await?;
+    let results = future::join_all(due.iter().map(|delivery| send(delivery, tls, timeout))).await;
+
+    for (delivery, result) in due.iter().zip(results) {
+        let span = tracing::info_span!("webhook", delivery = delivery.id, url = %delivery.url, event = delivery.event_id);
+        let attempt = match result {
+            Ok(()) => {
+                metrics.webhook_attempt("delivered");
+                span.in_scope(|| tracing::debug!("Webhook delivered"));
+                Attempt::Delivered
+            }
+            Err(error) => {
+                let attempts = delivery.attempts + 1;
+                let retry_at = (attempts < config.max_attempts).then(|| Utc::now() + delta(config.backoff(attempts)));
+                match retry_at {
+                    Some(retry_at) => {
+                        metrics.webhook_attempt("failed");
+                        span.in_scope(|| tracing::warn!(%error, attempts, %retry_at, "Webhook delivery failed, retrying"));
+                    }
+                    None => {
+                        metrics.webhook_attempt("dead");
+                        span.in_scope(|| tracing::error!(%error, attempts, "Webhook delivery failed, giving up"));
+                    }
+                }
+                Attempt::Failed { error, retry_at }
+            }
+        };
+        webhooks.record(delivery.id, &attempt).await?;
+    }
+    Ok(due.len())
+}
+
+fn delta(duration: Duration) -> TimeDelta {
+    TimeDelta::from_std(duration).unwrap_or(TimeDelta::MAX)
+}
+
+// Posts the event to the webhook, fails unless the receiver answers 2xx
+// within `timeout`.
+async fn send(delivery: &DueDelivery, tls: &TlsConnector, timeout: Duration) -> Result<(), String> {
+    let body = delivery.body();
+    let timestamp = Utc::now().timestamp();
+    let headers = [
+        ("x-l0-event", delivery.event.as_str().to_string()),
+        ("x-l0-delivery", delivery.id.to_string()),
+        ("x-l0-timestamp", timestamp.to_string()),
+        ("x-l0-signature", sign(&delivery.secret, timestamp, &body)),
+    ];
+    match tokio::time::timeout(timeout, post(&delivery.url, tls, &headers, body)).await {
+        Ok(Ok(status)) if status.is_success() => Ok(()),
+        Ok(Ok(status)) => Err(format!("the receiver answered {}", status)),
+        Ok(Err(err)) => Err(err),
+        Err(_) => Err(format!("no answer within {} ms", timeout.as_millis())),
+    }
+}
+
+// A POST over a connection of its own, only the status of the answer is read.
+async fn post(url: &str, tls: &TlsConnector, headers: &[(&str, String)], body: String) -> Result<StatusCode, String> {
+    let uri = check_url(url)?;
+    let host = uri.host().unwrap_or_default();
+    let https = uri.scheme_str() == Some("https");
+    let address = host.trim_start_matches('[').trim_end_matches(']');
+    let stream = TcpStream::connect((address, uri.port_u16().unwrap_or(if https { 443 } else { 80 }))).await
+        .map_err(|err| format!("failed to connect: {}", err))?;
+
+    // the authority without the userinfo, credentials of the URL are not sent
+    let authority = uri.port().map_or_else(|| host.to_string(), |port| format!("{}:{}", host, port));
+    let mut request = Request::post(uri.path_and_query().map_or("/", |path| path.as_str()))
+        .header(header::HOST, authority)
+        .header(header::CONTENT_TYPE, "application/json")
+        .header(header::CONNECTION, "close");
+    for (name, value) in headers {
+        request = request.header(*name, value);
+    }
+    let request = request.body(Full::new(Bytes::from(body))).map_err(|err| err.to_string())?;
+
+    if !https {
+        return exchange(stream, request).await;
+    }
+    let server_name = ServerName::try_from(address.to_string()).map_err(|err| err.to_string())?;
+    let stream = tls.connect(server_name, stream).await.map_err(|err| format!("TLS handshake failed: {}", err))?;
+    exchange(stream, request).await
+}
+
+// Sends the request over the connection and returns the status of the answer.
+async fn exchange<S>(stream: S, request: Request<Full<Bytes>>) -> Result<StatusCode, String>
+where
+    S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    let (mut sender, connection) = hyper::client::conn::http1::handshake(TokioIo::new(stream)).await
+        .map_err(|err| err.to_string())?;
+
+    // the connection is driven until the answer arrives, it ends first when
+    // the receiver closes it right after answering
+    let (mut response, mut connection) = (std::pin::pin!(sender.send_request(request)), std::pin::pin!(connection));
+    let response = tokio::select! {
+        response = &mut response => response,
+        result = &mut connection => {
+            result.map_err(|err| err.to_string())?;
+            response.await
+        }
+    };
+    response.map(|response| response.status()).map_err(|err| err.to_string())
+}
+
+// sqlite
+// Adds an event to the outbox, called in the transaction that changes the order.
+pub fn insert_outbox_event(connection: &Connection, change: OrderChange, order: &Order) -> Result<(), RepositoryError> {
+    let payload = serde_json::to_string(order).map_err(|err| RepositoryError::Storage(err.to_string()))?;
+    connection.execute(
+        "INSERT INTO outbox (event, order_uid, payload) VALUES (?1, ?2, ?3)",
+        params![change, order.order_uid, payload],
+    )?;
+    Ok(())
+}
+
+pub struct SqliteWebhookRepository {
+    pool: Pool,
+}
+
+impl SqliteWebhookRepository {
+    pub fn new(pool: Pool) -> Self {
+        SqliteWebhookRepository { pool }
+    }
+}
+
+const WEBHOOK_COLUMNS: &str = "id, url, created_at, deleted_at";
+
+const SELECT_DELIVERIES: &str = r#"
+    SELECT webhook_deliveries.id, webhook_deliveries.webhook_id, webhook_deliveries.event_id, outbox.event, outbox.order_uid,
+           webhook_deliveries.status, webhook_deliveries.attempts, webhook_deliveries.next_attempt_at, webhook_deliveries.last_error,
+           webhook_deliveries.created_at, webhook_deliveries.updated_at
+    FROM webhook_deliveries
+    JOIN outbox ON outbox.id = webhook_deliveries.event_id
+"#;
+
+fn webhook(row: &Row) -> rusqlite::Result<Webhook> {
+    Ok(Webhook {
+        id: row.get(0)?,
+        url: row.get(1)?,
+        created_at: row.get(2)?,
+        deleted_at: row.get(3)?,
+    })
+}
+
+fn delivery(row: &Row) -> rusqlite::Result<WebhookDelivery> {
+    Ok(WebhookDelivery {
+        id: row.get(0)?,
+        webhook_id: row.get(1)?,
+        event_id: row.get(2)?,
+        event: row.get(3)?,
+        order_uid: row.get(4)?,
+        status: row.get(5)?,
+        attempts: row.get(6)?,
+        next_attempt_at: row.get(7)?,
+        last_error: row.get(8)?,
+        created_at: row.get(9)?,
+        updated_at: row.get(10)?,
+    })
+}
+
+fn load_deliveries<P: Params>(connection: &Connection, clause: &str, params: P) -> rusqlite::Result<Vec<WebhookDelivery>> {
+    let mut stmt = connection.prepare(&format!("{} {}", SELECT_DELIVERIES, clause))?;
+    let deliveries = stmt.query_map(params, delivery)?.collect::<rusqlite::Result<Vec<WebhookDelivery>>>()?;
+    Ok(deliveries)
+}
+
+#[async_trait]
+impl WebhookRepository for SqliteWebhookRepository {
+    async fn insert(&self, url: &str, secret: &str) -> Result<Webhook, RepositoryError> {
+        let (url, secret) = (url.to_string(), secret.to_string());
+        db::with_connection(&self.pool, "insert_webhook", move |connection| {
+            connection.execute("INSERT INTO webhooks (url, secret) VALUES (?1, ?2)", params![url, secret])?;
+            let id = connection.last_insert_rowid();
+            let webhook = connection.query_row(&format!("SELECT {} FROM webhooks WHERE id = ?1", WEBHOOK_COLUMNS), params![id], webhook)?;
+            Ok(webhook)
+        }).await
+    }
+
+    async fn list(&self) -> Result<Vec<Webhook>, RepositoryError> {
+        db::with_connection(&self.pool, "list_webhooks", |connection| {
+            let mut stmt = connection.prepare(&format!("SELECT {} FROM webhooks ORDER BY id", WEBHOOK_COLUMNS))?;
+            let webhooks = stmt.query_map([], webhook)?.collect::<rusqlite::Result<Vec<Webhook>>>()?;
+            Ok(webhooks)
+        }).await
+    }
+
+    async fn delete(&self, id: i64) -> Result<bool, RepositoryError> {
+        db::with_connection(&self.pool, "delete_webhook", move |connection| {
+            let now = format_time(Utc::now());
+            let transaction = connection.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            let deleted = transaction.execute("UPDATE webhooks SET deleted_at = ?2 WHERE id = ?1 AND deleted_at IS NULL", params![id, now])?;
+            if deleted > 0 {
+                transaction.execute(
+                    r#"UPDATE webhook_deliveries SET status = 'dead', next_attempt_at = NULL, last_error = 'the webhook has been deleted', updated_at = ?2
+                        WHERE webhook_id = ?1 AND status = 'pending'"#,
+                    params![id, now],
+                )?;
+            }
+            let exists = transaction.query_row("SELECT 1 FROM webhooks WHERE id = ?1", params![id], |_| Ok(())).optional()?;
+            transaction.commit()?;
+            Ok(exists.is_some())
+        }).await
+    }
+
+    async fn deliveries(&self, webhook_id: i64, status: Option<DeliveryStatus>, limit: usize) -> Result<Option<Vec<WebhookDelivery>>, RepositoryError> {
+        db::with_connection(&self.pool, "list_webhook_deliveries", move |connection| {
+            let exists = connection.query_row("SELECT 1 FROM webhooks WHERE id = ?1", params![webhook_id], |_| Ok(())).optional()?;
+            if exists.is_none() {
+                return Ok(None);
+            }
+            let deliveries = load_deliveries(
+                connection,
+                r#"WHERE webhook_deliveries.webhook_id = ?1 AND (?2 IS NULL OR webhook_deliveries.status = ?2)
+                    ORDER BY webhook_deliveries.id DESC LIMIT ?3"#,
+                params![webhook_id, status, limit as i64],
+            )?;
+            Ok(Some(deliveries))
+        }).await
+    }
+
+    async fn retry(&self, webhook_id: i64, delivery_id: i64) -> Result<Option<WebhookDelivery>, RepositoryError> {
+        db::with_connection(&self.pool, "retry_webhook_delivery", move |connection| {
+            let now = format_time(Utc::now());
+            let transaction = connection.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            let updated = transaction.execute(
+                r#"UPDATE webhook_deliveries SET status = 'pending', attempts = 0, next_attempt_at = ?3, updated_at = ?3
+                    WHERE id = ?1 AND webhook_id = ?2 AND webhook_id IN (SELECT id FROM webhooks WHERE deleted_at IS NULL)"#,
+                params![delivery_id, webhook_id, now],
+            )?;
+            if updated == 0 {
+                return Ok(None);
+            }
+            let deliveries = load_deliveries(&transaction, "WHERE webhook_deliveries.id = ?1", params![delivery_id])?;
+            transaction.commit()?;
+            Ok(deliveries.into_iter().next())
+        }).await
+    }
+
+    async fn fan_out(&self, limit: usize) -> Result<usize, RepositoryError> {
+        db::with_connection(&self.pool, "fan_out_webhook_events", move |connection| {
+            let now = format_time(Utc::now());
+            let transaction = connection.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            let ids = {
+                let mut stmt = transaction.prepare("SELECT id FROM outbox WHERE dispatched_at IS NULL ORDER BY id LIMIT ?1")?;
+                let ids = stmt.query_map(params![limit as i64], |row| row.get(0))?.collect::<rusqlite::Result<Vec<i64>>>()?;
+                ids
+            };
+            let Some(last) = ids.last() else {
+                return Ok(0);
+            };
+            // the transaction holds the write lock, no event below `last` is added meanwhile
+            transaction.execute(
+                r#"INSERT INTO webhook_deliveries (webhook_id, event_id, next_attempt_at, created_at, updated_at)
+                    SELECT webhooks.id, outbox.id, ?2, ?2, ?2 FROM outbox, webhooks
+                    WHERE outbox.dispatched_at IS NULL AND outbox.id <= ?1 AND webhooks.deleted_at IS NULL"#,
+                params![last, now],
+            )?;
+            transaction.execute("UPDATE outbox SET dispatched_at = ?2 WHERE dispatched_at IS NULL AND id <= ?1", params![last, now])?;
+            transaction.commit()?;
+            Ok(ids.len())
+        }).await
+    }
+
+    async fn claim(&self, now: DateTime<Utc>, lease_until: DateTime<Utc>, limit: usize) -> Result<Vec<DueDelivery>, RepositoryError> {
+        db::with_connection(&self.pool, "claim_webhook_deliveries", move |connection| {
+            let transaction = connection.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            let due = {
+                let mut stmt = transaction.prepare(
+                    r#"SELECT webhook_deliveries.id, webhooks.url, webhooks.secret, webhook_deliveries.attempts,
+                            outbox.id, outbox.event, outbox.created_at, outbox.payload
+                        FROM webhook_deliveries
+                        JOIN webhooks ON webhooks.id = webhook_deliveries.webhook_id
+                        JOIN outbox ON outbox.id = webhook_deliveries.event_id
+                        WHERE webhook_deliveries.status = 'pending' AND webhook_deliveries.next_attempt_at <= ?1
+                        ORDER BY webhook_deliveries.next_attempt_at, webhook_deliveries.id
+                        LIMIT ?2"#,
+                )?;
+                let due = stmt.query_map(params![format_time(now), limit as i64], |row| {
+                    let payload: String = row.get(7)?;
+                    Ok(DueDelivery {
+                        id: row.get(0)?,
+                        url: row.get(1)?,
+                        secret: row.get(2)?,
+                        attempts: row.get(3)?,
+                        event_id: row.get(4)?,
+                        event: row.get(5)?,
+                        occurred_at: row.get(6)?,
+                        order: serde_json::from_str(&payload)
+                            .map_err(|err| rusqlite::Error::FromSqlConversionFailure(7, rusqlite::types::Type::Text, Box::new(err)))?,
+                    })
+                })?.collect::<rusqlite::Result<Vec<DueDelivery>>>()?;
+                due
+            };
+            let lease_until = format_time(lease_until);
+            for delivery in &due {
+                transaction.execute("UPDATE webhook_deliveries SET next_attempt_at = ?2 WHERE id = ?1", params![delivery.id, lease_until])?;
+            }
+            transaction.commit()?;
+            Ok(due)
+        }).await
+    }
+
+    async fn record(&self, delivery_id: i64, attempt: &Attempt) -> Result<(), RepositoryError> {
+        let (status, next_attempt_at, last_error) = attempt.columns();
+        let (next_attempt_at, last_error) = (next_attempt_at.map(format_time), last_error.map(str::to_string));
+        db::with_connection(&self.pool, "record_webhook_attempt", move |connection| {
+            connection.execute(
+                r#"UPDATE webhook_deliveries SET status = ?2, attempts = attempts + 1, next_attempt_at = ?3, last_error = ?4, updated_at = ?5
+                    WHERE id = ?1 AND status = 'pending'"#,
+                params![delivery_id, status, next_attempt_at, last_error, format_time(Utc::now())],
+            )?;
+            Ok(())
+        }).await
+    }
+
+    async fn purge(&self, before: DateTime<Utc>) -> Result<usize, RepositoryError> {
+        db::with_connection(&self.pool, "purge_webhook_deliveries", move |connection| {
+            let before = format_time(before);
+            let transaction = connection.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            let deleted = transaction.execute(
+                "DELETE FROM webhook_deliveries WHERE status <> 'pending' AND updated_at < ?1",
+                params![before],
+            )?;
+            transaction.execute(
+                r#"DELETE FROM outbox WHERE dispatched_at < ?1
+                    AND NOT EXISTS (SELECT 1 FROM webhook_deliveries WHERE webhook_deliveries.event_id = outbox.id)"#,
+                params![before],
+            )?;
+            transaction.commit()?;
+            Ok(deleted)
+        }).await
+    }
+}
+
+impl ToSql for OrderChange {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.as_str()))
+    }
+}
+
+impl FromSql for OrderChange {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        value.as_str()?.parse().map_err(|err: String| FromSqlError::Other(err.into()))
+    }
+}
+
+impl ToSql for DeliveryStatus {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.as_str()))
+    }
+}
+
+impl FromSql for DeliveryStatus {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        value.as_str()?.parse().map_err(|err: String| FromSqlError::Other(err.into()))
+    }
+}
+
+// in-memory
+struct OutboxEvent {
+    id: i64,
+    event: OrderChange,
+    order: Order,
+    created_at: DateTime<Utc>,
+}
+
+#[derive(Default)]
+struct OutboxState {
+    next_id: i64,
+    events: Vec<Arc<OutboxEvent>>,
+}
+
+// Outbox of the in-memory storage: the order repository adds events while
+// it holds its lock, InMemoryWebhookRepository takes them.
+#[derive(Default)]
+pub struct Outbox {
+    state: Mutex<OutboxState>,
+}
+
+impl Outbox {
+    pub fn push(&self, change: OrderChange, order: &Order) -> Result<(), RepositoryError> {
+        let mut state = self.state.lock().map_err(|err| RepositoryError::Storage(err.to_string()))?;
+        state.next_id += 1;
+        let event = OutboxEvent { id: state.next_id, event: change, order: order.clone(), created_at: Utc::now() };
+        state.events.push(Arc::new(event));
+        Ok(())
+    }
+
+    fn take(&self, limit: usize) -> Result<Vec<Arc<OutboxEvent>>, RepositoryError> {
+        let mut state = self.state.lock().map_err(|err| RepositoryError::Storage(err.to_string()))?;
+        let count = limit.min(state.events.len());
+        Ok(state.events.drain(..count).collect())
+    }
+}
+
+struct StoredDelivery {
+    id: i64,
+    webhook_id: i64,
+    event: Arc<OutboxEvent>,
+    status: DeliveryStatus,
+    attempts: u32,
+    next_attempt_at: Option<DateTime<Utc>>,
+    last_error: Option<String>,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+}
+
+impl StoredDelivery {
+    fn view(&self) -> WebhookDelivery {
+        WebhookDelivery {
+            id: self.id,
+            webhook_id: self.webhook_id,
+            event_id: self.event.id,
+            event: self.event.event,
+            order_uid: self.event.order.order_uid.clone(),
+            status: self.status,
+            attempts: self.attempts,
+            next_attempt_at: self.next_attempt_at.map(format_time),
+            last_error: self.last_error.clone(),
+            created_at: format_time(self.created_at),
+            updated_at: format_time(self.updated_at),
+        }
+    }
+}
+
+#[derive(Default)]
+struct WebhookState {
+    // with the secret of each
+    webhooks: Vec<(Webhook, String)>,
+    deliveries: Vec<StoredDelivery>,
+    // ids are not reused after purge
+    last_delivery_id: i64,
+}
+
+// Webhooks of the in-memory storage, they are gone after a restart.
+pub struct InMemoryWebhookRepository {
+    outbox: Arc<Outbox>,
+    state: Mutex<WebhookState>,
+}
+
+impl InMemoryWebhookRepository {
+    pub fn new(outbox: Arc<Outbox>) -> Self {
+        InMemoryWebhookRepository { outbox, state: Mutex::default() }
+    }
+
+    fn lock(&self) -> Result<MutexGuard<'_, WebhookState>, RepositoryError> {
+        self.state.lock().map_err(|err| RepositoryError::Storage(err.to_string()))
+    }
+}
+
+#[async_trait]
+impl WebhookRepository for InMemoryWebhookRepository {
+    async fn insert(&self, url: &str, secret: &str) -> Result<Webhook, RepositoryError> {
+        let mut state = self.lock()?;
+        let webhook = Webhook {
+            id: state.webhooks.len() as i64 + 1,
+            url: url.to_string(),
+            created_at: format_time(Utc::now()),
+            deleted_at: None,
+        };
+        state.webhooks.push((webhook.clone(), secret.to_string()));
+        Ok(webhook)
+    }
+
+    async fn list(&self) -> Result<Vec<Webhook>, RepositoryError> {
+        let state = self.lock()?;
+        Ok(state.webhooks.iter().map(|(webhook, _)| webhook.clone()).collect())
+    }
+
+    async fn delete(&self, id: i64) -> Result<bool, RepositoryError> {
+        let mut state = self.lock()?;
+        let now = Utc::now();
+        let Some((webhook, _)) = state.webhooks.iter_mut().find(|(webhook, _)| webhook.id == id) else {
+            return Ok(false);
+        };
+        if webhook.deleted_at.is_some() {
+            return Ok(true);
+        }
+        webhook.deleted_at = Some(format_time(now));
+        for delivery in state.deliveries.iter_mut().filter(|delivery| delivery.webhook_id == id && delivery.status == DeliveryStatus::Pending) {
+            delivery.status = DeliveryStatus::Dead;
+            delivery.next_attempt_at = None;
+            delivery.last_error = Some(String::from("the webhook has been deleted"));
+            delivery.updated_at = now;
+        }
+        Ok(true)
+    }
+
+    async fn deliveries(&self, webhook_id: i64, status: Option<DeliveryStatus>, limit: usize) -> Result<Option<Vec<WebhookDelivery>>, RepositoryError> {
+        let state = self.lock()?;
+        if !state.webhooks.iter().any(|(webhook, _)| webhook.id == webhook_id) {
+            return Ok(None);
+        }
+        Ok(Some(state.deliveries.iter().rev()
+            .filter(|delivery| delivery.webhook_id == webhook_id && status.is_none_or(|status| delivery.status == status))
+            .take(limit)
+            .map(StoredDelivery::view)
+            .collect()))
+    }
+
+    async fn retry(&self, webhook_id: i64, delivery_id: i64) -> Result<Option<WebhookDelivery>, RepositoryError> {
+        let mut state = self.lock()?;
+        if !state.webhooks.iter().any(|(webhook, _)| webhook.id == webhook_id && webhook.deleted_at.is_none()) {
+            return Ok(None);
+        }
+        let Some(delivery) = state.deliveries.iter_mut().find(|delivery| delivery.id == delivery_id && delivery.webhook_id == webhook_id) else {
+            return Ok(None);
+        };
+        let now = Utc::now();
+        delivery.status = DeliveryStatus::Pending;
+        delivery.attempts = 0;
+        delivery.next_attempt_at = Some(now);
+        delivery.updated_at = now;
+        Ok(Some(delivery.view()))
+    }
+
+    async fn fan_out(&self, limit: usize) -> Result<usize, RepositoryError> {
+        let events = self.outbox.take(limit)?;
+        let mut state = self.lock()?;
+        let now = Utc::now();
+        let webhook_ids: Vec<i64> = state.webhooks.iter()
+            .filter(|(webhook, _)| webhook.deleted_at.is_none())
+            .map(|(webhook, _)| webhook.id)
+            .collect();
+        for event in &events {
+            for webhook_id in &webhook_ids {
+                state.last_delivery_id += 1;
+                let id = state.last_delivery_id;
+                state.deliveries.push(StoredDelivery {
+                    id,
+                    webhook_id: *webhook_id,
+                    event: Arc::clone(event),
+                    status: DeliveryStatus::Pending,
+                    attempts: 0,
+                    next_attempt_at: Some(now),
+                    last_error: None,
+                    created_at: now,
+                    updated_at: now,
+                });
+            }
+        }
+        Ok(events.len())
+    }
+
+    async fn claim(&self, now: DateTime<Utc>, lease_until: DateTime<Utc>, limit: usize) -> Result<Vec<DueDelivery>, RepositoryError> {
+        let mut state = self.lock()?;
+        let WebhookState { webhooks, deliveries, .. } = &mut *state;
+        let mut due: Vec<&mut StoredDelivery> = deliveries.iter_mut()
+            .filter(|delivery| delivery.status == DeliveryStatus::Pending && delivery.next_attempt_at.is_some_and(|at| at <= now))
+            .collect();
+        due.sort_by_key(|delivery| (delivery.next_attempt_at, delivery.id));
+        due.truncate(limit);
+
+        let mut claimed = Vec::with_capacity(due.len());
+        for delivery in due {
+            let Some((webhook, secret)) = webhooks.iter().find(|(webhook, _)| webhook.id == delivery.webhook_id) else {
+                continue;
+            };
+            delivery.next_attempt_at = Some(lease_until);
+            claimed.push(DueDelivery {
+                id: delivery.id,
+                url: webhook.url.clone(),
+                secret: secret.clone(),
+                attempts: delivery.attempts,
+                event_id: delivery.event.id,
+                event: delivery.event.event,
+                occurred_at: format_time(delivery.event.created_at),
+                order: serde_json::to_value(&delivery.event.order).map_err(|err| RepositoryError::Storage(err.to_string()))?,
+            });
+        }
+        Ok(claimed)
+    }
+
+    async fn record(&self, delivery_id: i64, attempt: &Attempt) -> Result<(), RepositoryError> {
+        let mut state = self.lock()?;
+        let (status, next_attempt_at, last_error) = attempt.columns();
+        if let Some(delivery) = state.deliveries.iter_mut().find(|delivery| delivery.id == delivery_id && delivery.status == DeliveryStatus::Pending) {
+            delivery.status = status;
+            delivery.attempts += 1;
+            delivery.next_attempt_at = next_attempt_at;
+            delivery.last_error = last_error.map(str::to_string);
+            delivery.updated_at = Utc::now();
+        }
+        Ok(())
+    }
+
+    // events are dropped with their last delivery
+    async fn purge(&self, before: DateTime<Utc>) -> Result<usize, RepositoryError> {
+        let mut state = self.lock()?;
+        let count = state.deliveries.len();
+        state.deliveries.retain(|delivery| delivery.status == DeliveryStatus::Pending || delivery.updated_at >= before);
+        Ok(count - state.deliveries.len())
+    }
+}
+
+// handlers
+// Query string of GET /admin/webhooks/:id/deliveries.
+#[derive(Deserialize, IntoParams, Default, Debug)]
+#[into_params(parameter_in = Query)]
+pub struct DeliveryQuery {
+    pub status: Option<DeliveryStatus>,
+    pub limit: Option<usize>,
+}
+
+#[utoipa::path(post, path = "/admin/webhooks", tag = "admin", request_body = NewWebhook,
+    responses(
+        (status = 201, body = CreatedWebhook),
+        (status = 400, body = Problem),
+        (status = 422, body = Problem),
+        (status = 500, body = Problem),
+    ))]
+pub async fn create_webhook(Extension(webhooks): Extension<Arc<dyn WebhookRepository>>, payload: Result<Json<NewWebhook>, JsonRejection>) -> Result<(StatusCode, Json<CreatedWebhook>), AppError> {
+    let Json(new_webhook) = payload.map_err(|rejection| AppError::InvalidJson(Resource::Webhook, rejection))?;
+    let url = new_webhook.url.trim();
+    let mut errors = Vec::new();
+    validation::required("", "url", url, &mut errors);
+    if errors.is_empty() {
+        if let Err(message) = check_url(url) {
+            errors.push(FieldError { field: String::from("url"), rule: "http_url", message });
+        }
+    }
+    if !errors.is_empty() {
+        return Err(AppError::Validation(Resource::Webhook, errors));
+    }
+
+    let secret = generate_secret();
+    let webhook = webhooks.insert(url, &secret).await?;
+    tracing::info!(id = webhook.id, url = %webhook.url, "Webhook registered");
+    Ok((StatusCode::CREATED, Json(CreatedWebhook { id: webhook.id, url: webhook.url, created_at: webhook.created_at, secret })))
+}
+
+#[utoipa::path(get, path = "/admin/webhooks", tag = "admin",
+    responses(
+        (status = 200, body = Vec<Webhook>),
+        (status = 500, body = Problem),
+    ))]
+pub async fn list_webhooks(Extension(webhooks): Extension<Arc<dyn WebhookRepository>>) -> Result<Json<Vec<Webhook>>, AppError> {
+    Ok(Json(webhooks.list().await?))
+}
+
+#[utoipa::path(delete, path = "/admin/webhooks/{id}", tag = "admin", params(("id" = i64, Path)),
+    responses(
+        (status = 204, description = "The webhook has been deleted, its pending deliveries are dead"),
+        (status = 404, body = Problem),
+        (status = 500, body = Problem),
+    ))]
+pub async fn delete_webhook(Path(id): Path<String>, Extension(webhooks): Extension<Arc<dyn WebhookRepository>>) -> Result<StatusCode, AppError> {
+    let deleted = match id.parse::<i64>() {
+        Ok(webhook_id) => webhooks.delete(webhook_id).await?,
+        Err(_) => false,
+    };
+    if !deleted {
+        return Err(AppError::WebhookNotFound(id));
+    }
+    tracing::info!(id, "Webhook deleted");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[utoipa::path(get, path = "/admin/webhooks/{id}/deliveries", tag = "admin", params(("id" = i64, Path), DeliveryQuery),
+    responses(
+        (status = 200, description = "The latest deliveries, newest first", body = Vec<WebhookDelivery>),
+        (status = 400, body = Problem),
+        (status = 404, body = Problem),
+        (status = 500, body = Problem),
+    ))]
+pub async fn list_deliveries(Path(id): Path<String>, Extension(webhooks): Extension<Arc<dyn WebhookRepository>>, query: Result<Query<DeliveryQuery>, QueryRejection>) -> Result<Json<Vec<WebhookDelivery>>, AppError> {
+    let Query(query) = query?;
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT);
+    if limit == 0 || limit > MAX_LIMIT {
+        return Err(AppError::InvalidQuery(format!("limit must be between 1 and {}", MAX_LIMIT)));
+    }
+    let deliveries = match id.parse::<i64>() {
+        Ok(webhook_id) => webhooks.deliveries(webhook_id, query.status, limit).await?,
+        Err(_) => None,
+    };
+    deliveries.map(Json).ok_or(AppError::WebhookNotFound(id))
+}
+
+// Sends a delivery again from its first attempt, e.g. a dead one after the
+// receiver has been fixed.
+#[utoipa::path(post, path = "/admin/webhooks/{id}/deliveries/{delivery_id}/retry", tag = "admin", params(("id" = i64, Path), ("delivery_id" = i64, Path)),
+    responses(
+        (status = 200, body = WebhookDelivery),
+        (status = 404, body = Problem),
+        (status = 500, body = Problem),
+    ))]
+pub async fn retry_delivery(Path((id, delivery_id)): Path<(String, String)>, Extension(webhooks): Extension<Arc<dyn WebhookRepository>>) -> Result<Json<WebhookDelivery>, AppError> {
+    let delivery = match (id.parse::<i64>(), delivery_id.parse::<i64>()) {
+        (Ok(webhook_id), Ok(delivery_id)) => webhooks.retry(webhook_id, delivery_id).await?,
+        _ => None,
+    };
+    let delivery = delivery.ok_or(AppError::DeliveryNotFound(delivery_id))?;
+    tracing::info!(webhook = delivery.webhook_id, delivery = delivery.id, "Webhook delivery retried");
+    Ok(Json(delivery))
+}